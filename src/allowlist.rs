@@ -0,0 +1,316 @@
+//! Allowlist validation of candidate IPs
+//!
+//! Candidates can come from imported lists and other external sources, so a
+//! poisoned source could otherwise get an attacker IP written for
+//! `github.com`. Every IP is checked against the published GitHub and Fastly
+//! address ranges and, optionally, an offline IP-to-ASN table before it is
+//! written to the hosts file.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// GitHub and Fastly address ranges (from GitHub's `/meta` API and Fastly's
+/// public IP list), plus the Azure front-ends GitHub serves `github.com` from
+const ALLOWED_RANGES: &[&str] = &[
+    // GitHub
+    "140.82.112.0/20",
+    "143.55.64.0/20",
+    "185.199.108.0/22",
+    "192.30.252.0/22",
+    "2606:50c0::/32",
+    "2a0a:a440::/29",
+    // GitHub on Azure
+    "4.208.26.197/32",
+    "4.225.11.194/32",
+    "4.237.22.38/32",
+    "20.26.156.215/32",
+    "20.27.177.113/32",
+    "20.29.134.23/32",
+    "20.87.245.0/32",
+    "20.199.39.232/32",
+    "20.200.245.247/32",
+    "20.201.28.151/32",
+    "20.205.243.160/28",
+    "20.207.73.82/32",
+    "20.217.135.5/32",
+    "20.233.83.145/32",
+    "20.248.137.48/32",
+    // Fastly
+    "146.75.0.0/17",
+    "151.101.0.0/16",
+    "199.232.0.0/16",
+    "2a04:4e42::/32",
+];
+
+/// Autonomous systems allowed when an ASN table is configured (GitHub,
+/// Fastly). Microsoft's AS8075 is deliberately absent: it covers every Azure
+/// customer, so GitHub's Azure front-ends are listed as single addresses above.
+const ALLOWED_ASNS: &[u32] = &[36459, 54113];
+
+/// An IPv4 or IPv6 network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parse `addr/prefix`; a bare address is a single-host network
+    pub fn parse(text: &str) -> Option<Cidr> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, prefix.parse::<u8>().ok()?),
+            None => {
+                let addr = text.parse::<IpAddr>().ok()?;
+                (addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix <= max).then_some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Offline IP-to-ASN table
+///
+/// One `cidr asn` pair per line, `#` comments allowed. The most specific
+/// matching network wins.
+#[derive(Debug, Clone, Default)]
+pub struct AsnTable {
+    entries: Vec<(Cidr, u32)>,
+}
+
+impl AsnTable {
+    pub fn parse(text: &str) -> AsnTable {
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.split('#').next().unwrap_or("");
+                let mut fields = line.split_whitespace();
+                let cidr = Cidr::parse(fields.next()?)?;
+                let asn = fields.next()?.trim_start_matches("AS").parse().ok()?;
+                Some((cidr, asn))
+            })
+            .collect();
+        AsnTable { entries }
+    }
+
+    pub fn load(path: &Path) -> io::Result<AsnTable> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        self.entries
+            .iter()
+            .filter(|(cidr, _)| cidr.contains(ip))
+            .max_by_key(|(cidr, _)| cidr.prefix)
+            .map(|(_, asn)| *asn)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Validation settings
+#[derive(Debug, Clone, Default)]
+pub struct AllowlistConfig {
+    /// Refuse to enable anything when a selected IP fails validation,
+    /// instead of dropping the offending IPs
+    pub strict: bool,
+    /// Optional ASN table; IPs outside the ranges pass when they map to an
+    /// allowed ASN
+    pub asn_table: Option<AsnTable>,
+}
+
+// Global allowlist settings
+static ALLOWLIST_CONFIG: OnceLock<Mutex<AllowlistConfig>> = OnceLock::new();
+
+fn get_allowlist_config() -> &'static Mutex<AllowlistConfig> {
+    ALLOWLIST_CONFIG.get_or_init(|| Mutex::new(AllowlistConfig::default()))
+}
+
+/// Current allowlist settings
+pub fn allowlist_config() -> AllowlistConfig {
+    get_allowlist_config().lock().unwrap().clone()
+}
+
+/// Replace the allowlist settings
+pub fn set_allowlist_config(config: AllowlistConfig) {
+    *get_allowlist_config().lock().unwrap() = config;
+}
+
+/// Whether strict mode is on
+pub fn is_strict() -> bool {
+    get_allowlist_config().lock().unwrap().strict
+}
+
+/// Why an IP was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationReason {
+    /// Not an IP address at all
+    InvalidIp,
+    /// Outside the allowed ranges and no ASN table to say otherwise
+    OutsideRanges,
+    /// Outside the allowed ranges and announced by another AS
+    ForeignAsn(u32),
+    /// Outside the allowed ranges and unknown to the ASN table
+    UnknownAsn,
+}
+
+/// An IP rejected for a domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub domain: String,
+    pub ip: String,
+    pub reason: ViolationReason,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            ViolationReason::InvalidIp => write!(f, "{} for {} is not a valid IP address", self.ip, self.domain),
+            ViolationReason::OutsideRanges => {
+                write!(f, "{} for {} is outside GitHub/Fastly address space", self.ip, self.domain)
+            }
+            ViolationReason::ForeignAsn(asn) => write!(
+                f,
+                "{} for {} is outside GitHub/Fastly address space (announced by AS{})",
+                self.ip, self.domain, asn
+            ),
+            ViolationReason::UnknownAsn => write!(
+                f,
+                "{} for {} is outside GitHub/Fastly address space (not in the ASN table)",
+                self.ip, self.domain
+            ),
+        }
+    }
+}
+
+/// Check whether an IP is inside the built-in GitHub/Fastly ranges
+pub fn in_allowed_ranges(ip: IpAddr) -> bool {
+    ALLOWED_RANGES
+        .iter()
+        .filter_map(|range| Cidr::parse(range))
+        .any(|cidr| cidr.contains(ip))
+}
+
+/// Validate an IP for a domain with explicit settings
+pub fn validate_with(domain: &str, ip: &str, config: &AllowlistConfig) -> Result<(), Violation> {
+    let violation = |reason| Violation {
+        domain: domain.to_string(),
+        ip: ip.to_string(),
+        reason,
+    };
+
+    let addr: IpAddr = ip.parse().map_err(|_| violation(ViolationReason::InvalidIp))?;
+    if in_allowed_ranges(addr) {
+        return Ok(());
+    }
+
+    match &config.asn_table {
+        None => Err(violation(ViolationReason::OutsideRanges)),
+        Some(table) => match table.lookup(addr) {
+            Some(asn) if ALLOWED_ASNS.contains(&asn) => Ok(()),
+            Some(asn) => Err(violation(ViolationReason::ForeignAsn(asn))),
+            None => Err(violation(ViolationReason::UnknownAsn)),
+        },
+    }
+}
+
+/// Validate an IP for a domain with the global settings
+pub fn validate(domain: &str, ip: &str) -> Result<(), Violation> {
+    validate_with(domain, ip, &get_allowlist_config().lock().unwrap())
+}
+
+/// Turn violations into one error listing every rejected IP
+pub fn violations_error(violations: &[Violation]) -> io::Error {
+    let list: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("refusing to write IPs that failed allowlist validation: {}", list.join("; ")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let v4 = Cidr::parse("140.82.112.0/20").unwrap();
+        assert!(v4.contains("140.82.127.255".parse().unwrap()));
+        assert!(!v4.contains("140.82.128.0".parse().unwrap()));
+        assert!(!v4.contains("2606:50c0::1".parse().unwrap()));
+
+        let v6 = Cidr::parse("2606:50c0::/32").unwrap();
+        assert!(v6.contains("2606:50c0:8003::154".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+    }
+
+    #[test]
+    fn test_builtin_candidates_pass() {
+        let config = AllowlistConfig::default();
+        for entry in crate::network::get_domain_candidates() {
+            for ip in &entry.candidate_ips {
+                assert!(validate_with(&entry.domain, ip, &config).is_ok(), "{} rejected", ip);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_foreign_ips() {
+        let config = AllowlistConfig::default();
+        let err = validate_with("github.com", "203.0.113.7", &config).unwrap_err();
+        assert_eq!(err.reason, ViolationReason::OutsideRanges);
+        assert!(err.to_string().contains("203.0.113.7 for github.com"));
+        assert_eq!(
+            validate_with("github.com", "github.com", &config).unwrap_err().reason,
+            ViolationReason::InvalidIp
+        );
+
+        // An ASN table can admit new GitHub addresses and names the AS otherwise
+        let table = AsnTable::parse("# cidr asn\n140.82.128.0/17 36459\n20.0.0.0/8 8075\n203.0.113.0/24 AS64500\n");
+        assert_eq!(table.len(), 3);
+        let config = AllowlistConfig {
+            strict: true,
+            asn_table: Some(table),
+        };
+        assert!(validate_with("github.com", "140.82.200.1", &config).is_ok());
+        // Any Azure VM is in Microsoft's AS; only GitHub's own Azure addresses pass
+        assert_eq!(
+            validate_with("github.com", "20.1.2.3", &config).unwrap_err().reason,
+            ViolationReason::ForeignAsn(8075)
+        );
+        assert!(validate_with("github.com", "20.205.243.166", &config).is_ok());
+        assert_eq!(
+            validate_with("github.com", "203.0.113.7", &config).unwrap_err().reason,
+            ViolationReason::ForeignAsn(64500)
+        );
+        assert_eq!(
+            validate_with("github.com", "198.51.100.1", &config).unwrap_err().reason,
+            ViolationReason::UnknownAsn
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Before/after comparison of system DNS against optimized IPs
+//!
+//! For each managed domain, the IP the upstream DNS returns (queried
+//! directly, so an enabled hosts block does not mask it) and the IP this tool
+//! selected (optimized, or the built-in fallback that would be
+//! applied) are probed with the same method: repeated TCP connects plus a
+//! verified TLS handshake. The report shows latency, TLS validity and loss
+//! side by side with a per-domain verdict.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::network::{self, ProbeConfig, ProbeOutcome};
+use crate::{dns, hosts, http};
+
+/// Probes per path; loss is the share of failed attempts
+const DEFAULT_ATTEMPTS: usize = 3;
+
+/// Latency differences below this share of the system latency count as unchanged
+const LATENCY_MARGIN: f64 = 0.1;
+
+/// Settings for a comparison run
+#[derive(Debug, Clone)]
+pub struct CompareConfig {
+    pub attempts: usize,
+    /// Complete a verified TLS handshake for the domain after connecting
+    pub tls: bool,
+    /// Port, timeout and proxy used for both paths
+    pub probe: ProbeConfig,
+    /// Resolvers asked for the system path (the system's own by default)
+    pub upstreams: Vec<SocketAddr>,
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_ATTEMPTS,
+            tls: true,
+            probe: network::probe_config(),
+            upstreams: dns::system_upstreams(),
+        }
+    }
+}
+
+/// Measurements of one path (system or optimized) to a domain
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathReport {
+    /// IP probed, `None` when this path produced no address
+    pub ip: Option<String>,
+    /// Median latency of the successful attempts (connect + handshake)
+    pub latency_ms: Option<u64>,
+    /// Whether the certificate validated for the domain (`None` when TLS was
+    /// not checked or no handshake was attempted)
+    pub tls_valid: Option<bool>,
+    /// Share of failed attempts, 1.0 when nothing could be probed
+    pub loss: f64,
+    /// Most frequent failure, if any attempt failed
+    pub failure: Option<ProbeOutcome>,
+}
+
+impl PathReport {
+    fn unavailable() -> Self {
+        Self {
+            ip: None,
+            latency_ms: None,
+            tls_valid: None,
+            loss: 1.0,
+            failure: None,
+        }
+    }
+
+    /// At least one attempt succeeded with a valid certificate
+    pub fn works(&self) -> bool {
+        self.latency_ms.is_some() && self.tls_valid != Some(false)
+    }
+}
+
+/// Whether the optimized IP helps for a domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Optimized path is faster, loses fewer probes, or is the only one working
+    Improved,
+    /// No meaningful difference (or both paths use the same IP)
+    Unchanged,
+    /// System resolution performs better
+    Worse,
+    /// Neither path works
+    BothFailed,
+    /// No optimized or applied IP exists for the domain
+    NoData,
+}
+
+impl Verdict {
+    /// Display label for the user interfaces
+    pub fn label(&self) -> &'static str {
+        match self {
+            Verdict::Improved => "有提升",
+            Verdict::Unchanged => "无明显差别",
+            Verdict::Worse => "变差",
+            Verdict::BothFailed => "均不可用",
+            Verdict::NoData => "无数据",
+        }
+    }
+}
+
+/// Side-by-side result for one domain
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainComparison {
+    pub domain: String,
+    pub system: PathReport,
+    pub optimized: PathReport,
+    pub verdict: Verdict,
+}
+
+/// Resolve a domain at the upstream DNS, bypassing the hosts file
+///
+/// The system resolver would return the applied IPs while the hosts block
+/// is enabled, making both paths identical.
+pub fn resolve_system(domain: &str, config: &CompareConfig) -> Vec<IpAddr> {
+    let timeout = Duration::from_millis(config.probe.timeout_ms);
+    dns::resolve_upstream(domain, &config.upstreams, timeout).unwrap_or_default()
+}
+
+/// Probe one IP for a domain `attempts` times
+pub fn probe_path(domain: &str, ip: &str, config: &CompareConfig) -> PathReport {
+    let timeout = Duration::from_millis(config.probe.timeout_ms);
+    let mut latencies = Vec::new();
+    let mut failures = Vec::new();
+    let mut tls_valid = None;
+
+    for _ in 0..config.attempts.max(1) {
+        let start = Instant::now();
+        let result = http::connect(
+            ip,
+            config.probe.port,
+            domain,
+            config.tls,
+            timeout,
+            config.probe.proxy.as_ref(),
+        );
+        match result {
+            Ok(_stream) => {
+                latencies.push(start.elapsed().as_millis() as u64);
+                if config.tls {
+                    tls_valid = Some(true);
+                }
+            }
+            Err(e) => {
+                let outcome = ProbeOutcome::classify(&e);
+                if outcome == ProbeOutcome::TlsFailure && tls_valid.is_none() {
+                    tls_valid = Some(false);
+                }
+                failures.push(outcome);
+            }
+        }
+    }
+
+    let attempts = latencies.len() + failures.len();
+    latencies.sort_unstable();
+    PathReport {
+        ip: Some(ip.to_string()),
+        latency_ms: latencies.get(latencies.len() / 2).copied(),
+        tls_valid,
+        loss: failures.len() as f64 / attempts as f64,
+        failure: network::FailureSummary::from_outcomes(failures)
+            .failures
+            .first()
+            .map(|(outcome, _)| *outcome),
+    }
+}
+
+/// Decide whether the optimized path is an improvement over the system path
+pub fn verdict(system: &PathReport, optimized: &PathReport) -> Verdict {
+    if optimized.ip.is_none() {
+        return Verdict::NoData;
+    }
+    if system.ip.is_some() && system.ip == optimized.ip {
+        return Verdict::Unchanged;
+    }
+
+    match (system.works(), optimized.works()) {
+        (false, false) => Verdict::BothFailed,
+        (false, true) => Verdict::Improved,
+        (true, false) => Verdict::Worse,
+        (true, true) => {
+            // A clearly lower loss rate outweighs latency
+            if (system.loss - optimized.loss).abs() > 0.3 {
+                return if optimized.loss < system.loss { Verdict::Improved } else { Verdict::Worse };
+            }
+            let (sys, opt) = (system.latency_ms.unwrap_or(0) as f64, optimized.latency_ms.unwrap_or(0) as f64);
+            if opt < sys * (1.0 - LATENCY_MARGIN) {
+                Verdict::Improved
+            } else if opt > sys * (1.0 + LATENCY_MARGIN) {
+                Verdict::Worse
+            } else {
+                Verdict::Unchanged
+            }
+        }
+    }
+}
+
+/// Compare one domain using the given resolver and optimized IP
+pub fn compare_domain<R>(domain: &str, resolve: R, optimized_ip: Option<String>, config: &CompareConfig) -> DomainComparison
+where
+    R: Fn(&str) -> Vec<IpAddr>,
+{
+    // Probe the first system address of the family the optimized path would use
+    let system_ip = resolve(domain)
+        .into_iter()
+        .find(|ip| config.probe.family.allows(&ip.to_string()));
+
+    let system = match system_ip {
+        Some(ip) => probe_path(domain, &ip.to_string(), config),
+        None => PathReport::unavailable(),
+    };
+    let optimized = match &optimized_ip {
+        Some(ip) => probe_path(domain, ip, config),
+        None => PathReport::unavailable(),
+    };
+
+    DomainComparison {
+        domain: domain.to_string(),
+        verdict: verdict(&system, &optimized),
+        system,
+        optimized,
+    }
+}
+
+/// Compare every managed domain: system resolver vs. the selected IP
+///
+/// Domains are compared in parallel; the report keeps the catalog order.
+pub fn compare_all(config: &CompareConfig) -> Vec<DomainComparison> {
+    let domains: Vec<String> = network::get_domain_candidates().into_iter().map(|e| e.domain).collect();
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = domains
+            .iter()
+            .map(|domain| {
+                scope.spawn(move || {
+                    let optimized_ip = hosts::selected_ips(domain).into_iter().next();
+                    compare_domain(domain, |d: &str| resolve_system(d, config), optimized_ip, config)
+                })
+            })
+            .collect();
+        handles.into_iter().filter_map(|h| h.join().ok()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn local_config(port: u16) -> CompareConfig {
+        CompareConfig {
+            attempts: 2,
+            tls: false,
+            probe: ProbeConfig {
+                port,
+                timeout_ms: 1000,
+                ..ProbeConfig::default()
+            },
+            upstreams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compare_domain_side_by_side() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || for _stream in listener.incoming() {});
+
+        // System DNS points at a dead address, the optimized IP answers
+        let dead = |_: &str| vec!["127.0.0.2".parse().unwrap()];
+        let report = compare_domain("github.com", dead, Some("127.0.0.1".to_string()), &local_config(port));
+
+        assert_eq!(report.system.ip.as_deref(), Some("127.0.0.2"));
+        assert!(report.optimized.works());
+        assert_eq!(report.optimized.loss, 0.0);
+        assert_eq!(report.optimized.tls_valid, None);
+
+        // Nothing listens on 127.0.0.2 (or it is not configured at all)
+        assert!(!report.system.works());
+        assert_eq!(report.system.loss, 1.0);
+        assert_eq!(report.verdict, Verdict::Improved);
+
+        let none = compare_domain("github.com", |_: &str| Vec::new(), None, &local_config(port));
+        assert_eq!(none.verdict, Verdict::NoData);
+    }
+
+    #[test]
+    fn test_verdict_rules() {
+        let path = |ip: &str, latency: Option<u64>, loss: f64| PathReport {
+            ip: Some(ip.to_string()),
+            latency_ms: latency,
+            tls_valid: Some(latency.is_some()),
+            loss,
+            failure: None,
+        };
+
+        assert_eq!(verdict(&path("a", Some(200), 0.0), &path("b", Some(100), 0.0)), Verdict::Improved);
+        assert_eq!(verdict(&path("a", Some(100), 0.0), &path("b", Some(105), 0.0)), Verdict::Unchanged);
+        assert_eq!(verdict(&path("a", Some(100), 0.0), &path("b", Some(150), 0.0)), Verdict::Worse);
+        assert_eq!(verdict(&path("a", Some(50), 0.67), &path("b", Some(80), 0.0)), Verdict::Improved);
+        assert_eq!(verdict(&path("a", None, 1.0), &path("b", None, 1.0)), Verdict::BothFailed);
+        assert_eq!(verdict(&path("a", Some(100), 0.0), &path("a", Some(90), 0.0)), Verdict::Unchanged);
+        assert_eq!(verdict(&path("a", Some(100), 0.0), &PathReport::unavailable()), Verdict::NoData);
+
+        // A handshake with an invalid certificate does not count as working
+        let mut invalid = path("b", Some(20), 0.0);
+        invalid.tls_valid = Some(false);
+        assert_eq!(verdict(&path("a", Some(100), 0.0), &invalid), Verdict::Worse);
+    }
+}
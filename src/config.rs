@@ -0,0 +1,391 @@
+//! Settings file read by the CLI
+//!
+//! A plain `key = value` file, one setting per line, `#` starts a comment.
+//! Every key is optional; anything left out keeps its built-in default.
+//! `import`, `domain_top_n` and `throughput_object` may be given several times.
+//!
+//! ```text
+//! hosts_path = /etc/hosts
+//! family = v4            # v4, v6 or fastest
+//! top_n = 2              # IPs written per domain and family
+//! domain_top_n = github.com 3   # top_n for one domain
+//! interleave = alternate # rank, alternate, v4-first or v6-first
+//! timeout_ms = 3000      # probe connect timeout
+//! proxy = socks5://127.0.0.1:1080
+//! ranking = git          # rank github.com and codeload by git negotiation
+//! throughput = on        # download from bulk-transfer domains to rank them
+//! throughput_max_bytes = 1048576
+//! throughput_object = objects.githubusercontent.com https://objects.githubusercontent.com/...
+//! strict_allowlist = true
+//! asn_table = /usr/share/free_to_github/ip2asn.txt   # `cidr asn` lines
+//! import = https://example.com/github-hosts.txt
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::allowlist::AsnTable;
+use crate::hosts::Interleave;
+use crate::network::{self, FamilyPolicy};
+use crate::proxy::ProxyConfig;
+use crate::{allowlist, gitprobe, hosts, http, import, paths, throughput};
+
+/// File name of the settings file inside the data directory
+pub const CONFIG_FILE: &str = "config";
+
+/// Keys the settings file understands
+pub const KEYS: &[&str] = &[
+    "hosts_path",
+    "family",
+    "top_n",
+    "domain_top_n",
+    "interleave",
+    "timeout_ms",
+    "proxy",
+    "ranking",
+    "throughput",
+    "throughput_max_bytes",
+    "throughput_object",
+    "strict_allowlist",
+    "asn_table",
+    "import",
+];
+
+/// Settings file used when none is given explicitly
+pub fn default_config_path() -> PathBuf {
+    paths::data_dir().join(CONFIG_FILE)
+}
+
+/// Parsed settings; `None` keeps the built-in default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub hosts_path: Option<PathBuf>,
+    pub family: Option<FamilyPolicy>,
+    pub top_n: Option<usize>,
+    /// Per-domain overrides of `top_n`
+    pub domain_top_n: Vec<(String, usize)>,
+    pub interleave: Option<Interleave>,
+    pub timeout_ms: Option<u64>,
+    pub proxy: Option<String>,
+    /// Rank the git domains by smart-HTTP negotiation instead of latency
+    pub git_ranking: Option<bool>,
+    pub throughput: Option<bool>,
+    pub throughput_max_bytes: Option<u64>,
+    /// (domain, URL) objects downloaded to rank bulk-transfer domains
+    pub throughput_objects: Vec<(String, String)>,
+    pub strict_allowlist: Option<bool>,
+    /// Offline IP-to-ASN table admitting addresses outside the built-in ranges
+    pub asn_table: Option<PathBuf>,
+    /// Community hosts lists (files or URLs) imported before each speed test
+    pub imports: Vec<String>,
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn positive<T: std::str::FromStr + PartialOrd + Default>(value: &str) -> Option<T> {
+    value.parse().ok().filter(|v| *v > T::default())
+}
+
+impl Settings {
+    /// Parse settings text; errors name the line and what was expected
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut settings = Settings::default();
+        for (index, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, msg));
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("expected `key = value`, got `{}`", line)));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if value.is_empty() {
+                return Err(error(format!("`{}` has no value", key)));
+            }
+
+            match key {
+                "hosts_path" => settings.hosts_path = Some(PathBuf::from(value)),
+                "family" => {
+                    settings.family = Some(
+                        FamilyPolicy::parse(value)
+                            .ok_or_else(|| error(format!("family must be v4, v6 or fastest, got `{}`", value)))?,
+                    )
+                }
+                "top_n" => {
+                    settings.top_n = Some(
+                        positive(value).ok_or_else(|| error(format!("top_n must be a positive number, got `{}`", value)))?,
+                    )
+                }
+                "domain_top_n" => {
+                    let (domain, count) = value
+                        .split_once(char::is_whitespace)
+                        .map(|(domain, count)| (domain, count.trim()))
+                        .ok_or_else(|| error(format!("domain_top_n must be `<domain> <count>`, got `{}`", value)))?;
+                    if !network::get_domain_candidates().iter().any(|entry| entry.domain == domain) {
+                        return Err(error(format!("domain_top_n: `{}` is not an accelerated domain", domain)));
+                    }
+                    let count = positive(count)
+                        .ok_or_else(|| error(format!("domain_top_n count must be a positive number, got `{}`", count)))?;
+                    settings.domain_top_n.push((domain.to_string(), count));
+                }
+                "interleave" => {
+                    settings.interleave = Some(Interleave::parse(value).ok_or_else(|| {
+                        error(format!("interleave must be rank, alternate, v4-first or v6-first, got `{}`", value))
+                    })?)
+                }
+                "timeout_ms" => {
+                    settings.timeout_ms = Some(
+                        positive(value)
+                            .ok_or_else(|| error(format!("timeout_ms must be a positive number, got `{}`", value)))?,
+                    )
+                }
+                "proxy" => {
+                    ProxyConfig::parse(value).map_err(|e| error(format!("invalid proxy `{}`: {}", value, e)))?;
+                    settings.proxy = Some(value.to_string());
+                }
+                "ranking" => {
+                    settings.git_ranking = Some(match value {
+                        "git" => true,
+                        "latency" => false,
+                        _ => return Err(error(format!("ranking must be git or latency, got `{}`", value))),
+                    })
+                }
+                "throughput" => {
+                    settings.throughput = Some(
+                        parse_bool(value).ok_or_else(|| error(format!("throughput must be on or off, got `{}`", value)))?,
+                    )
+                }
+                "throughput_max_bytes" => {
+                    settings.throughput_max_bytes = Some(
+                        positive(value)
+                            .filter(|bytes| *bytes <= throughput::MAX_BYTES_LIMIT)
+                            .ok_or_else(|| {
+                                error(format!(
+                                    "throughput_max_bytes must be between 1 and {}, got `{}`",
+                                    throughput::MAX_BYTES_LIMIT,
+                                    value
+                                ))
+                            })?,
+                    )
+                }
+                "throughput_object" => {
+                    let (domain, url) = value
+                        .split_once(char::is_whitespace)
+                        .map(|(domain, url)| (domain, url.trim()))
+                        .ok_or_else(|| error(format!("throughput_object must be `<domain> <url>`, got `{}`", value)))?;
+                    http::Url::parse(url).map_err(|e| error(format!("invalid throughput_object URL `{}`: {}", url, e)))?;
+                    settings.throughput_objects.push((domain.to_string(), url.to_string()));
+                }
+                "strict_allowlist" => {
+                    settings.strict_allowlist = Some(
+                        parse_bool(value)
+                            .ok_or_else(|| error(format!("strict_allowlist must be true or false, got `{}`", value)))?,
+                    )
+                }
+                "asn_table" => settings.asn_table = Some(PathBuf::from(value)),
+                "import" => settings.imports.push(value.to_string()),
+                other => {
+                    return Err(error(format!("unknown key `{}` (known keys: {})", other, KEYS.join(", "))))
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Read a settings file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Read the default settings file, if there is one
+    pub fn load_default() -> io::Result<Self> {
+        let path = default_config_path();
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Install the settings into the global configuration
+    pub fn apply(&self) -> io::Result<()> {
+        // Strict mode goes first so a bad setting below cannot leave it off
+        if let Some(strict) = self.strict_allowlist {
+            let mut allowlist = allowlist::allowlist_config();
+            allowlist.strict = strict;
+            allowlist::set_allowlist_config(allowlist);
+        }
+
+        // Everything that can fail, before any other global changes
+        let proxy = self.proxy.as_deref().map(ProxyConfig::parse).transpose()?;
+        let asn_table = match &self.asn_table {
+            Some(path) => {
+                let table = AsnTable::load(path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("asn_table {}: {}", path.display(), e)))?;
+                if table.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("asn_table {} has no `cidr asn` entries", path.display()),
+                    ));
+                }
+                Some(table)
+            }
+            None => None,
+        };
+
+        if let Some(path) = &self.hosts_path {
+            hosts::set_hosts_path(Some(path.clone()));
+        }
+
+        let mut probe = network::probe_config();
+        if let Some(family) = self.family {
+            probe.family = family;
+        }
+        if let Some(timeout_ms) = self.timeout_ms {
+            probe.timeout_ms = timeout_ms;
+        }
+        if proxy.is_some() {
+            probe.proxy = proxy;
+        }
+        match self.git_ranking {
+            Some(true) => gitprobe::use_git_ranking(&mut probe),
+            Some(false) => {
+                for domain in gitprobe::GIT_RANKED_DOMAINS {
+                    probe.ranking_overrides.remove(*domain);
+                }
+            }
+            None => {}
+        }
+        if let Some(enabled) = self.throughput {
+            probe.throughput.enabled = enabled;
+        }
+        if let Some(max_bytes) = self.throughput_max_bytes {
+            probe.throughput.max_bytes = max_bytes;
+        }
+        for (domain, url) in &self.throughput_objects {
+            probe.throughput.objects.insert(domain.clone(), url.clone());
+        }
+        network::set_probe_config(probe);
+
+        if self.top_n.is_some() || self.interleave.is_some() || !self.domain_top_n.is_empty() {
+            let mut layout = hosts::hosts_layout();
+            if let Some(top_n) = self.top_n {
+                layout.default_top_n = top_n;
+            }
+            if let Some(interleave) = self.interleave {
+                layout.interleave = interleave;
+            }
+            layout.per_domain.extend(self.domain_top_n.iter().cloned());
+            hosts::set_hosts_layout(layout);
+        }
+
+        if asn_table.is_some() {
+            let mut allowlist = allowlist::allowlist_config();
+            allowlist.asn_table = asn_table;
+            allowlist::set_allowlist_config(allowlist);
+        }
+
+        if !self.imports.is_empty() {
+            import::set_import_sources(self.imports.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let settings = Settings::parse(
+            "# comment\nhosts_path = /tmp/hosts\nfamily = v4  # trailing\ntop_n=2\n\nstrict_allowlist = yes\n",
+        )
+        .unwrap();
+        assert_eq!(settings.hosts_path, Some(PathBuf::from("/tmp/hosts")));
+        assert_eq!(settings.family, Some(FamilyPolicy::V4Only));
+        assert_eq!(settings.top_n, Some(2));
+        assert_eq!(settings.strict_allowlist, Some(true));
+        assert_eq!(settings.timeout_ms, None);
+        assert_eq!(settings.asn_table, None);
+        assert_eq!(settings.throughput, None);
+        assert_eq!(settings.git_ranking, None);
+        assert!(settings.domain_top_n.is_empty());
+
+        let settings =
+            Settings::parse("domain_top_n = github.com 3\ndomain_top_n = api.github.com\t2\ninterleave = v6-first\n")
+                .unwrap();
+        assert_eq!(
+            settings.domain_top_n,
+            vec![("github.com".to_string(), 3), ("api.github.com".to_string(), 2)]
+        );
+        assert_eq!(settings.interleave, Some(Interleave::V6First));
+        assert_eq!(Settings::parse("ranking = git").unwrap().git_ranking, Some(true));
+
+        let settings = Settings::parse(
+            "throughput = on\nthroughput_max_bytes = 65536\nthroughput_object = objects.githubusercontent.com  https://objects.githubusercontent.com/x\n",
+        )
+        .unwrap();
+        assert_eq!(settings.throughput, Some(true));
+        assert_eq!(settings.throughput_max_bytes, Some(65536));
+        assert_eq!(
+            settings.throughput_objects,
+            vec![("objects.githubusercontent.com".to_string(), "https://objects.githubusercontent.com/x".to_string())]
+        );
+
+        let settings = Settings::parse("asn_table = /nonexistent/ip2asn.txt\n").unwrap();
+        assert_eq!(settings.asn_table, Some(PathBuf::from("/nonexistent/ip2asn.txt")));
+        let err = settings.apply().unwrap_err().to_string();
+        assert!(err.contains("asn_table /nonexistent/ip2asn.txt"), "{}", err);
+
+        for (bad, hint) in [
+            ("colour = blue", "unknown key `colour`"),
+            ("family = v5", "line 1: family must be"),
+            ("\ntop_n = 0", "line 2: top_n"),
+            ("proxy = ftp://x", "invalid proxy"),
+            ("ranking = speed", "ranking must be git or latency"),
+            ("domain_top_n = github.com", "`<domain> <count>`"),
+            ("domain_top_n = example.com 2", "not an accelerated domain"),
+            ("domain_top_n = github.com 0", "count must be a positive number"),
+            ("interleave = random", "interleave must be"),
+            ("throughput_max_bytes = 999999999", "between 1 and"),
+            ("throughput_object = https://example.com/x", "`<domain> <url>`"),
+            ("throughput_object = raw.githubusercontent.com ftp://x", "invalid throughput_object URL"),
+            ("just words", "expected `key = value`"),
+        ] {
+            let err = Settings::parse(bad).unwrap_err().to_string();
+            assert!(err.contains(hint), "{} -> {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_apply_fails_closed() {
+        // A table that cannot be loaded must not leave strict mode off, and
+        // must not half-apply the settings that came before it
+        let settings = Settings::parse(
+            "hosts_path = /tmp/never-applied-hosts
+strict_allowlist = yes
+asn_table = /nonexistent/ip2asn.txt
+",
+        )
+        .unwrap();
+        assert!(settings.apply().is_err());
+        assert!(allowlist::is_strict());
+        assert_ne!(hosts::get_hosts_path(), PathBuf::from("/tmp/never-applied-hosts"));
+
+        let mut allowlist = allowlist::allowlist_config();
+        allowlist.strict = false;
+        allowlist::set_allowlist_config(allowlist);
+    }
+}
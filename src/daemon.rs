@@ -0,0 +1,693 @@
+//! Background daemon: periodic re-test and re-apply with hysteresis
+//!
+//! Network conditions drift, so IPs that were fastest an hour ago may not be
+//! now. The daemon re-runs the speed test on a schedule, but only replaces a
+//! domain's IPs when a challenger beats the applied IP by `margin` for
+//! `rounds` consecutive rounds, so the hosts block is not rewritten on every
+//! bit of jitter. Groups of related domains switch together. When the local
+//! network is down the schedule backs off exponentially instead of burning
+//! probes. Status (state, last and next run) is kept in-process and written to
+//! a small file so the CLI and GUIs can show it.
+//!
+//! The speed test table always holds the latest ranking, while the IPs in use
+//! are read back from the hosts block. Rounds requested over RPC run through
+//! the same schedule, so only one test runs at a time and every round feeds
+//! the same hysteresis.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::network::{self, RankedResults, SharedProgressCallback};
+use crate::{helper, hosts, import, netprofile, paths};
+
+/// Default time between speed tests (seconds)
+const DEFAULT_INTERVAL_SECS: u64 = 30 * 60;
+
+/// First retry delay after a round with the network down (seconds)
+const BACKOFF_BASE_SECS: u64 = 60;
+
+/// Longest retry delay while the network stays down (seconds)
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 60 * 60;
+
+/// File the status is mirrored to for other processes
+pub const STATUS_FILE: &str = "daemon.status";
+
+/// Settings for the daemon
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    pub interval_secs: u64,
+    /// Share by which a challenger must be faster than the applied IP
+    pub margin: f64,
+    /// Consecutive rounds the applied IP must be beaten before switching
+    pub rounds: u32,
+    pub max_backoff_secs: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            margin: 0.2,
+            rounds: 3,
+            max_backoff_secs: DEFAULT_MAX_BACKOFF_SECS,
+        }
+    }
+}
+
+/// What the daemon is doing right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DaemonState {
+    /// Not running in this process
+    #[default]
+    Stopped,
+    /// Waiting for the next scheduled round
+    Idle,
+    /// Speed test in progress
+    Testing,
+    /// Network down, retrying with growing delays
+    Backoff,
+}
+
+impl DaemonState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DaemonState::Stopped => "stopped",
+            DaemonState::Idle => "idle",
+            DaemonState::Testing => "testing",
+            DaemonState::Backoff => "backoff",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DaemonState> {
+        match s {
+            "stopped" => Some(DaemonState::Stopped),
+            "idle" => Some(DaemonState::Idle),
+            "testing" => Some(DaemonState::Testing),
+            "backoff" => Some(DaemonState::Backoff),
+            _ => None,
+        }
+    }
+
+    /// Display label for the user interfaces
+    pub fn label(&self) -> &'static str {
+        match self {
+            DaemonState::Stopped => "未运行",
+            DaemonState::Idle => "等待下次测速",
+            DaemonState::Testing => "测速中",
+            DaemonState::Backoff => "网络不可用, 稍后重试",
+        }
+    }
+}
+
+/// Result of one round
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundOutcome {
+    /// These domains got new IPs
+    Applied(Vec<String>),
+    /// Applied IPs are still good; `pending` domains are being challenged
+    Unchanged { pending: usize },
+    /// Acceleration is off: the results were saved, nothing was applied
+    Recorded,
+    /// No probe succeeded
+    NetworkDown,
+}
+
+impl RoundOutcome {
+    /// Short description for status output
+    pub fn describe(&self) -> String {
+        match self {
+            RoundOutcome::Applied(domains) => format!("已更新 {} 个域名", domains.len()),
+            RoundOutcome::Unchanged { pending: 0 } => "无需更新".to_string(),
+            RoundOutcome::Unchanged { pending } => format!("无需更新 ({} 个域名待确认)", pending),
+            RoundOutcome::Recorded => "加速未启用, 已保存测速结果".to_string(),
+            RoundOutcome::NetworkDown => "网络不可用".to_string(),
+        }
+    }
+}
+
+/// Daemon status exposed to the user interfaces
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DaemonStatus {
+    pub state: DaemonState,
+    /// Unix time the last round finished
+    pub last_run: Option<u64>,
+    /// Unix time of the next scheduled round
+    pub next_run: Option<u64>,
+    /// Description of the last round's outcome
+    pub last_outcome: Option<String>,
+    /// Consecutive rounds with the network down
+    pub failures: u32,
+}
+
+impl DaemonStatus {
+    fn to_file(&self) -> String {
+        let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        format!(
+            "state={}\nlast_run={}\nnext_run={}\nfailures={}\noutcome={}\n",
+            self.state.as_str(),
+            opt(self.last_run),
+            opt(self.next_run),
+            self.failures,
+            self.last_outcome.as_deref().unwrap_or("")
+        )
+    }
+
+    fn from_file(content: &str) -> DaemonStatus {
+        let mut status = DaemonStatus::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "state" => status.state = DaemonState::parse(value).unwrap_or_default(),
+                "last_run" => status.last_run = value.parse().ok(),
+                "next_run" => status.next_run = value.parse().ok(),
+                "failures" => status.failures = value.parse().unwrap_or(0),
+                "outcome" if !value.is_empty() => status.last_outcome = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        status
+    }
+}
+
+// Status of the daemon running in this process
+static STATUS: OnceLock<Mutex<DaemonStatus>> = OnceLock::new();
+
+fn get_status() -> &'static Mutex<DaemonStatus> {
+    STATUS.get_or_init(|| Mutex::new(DaemonStatus::default()))
+}
+
+/// Status of the daemon running in this process
+pub fn status() -> DaemonStatus {
+    get_status().lock().unwrap().clone()
+}
+
+/// Path of the status file shared with other processes
+pub fn status_path() -> PathBuf {
+    paths::data_dir().join(STATUS_FILE)
+}
+
+/// Status last written by a daemon process, if any
+pub fn load_status() -> Option<DaemonStatus> {
+    load_status_from(&status_path())
+}
+
+/// Status written to a specific file (e.g. by the system service)
+pub fn load_status_from(path: &Path) -> Option<DaemonStatus> {
+    fs::read_to_string(path).ok().map(|c| DaemonStatus::from_file(&c))
+}
+
+fn update_status(update: impl FnOnce(&mut DaemonStatus)) {
+    let mut status = get_status().lock().unwrap();
+    update(&mut status);
+    let path = status_path();
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, status.to_file()));
+    if let Err(_e) = written {
+        #[cfg(debug_assertions)]
+        log::warn!("Failed to write daemon status: {}", _e);
+    }
+}
+
+/// Each domain's ranking signal per IP as a cost, lower is better
+pub type SignalCosts = HashMap<String, HashMap<String, f64>>;
+
+/// Costs by the signal that ranked each domain in the last speed test
+///
+/// Throughput-ranked domains cost the inverse of their throughput score and
+/// git-ranked ones their negotiation time; the rest cost connect latency.
+pub fn signal_costs(fresh: &RankedResults) -> SignalCosts {
+    let throughput = network::last_throughput_results();
+    let git = network::last_git_results();
+    fresh
+        .iter()
+        .map(|(domain, ranked)| {
+            let costs = if let Some(measured) = throughput.get(domain) {
+                measured
+                    .iter()
+                    .map(|(ip, result)| (ip.clone(), 1.0 / result.score().max(f64::MIN_POSITIVE)))
+                    .collect()
+            } else if let Some(measured) = git.get(domain) {
+                measured
+                    .iter()
+                    .map(|(ip, result)| (ip.clone(), result.negotiation_ms as f64))
+                    .collect()
+            } else {
+                latency_costs(ranked)
+            };
+            (domain.clone(), costs)
+        })
+        .collect()
+}
+
+fn latency_costs(ranked: &[(String, u64)]) -> HashMap<String, f64> {
+    ranked.iter().map(|(ip, latency)| (ip.clone(), *latency as f64)).collect()
+}
+
+/// Tracks how many consecutive rounds each domain's applied IP was beaten
+#[derive(Debug, Clone, Default)]
+pub struct Hysteresis {
+    streaks: HashMap<String, u32>,
+}
+
+impl Hysteresis {
+    /// Domains whose applied IP has been beaten in the current streak
+    pub fn pending(&self) -> usize {
+        self.streaks.len()
+    }
+
+    /// Record a round and return the domains that should switch now
+    ///
+    /// `applied` holds the IP currently used per domain. A domain switches
+    /// immediately when it has no applied IP or the applied IP was not
+    /// reachable this round; otherwise the fresh best IP must
+    /// beat the applied IP for `rounds` rounds in a row. Beating means costing
+    /// at least `margin` less by the domain's signal in `costs`; when the
+    /// ranking put the best IP first for another reason (the applied IP was
+    /// not measured, or group coherence outweighed its cost), its rank decides.
+    /// A switching domain takes the rest of its group along.
+    pub fn observe(
+        &mut self,
+        applied: &HashMap<String, String>,
+        fresh: &RankedResults,
+        costs: &SignalCosts,
+        margin: f64,
+        rounds: u32,
+    ) -> Vec<String> {
+        let mut switch = Vec::new();
+
+        for (domain, ranked) in fresh {
+            let Some((best_ip, _)) = ranked.first() else {
+                continue;
+            };
+            // Nothing applied yet, or the applied IP is down: no jitter to damp
+            let Some(current_ip) = applied.get(domain).filter(|ip| ranked.iter().any(|(r, _)| r == *ip)) else {
+                switch.push(domain.clone());
+                continue;
+            };
+            if current_ip == best_ip {
+                self.streaks.remove(domain);
+                continue;
+            }
+
+            let cost = |ip: &String| costs.get(domain).and_then(|c| c.get(ip)).copied();
+            let beaten = match (cost(best_ip), cost(current_ip)) {
+                (Some(best), Some(current)) if best <= current => best <= current * (1.0 - margin),
+                _ => true,
+            };
+            if !beaten {
+                self.streaks.remove(domain);
+                continue;
+            }
+
+            let streak = self.streaks.entry(domain.clone()).or_insert(0);
+            *streak += 1;
+            if *streak >= rounds.max(1) {
+                switch.push(domain.clone());
+            }
+        }
+
+        // Keep groups on one edge: a switching member takes its group along
+        let groups: Vec<String> = switch.iter().filter_map(|d| network::group_of(d)).collect();
+        for domain in fresh.keys() {
+            if !switch.contains(domain) && network::group_of(domain).is_some_and(|g| groups.contains(&g)) {
+                switch.push(domain.clone());
+            }
+        }
+
+        for domain in &switch {
+            self.streaks.remove(domain);
+        }
+        switch.sort();
+        switch
+    }
+}
+
+/// Delay before the next round after `failures` consecutive network-down rounds
+pub fn next_delay_secs(config: &DaemonConfig, failures: u32) -> u64 {
+    if failures == 0 {
+        return config.interval_secs;
+    }
+    let backoff = BACKOFF_BASE_SECS.saturating_mul(1 << (failures - 1).min(16));
+    backoff.min(config.max_backoff_secs)
+}
+
+/// IP in use per domain: the first entry of each domain in the hosts block
+fn applied_ips(written: &[(String, String)]) -> HashMap<String, String> {
+    let mut applied = HashMap::new();
+    for (ip, domain) in written {
+        applied.entry(domain.clone()).or_insert_with(|| ip.clone());
+    }
+    applied
+}
+
+/// Entries to write: `target` for switched domains and domains not written
+/// yet, the written entries for every other domain, in `target`'s order
+fn merge_mapping(
+    written: &[(String, String)],
+    target: &[(String, String)],
+    switch: &[String],
+) -> Vec<(String, String)> {
+    let mut merged = Vec::with_capacity(target.len());
+    let mut seen: Vec<&str> = Vec::new();
+    for (_, domain) in target {
+        if seen.contains(&domain.as_str()) {
+            continue;
+        }
+        seen.push(domain);
+        let keep = !switch.contains(domain) && written.iter().any(|(_, d)| d == domain);
+        let source = if keep { written } else { target };
+        merged.extend(source.iter().filter(|(_, d)| d == domain).cloned());
+    }
+    merged
+}
+
+/// Run one speed test round and apply the domains that switched
+///
+/// The fresh ranking is saved as the speed test results either way; the
+/// hosts block only changes for domains that passed the hysteresis.
+pub fn run_round(
+    config: &DaemonConfig,
+    hysteresis: &mut Hysteresis,
+    progress: Option<SharedProgressCallback>,
+) -> io::Result<RoundOutcome> {
+    netprofile::check_network_change();
+    import::import_configured();
+
+    let fresh = network::test_all_domains_ranked(progress);
+    if fresh.is_empty() || network::last_failure_summary().local_network_down() {
+        return Ok(RoundOutcome::NetworkDown);
+    }
+    let costs = signal_costs(&fresh);
+    hosts::set_ranked_ips(fresh.clone());
+
+    if !hosts::is_enabled()? {
+        *hysteresis = Hysteresis::default();
+        return Ok(RoundOutcome::Recorded);
+    }
+    let written = hosts::managed_entries()?;
+    let switch = hysteresis.observe(&applied_ips(&written), &fresh, &costs, config.margin, config.rounds);
+    if switch.is_empty() {
+        return Ok(RoundOutcome::Unchanged { pending: hysteresis.pending() });
+    }
+
+    let merged = merge_mapping(&written, &hosts::current_mapping(true)?, &switch);
+    // The writer may order a domain's IPs differently from the ranking
+    if merged == written {
+        return Ok(RoundOutcome::Unchanged { pending: hysteresis.pending() });
+    }
+    helper::apply_mapping(merged)?;
+
+    #[cfg(debug_assertions)]
+    log::info!("Daemon: switched IPs for {:?}", switch);
+    Ok(RoundOutcome::Applied(switch))
+}
+
+// Set while a round runs in this process, on schedule or on request
+static ROUND_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Marks a round as running until dropped
+#[derive(Debug)]
+pub struct RoundGuard(());
+
+impl Drop for RoundGuard {
+    fn drop(&mut self) {
+        ROUND_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Claim the right to run a speed test; `None` while another one runs
+pub fn try_begin_round() -> Option<RoundGuard> {
+    (!ROUND_RUNNING.swap(true, Ordering::SeqCst)).then_some(RoundGuard(()))
+}
+
+/// Whether a speed test is running in this process
+pub fn is_round_running() -> bool {
+    ROUND_RUNNING.load(Ordering::SeqCst)
+}
+
+// Settings and hysteresis of the schedule running in this process
+static SCHEDULE: OnceLock<Mutex<Option<(DaemonConfig, Hysteresis)>>> = OnceLock::new();
+
+fn get_schedule() -> &'static Mutex<Option<(DaemonConfig, Hysteresis)>> {
+    SCHEDULE.get_or_init(|| Mutex::new(None))
+}
+
+/// Whether [`run`] is active in this process
+pub fn is_scheduled() -> bool {
+    get_schedule().lock().unwrap().is_some()
+}
+
+fn scheduled_round(progress: Option<SharedProgressCallback>) -> Option<io::Result<RoundOutcome>> {
+    let mut schedule = get_schedule().lock().unwrap();
+    let (config, hysteresis) = schedule.as_mut()?;
+    Some(run_round(config, hysteresis, progress))
+}
+
+fn describe_result(outcome: &io::Result<RoundOutcome>) -> String {
+    match outcome {
+        Ok(outcome) => outcome.describe(),
+        Err(e) => format!("应用失败: {}", e),
+    }
+}
+
+/// Run a round of the schedule running in this process right away
+///
+/// The caller holds the [`RoundGuard`]. Returns `None` when no schedule runs
+/// here, so the caller has nobody to hand the test to.
+pub fn run_round_now(_guard: &RoundGuard, progress: Option<SharedProgressCallback>) -> Option<io::Result<RoundOutcome>> {
+    if !is_scheduled() {
+        return None;
+    }
+    let previous = status().state;
+    update_status(|s| s.state = DaemonState::Testing);
+    let outcome = scheduled_round(progress)?;
+    let description = describe_result(&outcome);
+    update_status(|s| {
+        s.state = previous;
+        s.last_run = Some(paths::unix_now());
+        s.last_outcome = Some(description);
+    });
+    Some(outcome)
+}
+
+/// Run a single round, for periodic one-shot runs (e.g. a systemd timer)
+///
+/// Hysteresis state does not survive between processes, so a one-shot run
+/// only applies the margin: a challenger must be `margin` faster right away.
+pub fn run_once(config: &DaemonConfig) -> io::Result<RoundOutcome> {
+    update_status(|s| s.state = DaemonState::Testing);
+    let once = DaemonConfig { rounds: 1, ..config.clone() };
+    let outcome = run_round(&once, &mut Hysteresis::default(), None);
+    let description = describe_result(&outcome);
+    update_status(|s| {
+        s.state = DaemonState::Stopped;
+        s.last_run = Some(paths::unix_now());
+        s.next_run = None;
+        s.last_outcome = Some(description);
+    });
+    outcome
+}
+
+/// Run rounds on the schedule until `stop` is set
+pub fn run(config: &DaemonConfig, stop: &AtomicBool) {
+    *get_schedule().lock().unwrap() = Some((config.clone(), Hysteresis::default()));
+    let mut failures = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        // Wait out a round requested over RPC instead of overlapping it
+        let Some(guard) = try_begin_round() else {
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        };
+        update_status(|s| s.state = DaemonState::Testing);
+        let outcome = scheduled_round(None).expect("schedule set above");
+        drop(guard);
+
+        failures = match &outcome {
+            Ok(RoundOutcome::NetworkDown) => failures + 1,
+            _ => 0,
+        };
+        let description = describe_result(&outcome);
+        let delay = next_delay_secs(config, failures);
+        let now = paths::unix_now();
+        update_status(|s| {
+            s.state = if failures > 0 { DaemonState::Backoff } else { DaemonState::Idle };
+            s.last_run = Some(now);
+            s.next_run = Some(now + delay);
+            s.last_outcome = Some(description);
+            s.failures = failures;
+        });
+
+        // Sleep in short steps so a stop request is noticed quickly
+        for _ in 0..delay {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    *get_schedule().lock().unwrap() = None;
+    update_status(|s| {
+        s.state = DaemonState::Stopped;
+        s.next_run = None;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(list: &[(&str, u64)]) -> Vec<(String, u64)> {
+        list.iter().map(|(ip, l)| (ip.to_string(), *l)).collect()
+    }
+
+    fn costs(fresh: &RankedResults) -> SignalCosts {
+        fresh.iter().map(|(domain, ranked)| (domain.clone(), latency_costs(ranked))).collect()
+    }
+
+    #[test]
+    fn test_hysteresis_needs_consecutive_rounds() {
+        let mut hysteresis = Hysteresis::default();
+        let applied: HashMap<String, String> = [("api.github.com".to_string(), "a".to_string())].into();
+        let mut fresh: RankedResults = HashMap::new();
+
+        // 70ms vs 100ms beats a 20% margin
+        fresh.insert("api.github.com".into(), ranked(&[("b", 70), ("a", 100)]));
+        assert!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3).is_empty());
+        assert!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3).is_empty());
+
+        // A round within the margin resets the streak
+        fresh.insert("api.github.com".into(), ranked(&[("b", 90), ("a", 100)]));
+        assert!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3).is_empty());
+        assert_eq!(hysteresis.pending(), 0);
+
+        fresh.insert("api.github.com".into(), ranked(&[("b", 70), ("a", 100)]));
+        for _ in 0..2 {
+            assert!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3).is_empty());
+        }
+        assert_eq!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3), vec!["api.github.com".to_string()]);
+    }
+
+    #[test]
+    fn test_hysteresis_switches_unreachable_and_groups() {
+        let mut hysteresis = Hysteresis::default();
+        let applied: HashMap<String, String> = [
+            ("github.com".to_string(), "a".to_string()),
+            ("gist.github.com".to_string(), "a".to_string()),
+        ]
+        .into();
+        let mut fresh: RankedResults = HashMap::new();
+        // The applied IP is no longer reachable for github.com
+        fresh.insert("github.com".into(), ranked(&[("b", 50)]));
+        fresh.insert("gist.github.com".into(), ranked(&[("a", 40), ("b", 45)]));
+        // No applied IP yet: switch right away
+        fresh.insert("collector.github.com".into(), ranked(&[("c", 30)]));
+
+        // Neither waits for the rounds that damp jitter
+        let switched = hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3);
+        assert_eq!(switched, vec!["collector.github.com", "gist.github.com", "github.com"]);
+        assert_eq!(hysteresis.pending(), 0);
+
+        // A merely slower applied IP still needs every round
+        let applied: HashMap<String, String> = [("github.com".to_string(), "a".to_string())].into();
+        fresh.insert("github.com".into(), ranked(&[("b", 50), ("a", 100)]));
+        fresh.remove("gist.github.com");
+        fresh.remove("collector.github.com");
+        for _ in 0..2 {
+            assert!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3).is_empty());
+        }
+        assert_eq!(hysteresis.observe(&applied, &fresh, &costs(&fresh), 0.2, 3), vec!["github.com"]);
+    }
+
+    #[test]
+    fn test_hysteresis_uses_ranking_signal() {
+        let mut hysteresis = Hysteresis::default();
+        let applied: HashMap<String, String> = [("objects.githubusercontent.com".to_string(), "a".to_string())].into();
+        let mut fresh: RankedResults = HashMap::new();
+        // Ranked by throughput: "b" connects slower but downloads twice as fast
+        fresh.insert("objects.githubusercontent.com".into(), ranked(&[("b", 120), ("a", 60)]));
+        let mut costs: SignalCosts = HashMap::new();
+        costs.insert(
+            "objects.githubusercontent.com".into(),
+            [("b".to_string(), 1.0 / 40.0), ("a".to_string(), 1.0 / 20.0)].into(),
+        );
+        assert_eq!(
+            hysteresis.observe(&applied, &fresh, &costs, 0.2, 1),
+            vec!["objects.githubusercontent.com".to_string()]
+        );
+
+        // Only slightly better throughput stays within the margin, despite lower latency
+        fresh.insert("objects.githubusercontent.com".into(), ranked(&[("b", 30), ("a", 60)]));
+        costs.insert(
+            "objects.githubusercontent.com".into(),
+            [("b".to_string(), 1.0 / 21.0), ("a".to_string(), 1.0 / 20.0)].into(),
+        );
+        assert!(hysteresis.observe(&applied, &fresh, &costs, 0.2, 1).is_empty());
+
+        // Applied IP reachable but not measured: it ranked behind, so it is beaten
+        costs.insert("objects.githubusercontent.com".into(), [("b".to_string(), 1.0 / 21.0)].into());
+        assert_eq!(hysteresis.observe(&applied, &fresh, &costs, 0.2, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_keeps_unswitched_domains() {
+        let pairs = |list: &[(&str, &str)]| -> Vec<(String, String)> {
+            list.iter().map(|(ip, d)| (ip.to_string(), d.to_string())).collect()
+        };
+        let written = pairs(&[("a", "github.com"), ("b", "github.com"), ("c", "api.github.com")]);
+        let target = pairs(&[
+            ("x", "github.com"),
+            ("y", "api.github.com"),
+            ("z", "codeload.github.com"),
+        ]);
+        assert_eq!(applied_ips(&written)["github.com"], "a");
+
+        // Only the switched domain and the one not written yet take the new IPs
+        let merged = merge_mapping(&written, &target, &["api.github.com".to_string()]);
+        assert_eq!(
+            merged,
+            pairs(&[("a", "github.com"), ("b", "github.com"), ("y", "api.github.com"), ("z", "codeload.github.com")])
+        );
+    }
+
+    #[test]
+    fn test_round_guard_is_exclusive() {
+        let guard = try_begin_round().unwrap();
+        assert!(is_round_running());
+        assert!(try_begin_round().is_none());
+        // No schedule in this process: the caller runs the test itself
+        assert!(run_round_now(&guard, None).is_none());
+        drop(guard);
+        assert!(!is_round_running());
+        assert!(try_begin_round().is_some());
+    }
+
+    #[test]
+    fn test_backoff_and_status_file() {
+        let config = DaemonConfig::default();
+        assert_eq!(next_delay_secs(&config, 0), config.interval_secs);
+        assert_eq!(next_delay_secs(&config, 1), 60);
+        assert_eq!(next_delay_secs(&config, 3), 240);
+        assert_eq!(next_delay_secs(&config, 40), config.max_backoff_secs);
+
+        let status = DaemonStatus {
+            state: DaemonState::Backoff,
+            last_run: Some(100),
+            next_run: Some(160),
+            last_outcome: Some("网络不可用".to_string()),
+            failures: 1,
+        };
+        assert_eq!(DaemonStatus::from_file(&status.to_file()), status);
+    }
+}
@@ -0,0 +1,607 @@
+//! Local DNS resolver mode
+//!
+//! An alternative to editing the hosts file: a small UDP DNS server answers
+//! managed domains from the optimized IP table (`hosts::managed_ips`, the
+//! same one the hosts writer uses) and forwards every other query to the
+//! configured upstreams. Group wildcards such as `*.githubusercontent.com`
+//! are answered too, which the hosts file cannot express. Answers carry a
+//! short TTL so a new speed test takes effect quickly, and no root is needed
+//! when binding a high port.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::hosts;
+
+/// Default listen address; port 53 needs root on most systems
+pub const DEFAULT_BIND: &str = "127.0.0.1:5353";
+
+/// Default upstream resolvers for unmanaged names
+pub const DEFAULT_UPSTREAMS: &[&str] = &["223.5.5.5:53", "119.29.29.29:53"];
+
+/// TTL of answers for managed domains (seconds)
+const DEFAULT_TTL_SECS: u32 = 30;
+
+/// Timeout for each upstream attempt (milliseconds)
+const UPSTREAM_TIMEOUT_MS: u64 = 2000;
+
+/// Forwarded queries allowed in flight before new ones get SERVFAIL
+const MAX_FORWARDS: usize = 64;
+
+/// How often the serve loop checks for a stop request (milliseconds)
+const POLL_INTERVAL_MS: u64 = 500;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_SERVFAIL: u16 = 2;
+
+/// Settings for the local DNS server
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    pub bind: SocketAddr,
+    /// Resolvers unmanaged queries are forwarded to, tried in order
+    pub upstreams: Vec<SocketAddr>,
+    /// TTL of answers for managed domains (seconds)
+    pub ttl_secs: u32,
+    pub upstream_timeout_ms: u64,
+    /// Forwarded queries allowed in flight; each one holds a thread
+    pub max_forwards: usize,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.parse().expect("valid default bind address"),
+            upstreams: DEFAULT_UPSTREAMS
+                .iter()
+                .map(|addr| addr.parse().expect("valid default upstream"))
+                .collect(),
+            ttl_secs: DEFAULT_TTL_SECS,
+            upstream_timeout_ms: UPSTREAM_TIMEOUT_MS,
+            max_forwards: MAX_FORWARDS,
+        }
+    }
+}
+
+/// Parse a listen address: `127.0.0.1:5353`, or `:53` and a bare port on loopback
+///
+/// Other machines only reach the server through an explicit address such as
+/// `0.0.0.0:53`, since it forwards any name it does not manage.
+pub fn parse_bind_addr(s: &str) -> io::Result<SocketAddr> {
+    let s = s.trim();
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid listen address: {}", s));
+    if let Some(port) = s.strip_prefix(':') {
+        let port = port.parse().map_err(|_| invalid())?;
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+    if let Ok(port) = s.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+    s.parse().map_err(|_| invalid())
+}
+
+/// Parse an upstream resolver: `ip` (port 53) or `ip:port`
+pub fn parse_upstream(s: &str) -> io::Result<SocketAddr> {
+    let s = s.trim();
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    s.parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid upstream resolver: {}", s)))
+}
+
+/// Question of a parsed query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    /// Lowercase name without the trailing dot
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// Offset of the first byte after the question section
+    end: usize,
+}
+
+/// Parse the single question of a standard query
+///
+/// Returns `None` for responses, other opcodes, multi-question packets and
+/// malformed names; such packets are dropped.
+pub fn parse_query(packet: &[u8]) -> Option<Question> {
+    if packet.len() < 12 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([packet[2], packet[3]]);
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0xf;
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    if is_response || opcode != 0 || qdcount != 1 {
+        return None;
+    }
+
+    let mut pos = 12;
+    let mut labels = Vec::new();
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers do not appear in questions of real queries
+        if len > 63 {
+            return None;
+        }
+        let label = packet.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len;
+    }
+
+    let fixed = packet.get(pos..pos + 4)?;
+    Some(Question {
+        name: labels.join("."),
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        end: pos + 4,
+    })
+}
+
+/// Response header and question copied from the query
+fn response_head(query: &[u8], question: &Question, rcode: u16, answers: u16) -> Vec<u8> {
+    let recursion_desired = u16::from_be_bytes([query[2], query[3]]) & 0x0100;
+    // QR, AA and RA set
+    let flags = 0x8000 | 0x0400 | 0x0080 | recursion_desired | rcode;
+
+    let mut response = Vec::with_capacity(question.end + 16 * answers as usize);
+    response.extend_from_slice(&query[0..2]);
+    response.extend_from_slice(&flags.to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&answers.to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&query[12..question.end]);
+    response
+}
+
+/// Build an authoritative answer for a managed name
+///
+/// Only addresses matching the query type are returned; a managed name with
+/// none of them gets an empty NOERROR answer rather than being forwarded, so
+/// clients never mix our IPs with upstream ones.
+pub fn build_answer(query: &[u8], question: &Question, ips: &[IpAddr], ttl_secs: u32) -> Vec<u8> {
+    let matching: Vec<&IpAddr> = ips
+        .iter()
+        .filter(|ip| match question.qtype {
+            TYPE_A => ip.is_ipv4(),
+            TYPE_AAAA => ip.is_ipv6(),
+            _ => false,
+        })
+        .collect();
+
+    let mut response = response_head(query, question, 0, matching.len() as u16);
+    for ip in matching {
+        // Name is a pointer to the question at offset 12
+        response.extend_from_slice(&[0xc0, 0x0c]);
+        response.extend_from_slice(&question.qtype.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ttl_secs.to_be_bytes());
+        match ip {
+            IpAddr::V4(v4) => {
+                response.extend_from_slice(&4u16.to_be_bytes());
+                response.extend_from_slice(&v4.octets());
+            }
+            IpAddr::V6(v6) => {
+                response.extend_from_slice(&16u16.to_be_bytes());
+                response.extend_from_slice(&v6.octets());
+            }
+        }
+    }
+    response
+}
+
+/// Empty response with an error code
+fn build_error(query: &[u8], question: &Question, rcode: u16) -> Vec<u8> {
+    response_head(query, question, rcode, 0)
+}
+
+/// Send a query to the upstreams in order and return the first matching response
+pub fn forward(query: &[u8], upstreams: &[SocketAddr], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no upstream resolvers configured");
+    for upstream in upstreams {
+        let local: SocketAddr = if upstream.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let attempt = (|| {
+            let socket = UdpSocket::bind(local)?;
+            socket.set_read_timeout(Some(timeout))?;
+            socket.connect(upstream)?;
+            socket.send(query)?;
+
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.recv(&mut buf)?;
+                // Ignore stray packets that do not answer our query ID
+                if n >= 12 && buf[0..2] == query[0..2] {
+                    return Ok(buf[..n].to_vec());
+                }
+            }
+        })();
+        match attempt {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Build a standard recursive query for one name
+pub fn build_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + name.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // RD set, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+/// Offset after a possibly compressed name starting at `pos`
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            // A pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l if l > 63 => return None,
+            l => pos += 1 + l,
+        }
+    }
+}
+
+/// A and AAAA addresses in the answer section of a response
+///
+/// CNAME records are skipped, so the addresses at the end of a chain are
+/// returned. Malformed responses yield what was parsed up to the error.
+pub fn parse_answer_ips(response: &[u8]) -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    if response.len() < 12 {
+        return ips;
+    }
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        let Some(end) = skip_name(response, pos) else { return ips };
+        pos = end + 4;
+    }
+    for _ in 0..ancount {
+        let Some(end) = skip_name(response, pos) else { return ips };
+        let Some(fixed) = response.get(end..end + 10) else { return ips };
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let Some(rdata) = response.get(end + 10..end + 10 + rdlength) else { return ips };
+        match (rtype, rdlength) {
+            (TYPE_A, 4) => ips.push(IpAddr::from(<[u8; 4]>::try_from(rdata).expect("length checked"))),
+            (TYPE_AAAA, 16) => ips.push(IpAddr::from(<[u8; 16]>::try_from(rdata).expect("length checked"))),
+            _ => {}
+        }
+        pos = end + 10 + rdlength;
+    }
+    ips
+}
+
+/// Nameservers of a `resolv.conf`, without loopback stubs
+///
+/// Local stubs such as systemd-resolved answer from the hosts file, which is
+/// exactly what callers of this want to bypass.
+pub fn parse_resolv_conf(text: &str) -> Vec<SocketAddr> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        // Scoped IPv6 addresses (fe80::1%eth0) cannot be parsed portably
+        .filter_map(|addr| addr.parse::<IpAddr>().ok())
+        .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect()
+}
+
+/// Upstream resolvers of the system, or the defaults when none is usable
+pub fn system_upstreams() -> Vec<SocketAddr> {
+    let system = if cfg!(unix) {
+        std::fs::read_to_string("/etc/resolv.conf")
+            .map(|text| parse_resolv_conf(&text))
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if system.is_empty() {
+        DnsConfig::default().upstreams
+    } else {
+        system
+    }
+}
+
+/// Resolve a name's A and AAAA records directly at the upstreams
+///
+/// Unlike the system resolver this never consults the hosts file, so it sees
+/// what the name resolves to without this tool.
+pub fn resolve_upstream(name: &str, upstreams: &[SocketAddr], timeout: Duration) -> io::Result<Vec<IpAddr>> {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut ips = Vec::new();
+    let mut last_error = None;
+    for (i, qtype) in [TYPE_A, TYPE_AAAA].into_iter().enumerate() {
+        let id = (seed as u16) ^ (std::process::id() as u16).wrapping_add(i as u16);
+        match forward(&build_query(id, name, qtype), upstreams, timeout) {
+            Ok(response) => ips.extend(parse_answer_ips(&response)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    match last_error {
+        Some(e) if ips.is_empty() => Err(e),
+        _ => Ok(ips),
+    }
+}
+
+/// Resolver used for managed names
+pub type Resolver = dyn Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync;
+
+/// Local DNS server
+pub struct DnsServer {
+    socket: UdpSocket,
+    config: DnsConfig,
+    resolve: Arc<Resolver>,
+    /// Reload the optimized IP cache written by other processes
+    reload_cache: bool,
+}
+
+impl DnsServer {
+    /// Bind the listen address, answering managed names from the optimized IPs
+    pub fn bind(config: DnsConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind(config.bind)?;
+        socket.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS)))?;
+        Ok(Self {
+            socket,
+            config,
+            resolve: Arc::new(hosts::managed_ips),
+            reload_cache: true,
+        })
+    }
+
+    /// Use a different resolver for managed names
+    pub fn with_resolver<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync + 'static,
+    {
+        self.resolve = Arc::new(resolve);
+        self.reload_cache = false;
+        self
+    }
+
+    /// Address actually bound (useful with port 0)
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Answer queries until `stop` is set
+    ///
+    /// Managed names are answered inline; forwarded queries run on their own
+    /// thread so a slow upstream does not hold up other clients.
+    pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+        let reload_interval = Duration::from_secs(u64::from(self.config.ttl_secs.max(1)));
+        let mut last_reload = Instant::now();
+        let mut buf = [0u8; 4096];
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        while !stop.load(Ordering::Relaxed) {
+            if self.reload_cache && last_reload.elapsed() >= reload_interval {
+                if hosts::reload_optimized_ips() {
+                    #[cfg(debug_assertions)]
+                    log::info!("DNS: loaded newer optimized IPs");
+                }
+                last_reload = Instant::now();
+            }
+
+            let (n, client) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                // ICMP errors from earlier replies surface here on some platforms
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e),
+            };
+            let query = buf[..n].to_vec();
+            let Some(question) = parse_query(&query) else {
+                continue;
+            };
+
+            if question.qclass == CLASS_IN {
+                if let Some(ips) = (self.resolve)(&question.name) {
+                    let response = build_answer(&query, &question, &ips, self.config.ttl_secs);
+                    let _ = self.socket.send_to(&response, client);
+                    continue;
+                }
+            }
+
+            // Each forward waits on upstreams in its own thread; refuse new ones
+            // beyond the cap instead of piling up threads under a flood
+            if in_flight.fetch_add(1, Ordering::AcqRel) >= self.config.max_forwards {
+                in_flight.fetch_sub(1, Ordering::AcqRel);
+                let _ = self.socket.send_to(&build_error(&query, &question, RCODE_SERVFAIL), client);
+                continue;
+            }
+            let socket = self.socket.try_clone()?;
+            let upstreams = self.config.upstreams.clone();
+            let timeout = Duration::from_millis(self.config.upstream_timeout_ms);
+            let in_flight = Arc::clone(&in_flight);
+            thread::spawn(move || {
+                let response = match forward(&query, &upstreams, timeout) {
+                    Ok(response) => response,
+                    Err(_e) => {
+                        #[cfg(debug_assertions)]
+                        log::warn!("DNS: forwarding {} failed: {}", question.name, _e);
+                        build_error(&query, &question, RCODE_SERVFAIL)
+                    }
+                };
+                let _ = socket.send_to(&response, client);
+                in_flight.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_server(config: DnsConfig) -> (SocketAddr, Arc<AtomicBool>) {
+        let server = DnsServer::bind(config).unwrap().with_resolver(|name: &str| {
+            (name == "github.com").then(|| vec!["140.82.112.4".parse().unwrap(), "2001:db8::1".parse().unwrap()])
+        });
+        let addr = server.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        thread::spawn(move || server.serve(&flag));
+        (addr, stop)
+    }
+
+    fn exchange(server: SocketAddr, packet: &[u8]) -> Vec<u8> {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.send_to(packet, server).unwrap();
+        let mut buf = [0u8; 512];
+        let (n, _) = client.recv_from(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn test_parse_addresses() {
+        assert_eq!(parse_bind_addr(":53").unwrap(), "127.0.0.1:53".parse().unwrap());
+        assert_eq!(parse_bind_addr("0.0.0.0:53").unwrap(), "0.0.0.0:53".parse().unwrap());
+        assert_eq!(parse_bind_addr("5353").unwrap(), "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(parse_bind_addr("[::1]:5353").unwrap(), "[::1]:5353".parse().unwrap());
+        assert!(parse_bind_addr("localhost").is_err());
+        assert_eq!(parse_upstream("1.1.1.1").unwrap(), "1.1.1.1:53".parse().unwrap());
+        assert!(parse_upstream("dns.example").is_err());
+
+        let resolv = "# generated\nnameserver 127.0.0.53\nnameserver 192.0.2.53\nnameserver 2001:db8::53\nsearch lan\n";
+        assert_eq!(
+            parse_resolv_conf(resolv),
+            vec!["192.0.2.53:53".parse().unwrap(), "[2001:db8::53]:53".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_answers_managed_names() {
+        let config = DnsConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            upstreams: Vec::new(),
+            ..DnsConfig::default()
+        };
+        let (server, stop) = spawn_server(config);
+
+        let response = exchange(server, &build_query(0x1234, "GitHub.com", TYPE_A));
+        assert_eq!(&response[0..2], &[0x12, 0x34]);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        // TTL and address of the single A record at the end
+        let rdata = &response[response.len() - 4..];
+        assert_eq!(rdata, &[140, 82, 112, 4]);
+        let ttl = &response[response.len() - 10..response.len() - 6];
+        assert_eq!(u32::from_be_bytes(ttl.try_into().unwrap()), DEFAULT_TTL_SECS);
+
+        let response = exchange(server, &build_query(7, "github.com", TYPE_AAAA));
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+
+        // No upstreams: unmanaged names fail instead of hanging
+        let response = exchange(server, &build_query(8, "example.com", TYPE_A));
+        assert_eq!(u16::from_be_bytes([response[2], response[3]]) & 0xf, RCODE_SERVFAIL);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_forwards_unmanaged_names() {
+        // Stand-in upstream answering with a canned response
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (n, from) = upstream.recv_from(&mut buf).unwrap();
+            let question = parse_query(&buf[..n]).unwrap();
+            assert_eq!(question.name, "example.com");
+            let answer = build_answer(&buf[..n], &question, &["93.184.216.34".parse().unwrap()], 300);
+            upstream.send_to(&answer, from).unwrap();
+        });
+
+        let config = DnsConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            upstreams: vec![upstream_addr],
+            ..DnsConfig::default()
+        };
+        let (server, stop) = spawn_server(config);
+
+        let response = exchange(server, &build_query(42, "example.com", TYPE_A));
+        assert_eq!(&response[0..2], &[0, 42]);
+        assert_eq!(&response[response.len() - 4..], &[93, 184, 216, 34]);
+        assert_eq!(parse_answer_ips(&response), vec!["93.184.216.34".parse::<IpAddr>().unwrap()]);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_forwards_are_capped() {
+        let config = DnsConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            upstreams: vec!["127.0.0.1:9".parse().unwrap()],
+            max_forwards: 0,
+            ..DnsConfig::default()
+        };
+        let (server, stop) = spawn_server(config);
+
+        // Over the cap the query fails at once instead of waiting for upstreams
+        let response = exchange(server, &build_query(9, "example.com", TYPE_A));
+        assert_eq!(u16::from_be_bytes([response[2], response[3]]) & 0xf, RCODE_SERVFAIL);
+        // Managed names are still answered
+        let response = exchange(server, &build_query(10, "github.com", TYPE_A));
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_resolve_upstream_bypasses_hosts() {
+        // Upstream answering github.com with a CNAME followed by an A record
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, from)) = upstream.recv_from(&mut buf) {
+                let question = parse_query(&buf[..n]).unwrap();
+                let mut response = response_head(&buf[..n], &question, 0, 0);
+                if question.qtype == TYPE_A {
+                    response[7] = 2;
+                    // github.com CNAME lb.github.com (compressed against the question)
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 5, 2, b'l', b'b', 0xc0, 0x0c]);
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 140, 82, 112, 3]);
+                }
+                upstream.send_to(&response, from).unwrap();
+            }
+        });
+
+        let ips = resolve_upstream("github.com", &[upstream_addr], Duration::from_secs(2)).unwrap();
+        assert_eq!(ips, vec!["140.82.112.3".parse::<IpAddr>().unwrap()]);
+        assert!(parse_answer_ips(&[0; 5]).is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Re-running the hosts-writing step elevated through pkexec or `sudo -A`
+//!
+//! On Linux the unprivileged process keeps all state (speed test results,
+//! the computed mapping, the UI) and only the final write runs as root: the
+//! CLI binary is re-launched with [`ELEVATED_ARG`] and receives the command
+//! over stdin in the helper's wire format. The elevated side validates the
+//! mapping exactly like the helper does, so it can write nothing the helper
+//! would refuse.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+use crate::helper::{self, Command};
+use crate::hosts;
+
+/// Hidden CLI argument that makes the process act as the elevated writer
+pub const ELEVATED_ARG: &str = "--write-hosts-elevated";
+
+/// polkit action authorizing the elevated writer
+pub const POLKIT_ACTION: &str = "io.github.free_to_github.write-hosts";
+
+/// polkit policy to install under `/usr/share/polkit-1/actions/`
+pub const POLKIT_POLICY: &str = include_str!("../io.github.free_to_github.policy");
+
+/// Binary re-launched for the elevated step (the GUI uses the CLI next to it)
+const CLI_EXE: &str = "free_to_github_cli";
+
+/// Exit codes pkexec uses when the user dismissed or failed authentication
+const PKEXEC_NOT_AUTHORIZED: i32 = 126;
+const PKEXEC_CANNOT_EXEC: i32 = 127;
+
+static AUTO_ELEVATE: OnceLock<Mutex<bool>> = OnceLock::new();
+
+fn get_auto_elevate() -> &'static Mutex<bool> {
+    AUTO_ELEVATE.get_or_init(|| Mutex::new(false))
+}
+
+/// Whether hosts writes may fall back to an elevated re-launch
+pub fn auto_elevate() -> bool {
+    *get_auto_elevate().lock().unwrap()
+}
+
+/// Allow or forbid falling back to an elevated re-launch (off by default)
+pub fn set_auto_elevate(enabled: bool) {
+    *get_auto_elevate().lock().unwrap() = enabled;
+}
+
+/// How the elevated step is launched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// `pkexec`, authorized by the shipped polkit policy
+    Pkexec,
+    /// `sudo -A`, asking for the password through `$SUDO_ASKPASS`
+    SudoAskpass,
+}
+
+impl Method {
+    pub fn program(&self) -> &'static str {
+        match self {
+            Method::Pkexec => "pkexec",
+            Method::SudoAskpass => "sudo",
+        }
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Elevation method usable in this session, if any
+///
+/// pkexec is preferred in a graphical session, where a polkit agent can show
+/// the password dialog. `sudo -A` needs `$SUDO_ASKPASS`, since there may be no
+/// terminal to type into. Only Linux is supported.
+pub fn available_method() -> Option<Method> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let graphical = std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let askpass = std::env::var_os("SUDO_ASKPASS").is_some();
+    let pkexec = find_in_path("pkexec").is_some();
+    let sudo = find_in_path("sudo").is_some();
+
+    match (pkexec, sudo && askpass) {
+        (true, _) if graphical => Some(Method::Pkexec),
+        (_, true) => Some(Method::SudoAskpass),
+        (true, false) => Some(Method::Pkexec),
+        (false, false) => None,
+    }
+}
+
+/// Absolute path of the binary handling [`ELEVATED_ARG`]
+pub fn elevated_exe() -> Option<PathBuf> {
+    let current = std::env::current_exe().ok()?;
+    let is_cli = current
+        .file_stem()
+        .map(|stem| stem.to_string_lossy() == CLI_EXE)
+        .unwrap_or(false);
+    if is_cli {
+        return Some(current);
+    }
+    let sibling = current
+        .parent()?
+        .join(format!("{}{}", CLI_EXE, std::env::consts::EXE_SUFFIX));
+    sibling.is_file().then_some(sibling)
+}
+
+/// Command line re-launching `exe` as the elevated writer
+pub fn build_command(method: Method, exe: &Path) -> process::Command {
+    let mut command = process::Command::new(method.program());
+    if method == Method::SudoAskpass {
+        command.arg("-A").arg("--");
+    }
+    command.arg(exe).arg(ELEVATED_ARG);
+    command
+}
+
+/// Run one hosts command through an elevated re-launch and wait for it
+pub fn run_elevated(request: &Command) -> io::Result<()> {
+    let method = available_method().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Unsupported, "neither pkexec nor sudo with SUDO_ASKPASS is available")
+    })?;
+    let exe = elevated_exe().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} not found next to this program", CLI_EXE))
+    })?;
+
+    #[cfg(debug_assertions)]
+    log::info!("Elevate: running {} via {}", request.name(), method.program());
+
+    let mut child = build_command(method, &exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(request.encode().as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let detail = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("").trim();
+    match (method, output.status.code()) {
+        (Method::Pkexec, Some(PKEXEC_NOT_AUTHORIZED | PKEXEC_CANNOT_EXEC)) if detail.is_empty() => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "authorization was cancelled or denied",
+        )),
+        _ => Err(io::Error::other(format!("elevated {} failed: {}", request.name(), detail))),
+    }
+}
+
+/// Elevated side: read one command from stdin and apply it to the system hosts file
+pub fn handle_elevated_request() -> io::Result<()> {
+    let mut reader = io::stdin().lock().take(helper::MAX_REQUEST_BYTES);
+    let request = Command::read_from(&mut reader)?;
+    helper::execute(&request, &hosts::system_hosts_path(), &helper::system_backup_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_command() {
+        let exe = Path::new("/usr/bin/free_to_github_cli");
+        let args = |command: &process::Command| -> Vec<String> {
+            command.get_args().map(|a| a.to_string_lossy().into_owned()).collect()
+        };
+
+        let pkexec = build_command(Method::Pkexec, exe);
+        assert_eq!(pkexec.get_program(), "pkexec");
+        assert_eq!(args(&pkexec), vec!["/usr/bin/free_to_github_cli", ELEVATED_ARG]);
+
+        let sudo = build_command(Method::SudoAskpass, exe);
+        assert_eq!(sudo.get_program(), "sudo");
+        assert_eq!(args(&sudo), vec!["-A", "--", "/usr/bin/free_to_github_cli", ELEVATED_ARG]);
+    }
+
+    #[test]
+    fn test_polkit_policy() {
+        assert!(POLKIT_POLICY.contains(&format!("<action id=\"{}\">", POLKIT_ACTION)));
+        assert!(POLKIT_POLICY.contains(&format!("/usr/bin/{}</annotate>", CLI_EXE)));
+    }
+}
@@ -0,0 +1,211 @@
+//! Git smart-HTTP probe
+//!
+//! What CI cares about is how long `git ls-remote`/`fetch` negotiation takes,
+//! not TCP connect time. This probe performs the smart-HTTP ref advertisement
+//! request (`info/refs?service=git-upload-pack`) for a small public
+//! repository through a candidate IP and reports the negotiation time and
+//! response size. It can replace latency as the ranking signal for
+//! `github.com` and `codeload.github.com`.
+
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::network::{ProbeConfig, RankingSignal};
+
+/// Domains the git probe can rank
+pub const GIT_RANKED_DOMAINS: &[&str] = &["github.com", "codeload.github.com"];
+
+/// First pkt-line of a valid smart-HTTP advertisement
+const SERVICE_LINE: &[u8] = b"001e# service=git-upload-pack\n";
+
+/// Settings for git smart-HTTP probes
+#[derive(Debug, Clone)]
+pub struct GitProbeConfig {
+    /// Host sent as `Host`/SNI; the connection always goes to the candidate IP
+    pub host: String,
+    /// Repository path, e.g. `/octocat/Hello-World.git`
+    pub repo_path: String,
+    pub port: u16,
+    pub tls: bool,
+    /// Only the fastest reachable IPs (by connect latency) are probed
+    pub max_candidates: usize,
+}
+
+impl Default for GitProbeConfig {
+    fn default() -> Self {
+        Self {
+            host: "github.com".to_string(),
+            repo_path: "/octocat/Hello-World.git".to_string(),
+            port: 443,
+            tls: true,
+            max_candidates: 3,
+        }
+    }
+}
+
+/// Outcome of one git smart-HTTP probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitProbeResult {
+    /// Time from sending the request to the end of the advertisement
+    pub negotiation_ms: u64,
+    /// Connect, TLS handshake and negotiation together
+    pub total_ms: u64,
+    /// Size of the advertisement body in bytes
+    pub bytes: usize,
+    /// Number of refs advertised
+    pub refs: usize,
+}
+
+/// Rank `github.com` and `codeload.github.com` by git negotiation time
+pub fn use_git_ranking(config: &mut ProbeConfig) {
+    for domain in GIT_RANKED_DOMAINS {
+        config
+            .ranking_overrides
+            .insert(domain.to_string(), RankingSignal::GitNegotiation);
+    }
+}
+
+/// Count refs in a v0 ref advertisement, checking its pkt-line framing
+fn count_refs(body: &[u8]) -> io::Result<usize> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let rest = body
+        .strip_prefix(SERVICE_LINE)
+        .ok_or_else(|| invalid("not a git-upload-pack advertisement"))?;
+
+    let mut pos = 0;
+    let mut refs = 0;
+    let mut flushes = 0;
+    while pos + 4 <= rest.len() {
+        let len_hex = std::str::from_utf8(&rest[pos..pos + 4]).map_err(|_| invalid("bad pkt-line length"))?;
+        let len = usize::from_str_radix(len_hex, 16).map_err(|_| invalid("bad pkt-line length"))?;
+        if len == 0 {
+            // Flush after the service line, then after the ref list
+            flushes += 1;
+            pos += 4;
+            continue;
+        }
+        if len < 4 || pos + len > rest.len() {
+            return Err(invalid("truncated pkt-line"));
+        }
+        refs += 1;
+        pos += len;
+    }
+
+    if flushes < 2 {
+        return Err(invalid("incomplete ref advertisement"));
+    }
+    Ok(refs)
+}
+
+/// Run the ref advertisement request through `ip`
+pub fn probe_git(ip: &str, config: &GitProbeConfig, probe: &ProbeConfig) -> io::Result<GitProbeResult> {
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid IP: {}", ip)))?;
+    let timeout = Duration::from_millis(probe.timeout_ms);
+    let start = Instant::now();
+
+    let mut stream = http::connect(
+        &ip.to_string(),
+        config.port,
+        &config.host,
+        config.tls,
+        timeout,
+        probe.proxy.as_ref(),
+    )?;
+
+    let negotiation_start = Instant::now();
+    let path = format!("{}/info/refs?service=git-upload-pack", config.repo_path.trim_end_matches('/'));
+    http::send_get(&mut stream, &config.host, &path, &[])?;
+
+    let mut reader = BufReader::new(stream);
+    let (status, headers) = http::read_head(&mut reader)?;
+    if status != 200 {
+        return Err(io::Error::other(format!("unexpected HTTP status {}", status)));
+    }
+    let body = http::read_body(&mut reader, &headers)?;
+    let refs = count_refs(&body)?;
+
+    Ok(GitProbeResult {
+        negotiation_ms: negotiation_start.elapsed().as_millis() as u64,
+        total_ms: start.elapsed().as_millis() as u64,
+        bytes: body.len(),
+        refs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Local stand-in smart-HTTP server advertising two refs
+    fn spawn_smart_http_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_string();
+            assert!(request.starts_with("GET /octocat/Hello-World.git/info/refs?service=git-upload-pack HTTP/1.1"));
+            assert!(request.contains("Host: github.com"));
+
+            let mut body = Vec::new();
+            body.extend_from_slice(SERVICE_LINE);
+            body.extend_from_slice(b"0000");
+            let head = b"7fd1a60b01f91b314f59955a4e4d4e80d8edf11d HEAD\0multi_ack side-band-64k\n";
+            let main = b"7fd1a60b01f91b314f59955a4e4d4e80d8edf11d refs/heads/master\n";
+            for line in [&head[..], &main[..]] {
+                body.extend_from_slice(format!("{:04x}", line.len() + 4).as_bytes());
+                body.extend_from_slice(line);
+            }
+            body.extend_from_slice(b"0000");
+
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/x-git-upload-pack-advertisement\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        });
+
+        port
+    }
+
+    #[test]
+    fn test_probe_git_against_stand_in() {
+        let port = spawn_smart_http_server();
+        let config = GitProbeConfig {
+            port,
+            tls: false,
+            ..GitProbeConfig::default()
+        };
+        let result = probe_git("127.0.0.1", &config, &ProbeConfig::default()).unwrap();
+
+        assert_eq!(result.refs, 2);
+        assert!(result.bytes > SERVICE_LINE.len());
+        assert!(result.total_ms >= result.negotiation_ms);
+    }
+
+    #[test]
+    fn test_use_git_ranking() {
+        let mut config = ProbeConfig::default();
+        use_git_ranking(&mut config);
+        assert_eq!(config.ranking_overrides.get("github.com"), Some(&RankingSignal::GitNegotiation));
+        assert_eq!(config.ranking_overrides.get("codeload.github.com"), Some(&RankingSignal::GitNegotiation));
+        assert!(!config.ranking_overrides.contains_key("api.github.com"));
+    }
+
+    #[test]
+    fn test_count_refs_rejects_non_git() {
+        assert!(count_refs(b"<html>rate limited</html>").is_err());
+        let mut truncated = SERVICE_LINE.to_vec();
+        truncated.extend_from_slice(b"00000040abc");
+        assert!(count_refs(&truncated).is_err());
+    }
+}
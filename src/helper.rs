@@ -0,0 +1,719 @@
+//! Privilege-separated hosts writes through a small root helper
+//!
+//! The helper (`free_to_github_helper`) is the only part that runs as root.
+//! It listens on a Unix domain socket, checks the peer's credentials and
+//! accepts exactly four commands: apply a mapping, disable, repair and
+//! restore. Every mapping is re-validated on the helper side (managed domain,
+//! IP inside the allowlist) so a compromised client cannot point arbitrary
+//! names anywhere. Probing and the UIs stay unprivileged and use the
+//! functions at the bottom of this module, which write directly when the
+//! process can, go through the helper otherwise, and as a last resort
+//! re-launch the write elevated when that is enabled (see `elevate`).
+//!
+//! Wire format, one command per connection:
+//!
+//! ```text
+//! APPLY <n>\n<ip> <domain>\n... (n lines)
+//! DISABLE\n | REPAIR\n | RESTORE\n
+//! ```
+//!
+//! answered by `OK\n` or `ERR <message>\n`.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use crate::{allowlist, elevate, hosts, network, paths};
+
+/// Environment variable overriding the helper socket path
+pub const SOCKET_ENV: &str = "FREE_TO_GITHUB_HELPER_SOCKET";
+
+/// Default helper socket path
+pub const DEFAULT_SOCKET: &str = "/run/free-to-github/helper.sock";
+
+/// File name of the hosts backup taken before the first modification
+pub const BACKUP_FILE: &str = "hosts.backup";
+
+/// Directory of the helper's backup; kept apart from the service state
+/// directory, which `service uninstall` removes
+pub const SYSTEM_BACKUP_DIR: &str = "/var/backups/free-to-github";
+
+/// Largest mapping the helper accepts
+pub const MAX_ENTRIES: usize = 512;
+
+/// Upper bound on a request, so a client cannot make the helper buffer forever
+pub(crate) const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// Socket read/write timeout (milliseconds)
+#[cfg(unix)]
+const IO_TIMEOUT_MS: u64 = 10_000;
+
+/// How often the accept loop checks for a stop request (milliseconds)
+#[cfg(unix)]
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// Helper socket path, honouring [`SOCKET_ENV`]
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET))
+}
+
+/// Backup used when this process writes the hosts file itself
+pub fn local_backup_path() -> PathBuf {
+    paths::data_dir().join(BACKUP_FILE)
+}
+
+/// Backup used by the helper
+pub fn system_backup_path() -> PathBuf {
+    Path::new(SYSTEM_BACKUP_DIR).join(BACKUP_FILE)
+}
+
+/// A request to the helper
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Replace the managed block with these `(ip, domain)` pairs
+    Apply(Vec<(String, String)>),
+    /// Remove the managed block
+    Disable,
+    /// Clean up damaged or duplicated managed blocks
+    Repair,
+    /// Put back the hosts file saved before the first modification
+    Restore,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Apply(_) => "APPLY",
+            Command::Disable => "DISABLE",
+            Command::Repair => "REPAIR",
+            Command::Restore => "RESTORE",
+        }
+    }
+
+    /// Serialize for the wire
+    pub fn encode(&self) -> String {
+        match self {
+            Command::Apply(mapping) => {
+                let mut out = format!("APPLY {}\n", mapping.len());
+                for (ip, domain) in mapping {
+                    out.push_str(&format!("{} {}\n", ip, domain));
+                }
+                out
+            }
+            other => format!("{}\n", other.name()),
+        }
+    }
+
+    /// Read one command; syntax only, see [`validate_mapping`] for the content
+    pub fn read_from(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let command = match (parts.next(), parts.next(), parts.next()) {
+            (Some("APPLY"), Some(count), None) => {
+                let count: usize = count.parse().map_err(|_| invalid("invalid APPLY count"))?;
+                if count == 0 || count > MAX_ENTRIES {
+                    return Err(invalid(format!("APPLY count must be 1..={}", MAX_ENTRIES)));
+                }
+                let mut mapping = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let mut entry = String::new();
+                    if reader.read_line(&mut entry)? == 0 {
+                        return Err(invalid("truncated APPLY mapping"));
+                    }
+                    let mut fields = entry.split_whitespace();
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some(ip), Some(domain), None) => mapping.push((ip.to_string(), domain.to_string())),
+                        _ => return Err(invalid(format!("malformed mapping line: {}", entry.trim()))),
+                    }
+                }
+                Command::Apply(mapping)
+            }
+            (Some("DISABLE"), None, None) => Command::Disable,
+            (Some("REPAIR"), None, None) => Command::Repair,
+            (Some("RESTORE"), None, None) => Command::Restore,
+            _ => return Err(invalid(format!("unknown command: {}", line.trim()))),
+        };
+        Ok(command)
+    }
+}
+
+/// Check a mapping before it goes anywhere near the hosts file
+///
+/// Every domain must be in the catalog and every IP must parse and pass the
+/// allowlist, whatever the client's own strictness setting.
+pub fn validate_mapping(mapping: &[(String, String)]) -> io::Result<()> {
+    if mapping.is_empty() || mapping.len() > MAX_ENTRIES {
+        return Err(invalid(format!("mapping must have 1..={} entries", MAX_ENTRIES)));
+    }
+
+    let mut violations = Vec::new();
+    for (ip, domain) in mapping {
+        if !network::is_managed_domain(domain) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not a managed domain", domain),
+            ));
+        }
+        if ip.parse::<IpAddr>().is_err() {
+            return Err(invalid(format!("{} is not a valid IP address", ip)));
+        }
+        if let Err(violation) = allowlist::validate(domain, ip) {
+            violations.push(violation);
+        }
+    }
+    if !violations.is_empty() {
+        return Err(allowlist::violations_error(&violations));
+    }
+    Ok(())
+}
+
+/// Copy the hosts file to `backup` unless a backup already exists
+///
+/// The backup therefore always holds the file as it was before the first
+/// change made through this module. Returns `true` when a copy was made.
+pub fn ensure_backup(hosts_path: &Path, backup: &Path) -> io::Result<bool> {
+    if backup.exists() {
+        return Ok(false);
+    }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(hosts_path, backup)?;
+    Ok(true)
+}
+
+/// Write a validated mapping as the managed block of `hosts_path`
+pub fn apply_mapping_at(hosts_path: &Path, backup: &Path, mapping: &[(String, String)]) -> io::Result<()> {
+    validate_mapping(mapping)?;
+    ensure_backup(hosts_path, backup)?;
+
+    let content = fs::read_to_string(hosts_path)?;
+    let mut new_content = hosts::strip_block(&content).trim_end_matches('\n').to_string();
+    new_content.push_str("\n\n");
+    new_content.push_str(&hosts::render_block(mapping));
+    fs::write(hosts_path, new_content)
+}
+
+/// Remove the managed block from `hosts_path`
+pub fn disable_at(hosts_path: &Path, backup: &Path) -> io::Result<()> {
+    let content = fs::read_to_string(hosts_path)?;
+    let stripped = hosts::strip_block(&content);
+    if stripped != content {
+        ensure_backup(hosts_path, backup)?;
+        fs::write(hosts_path, stripped)?;
+    }
+    Ok(())
+}
+
+/// Repair the managed blocks of `hosts_path`; returns `true` if anything changed
+pub fn repair_at(hosts_path: &Path, backup: &Path) -> io::Result<bool> {
+    let content = fs::read_to_string(hosts_path)?;
+    let repaired = hosts::repair_content(&content);
+    if repaired == content {
+        return Ok(false);
+    }
+    ensure_backup(hosts_path, backup)?;
+    fs::write(hosts_path, repaired)?;
+    Ok(true)
+}
+
+/// Put the backup back in place and drop it, so the next change takes a fresh one
+pub fn restore_at(hosts_path: &Path, backup: &Path) -> io::Result<()> {
+    let saved = fs::read(backup).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            io::Error::new(io::ErrorKind::NotFound, "no hosts backup to restore")
+        } else {
+            e
+        }
+    })?;
+    fs::write(hosts_path, saved)?;
+    fs::remove_file(backup)
+}
+
+/// Run one command against a hosts file
+pub fn execute(command: &Command, hosts_path: &Path, backup: &Path) -> io::Result<()> {
+    match command {
+        Command::Apply(mapping) => apply_mapping_at(hosts_path, backup, mapping),
+        Command::Disable => disable_at(hosts_path, backup),
+        Command::Repair => repair_at(hosts_path, backup).map(|_| ()),
+        Command::Restore => restore_at(hosts_path, backup),
+    }
+}
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::io::{BufReader, Read};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Credentials of the process on the other end of the socket
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PeerCredentials {
+        pub uid: u32,
+        pub gid: u32,
+    }
+
+    /// Peer credentials as reported by the kernel (`SO_PEERCRED`)
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: cred and len are valid for writes and len matches the buffer size
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials { uid: cred.uid, gid: cred.gid })
+    }
+
+    /// Peer credentials as reported by the kernel (`getpeereid`)
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut uid: libc::uid_t = 0;
+        let mut gid: libc::gid_t = 0;
+        // SAFETY: uid and gid are valid for writes
+        let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials { uid, gid })
+    }
+
+    /// Real user id of this process
+    pub fn current_uid() -> u32 {
+        // SAFETY: getuid has no preconditions and cannot fail
+        unsafe { libc::getuid() }
+    }
+
+    /// Settings for the helper
+    #[derive(Debug, Clone)]
+    pub struct HelperConfig {
+        pub socket: PathBuf,
+        pub hosts_path: PathBuf,
+        pub backup: PathBuf,
+        /// Users besides root allowed to send commands
+        pub allowed_uids: Vec<u32>,
+        /// Groups whose members (by primary gid) may send commands
+        pub allowed_gids: Vec<u32>,
+    }
+
+    impl Default for HelperConfig {
+        fn default() -> Self {
+            Self {
+                socket: socket_path(),
+                hosts_path: hosts::get_hosts_path(),
+                backup: system_backup_path(),
+                allowed_uids: Vec::new(),
+                allowed_gids: Vec::new(),
+            }
+        }
+    }
+
+    impl HelperConfig {
+        /// Whether a peer may talk to the helper
+        pub fn is_authorized(&self, peer: PeerCredentials) -> bool {
+            peer.uid == 0 || self.allowed_uids.contains(&peer.uid) || self.allowed_gids.contains(&peer.gid)
+        }
+    }
+
+    /// The privileged side: a Unix socket server executing validated commands
+    pub struct Helper {
+        listener: UnixListener,
+        config: HelperConfig,
+    }
+
+    impl Helper {
+        /// Bind the socket, replacing a stale one left by a previous run
+        ///
+        /// The socket itself is world-connectable; access is decided per
+        /// connection from the peer credentials.
+        pub fn bind(config: HelperConfig) -> io::Result<Self> {
+            if let Some(parent) = config.socket.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if config.socket.exists() {
+                if UnixStream::connect(&config.socket).is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("a helper is already listening on {}", config.socket.display()),
+                    ));
+                }
+                fs::remove_file(&config.socket)?;
+            }
+
+            let listener = UnixListener::bind(&config.socket)?;
+            fs::set_permissions(&config.socket, fs::Permissions::from_mode(0o666))?;
+            listener.set_nonblocking(true)?;
+            Ok(Self { listener, config })
+        }
+
+        pub fn socket(&self) -> &Path {
+            &self.config.socket
+        }
+
+        /// Answer clients until `stop` is set, then remove the socket
+        pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+            while !stop.load(Ordering::Relaxed) {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        // Commands are short and must not interleave, so they run inline
+                        if let Err(_e) = self.handle(stream) {
+                            #[cfg(debug_assertions)]
+                            log::warn!("Helper: failed to answer client: {}", _e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            let _ = fs::remove_file(&self.config.socket);
+            Ok(())
+        }
+
+        fn handle(&self, mut stream: UnixStream) -> io::Result<()> {
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(Duration::from_millis(IO_TIMEOUT_MS)))?;
+            stream.set_write_timeout(Some(Duration::from_millis(IO_TIMEOUT_MS)))?;
+
+            let peer = peer_credentials(&stream)?;
+            if !self.config.is_authorized(peer) {
+                #[cfg(debug_assertions)]
+                log::warn!("Helper: refused uid {} gid {}", peer.uid, peer.gid);
+                return stream.write_all(b"ERR not authorized\n");
+            }
+
+            let mut reader = BufReader::new((&stream).take(MAX_REQUEST_BYTES));
+            let result = Command::read_from(&mut reader)
+                .and_then(|command| {
+                    #[cfg(debug_assertions)]
+                    log::info!("Helper: {} from uid {}", command.name(), peer.uid);
+                    execute(&command, &self.config.hosts_path, &self.config.backup)
+                });
+
+            match result {
+                Ok(()) => stream.write_all(b"OK\n"),
+                Err(e) => {
+                    let message = e.to_string().replace('\n', " ");
+                    stream.write_all(format!("ERR {}\n", message).as_bytes())
+                }
+            }
+        }
+    }
+
+    /// Whether a helper answers on `socket`
+    ///
+    /// A socket file left behind by a crashed helper does not count.
+    pub fn is_listening(socket: &Path) -> bool {
+        UnixStream::connect(socket).is_ok()
+    }
+
+    /// Whether a helper answers on the default socket
+    pub fn is_available() -> bool {
+        is_listening(&socket_path())
+    }
+
+    /// Send one command to the helper listening on `socket`
+    pub fn send_to(socket: &Path, command: &Command) -> io::Result<()> {
+        let mut stream = UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(Duration::from_millis(IO_TIMEOUT_MS)))?;
+        stream.set_write_timeout(Some(Duration::from_millis(IO_TIMEOUT_MS)))?;
+        stream.write_all(command.encode().as_bytes())?;
+
+        let mut reply = String::new();
+        BufReader::new(&stream).take(MAX_REQUEST_BYTES).read_line(&mut reply)?;
+        let reply = reply.trim_end();
+        if reply == "OK" {
+            return Ok(());
+        }
+        let message = reply.strip_prefix("ERR ").unwrap_or("no reply from helper");
+        let kind = if message == "not authorized" {
+            io::ErrorKind::PermissionDenied
+        } else {
+            io::ErrorKind::Other
+        };
+        Err(io::Error::new(kind, format!("helper: {}", message)))
+    }
+
+    /// Send one command to the default helper
+    pub fn send(command: &Command) -> io::Result<()> {
+        send_to(&socket_path(), command)
+    }
+}
+
+/// Who performs a hosts change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    /// This process can write the file itself
+    Direct,
+    /// A running helper writes it
+    Helper,
+    /// An elevated re-launch of the CLI writes it
+    Elevated,
+}
+
+#[cfg(unix)]
+fn helper_available() -> bool {
+    is_available()
+}
+
+#[cfg(not(unix))]
+fn helper_available() -> bool {
+    false
+}
+
+fn route() -> Route {
+    // The helper and the elevated writer only ever touch the system hosts
+    // file, so writes to another file are never delegated
+    if hosts::check_permission().is_ok() || hosts::is_hosts_path_overridden() {
+        Route::Direct
+    } else if helper_available() {
+        Route::Helper
+    } else if elevate::auto_elevate() && elevate::available_method().is_some() {
+        Route::Elevated
+    } else {
+        // Let the direct write fail with the usual permission error
+        Route::Direct
+    }
+}
+
+#[cfg(unix)]
+fn delegate(route: Route, command: Command) -> io::Result<()> {
+    match route {
+        Route::Elevated => elevate::run_elevated(&command),
+        _ => match send(&command) {
+            // The helper went away since `route` looked
+            Err(e)
+                if matches!(e.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound)
+                    && elevate::auto_elevate()
+                    && elevate::available_method().is_some() =>
+            {
+                elevate::run_elevated(&command)
+            }
+            result => result,
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn delegate(_route: Route, command: Command) -> io::Result<()> {
+    elevate::run_elevated(&command)
+}
+
+/// Whether hosts changes can be made, directly, through the helper or elevated
+pub fn check_write_access() -> Result<(), String> {
+    match hosts::check_permission() {
+        Ok(()) => Ok(()),
+        Err(_) if route() != Route::Direct => Ok(()),
+        Err(msg) if hosts::is_hosts_path_overridden() => Err(format!(
+            "{}\n{} 不是系统 hosts 文件, 不会通过助手或提权写入",
+            msg,
+            hosts::get_hosts_path().display()
+        )),
+        Err(msg) => Err(msg),
+    }
+}
+
+fn enable_with(use_optimized: bool) -> io::Result<()> {
+    let route = route();
+    if route != Route::Direct {
+        return delegate(route, Command::Apply(hosts::current_mapping(use_optimized)?));
+    }
+    let hosts_path = hosts::get_hosts_path();
+    ensure_backup(&hosts_path, &local_backup_path())?;
+    if use_optimized {
+        hosts::enable_optimized()
+    } else {
+        hosts::enable()
+    }
+}
+
+/// [`hosts::enable`], delegated when this process cannot write hosts
+pub fn enable() -> io::Result<()> {
+    enable_with(false)
+}
+
+/// [`hosts::enable_optimized`], delegated when this process cannot write hosts
+pub fn enable_optimized() -> io::Result<()> {
+    enable_with(true)
+}
+
+/// Write explicit `(ip, domain)` pairs as the managed block, delegated when
+/// this process cannot write hosts
+pub fn apply_mapping(mapping: Vec<(String, String)>) -> io::Result<()> {
+    let route = route();
+    if route != Route::Direct {
+        return delegate(route, Command::Apply(mapping));
+    }
+    apply_mapping_at(&hosts::get_hosts_path(), &local_backup_path(), &mapping)
+}
+
+/// [`hosts::disable`], delegated when this process cannot write hosts
+pub fn disable() -> io::Result<()> {
+    let route = route();
+    if route != Route::Direct {
+        return delegate(route, Command::Disable);
+    }
+    disable_at(&hosts::get_hosts_path(), &local_backup_path())
+}
+
+/// Clean up damaged managed blocks, delegated when needed
+pub fn repair() -> io::Result<()> {
+    let route = route();
+    if route != Route::Direct {
+        return delegate(route, Command::Repair);
+    }
+    repair_at(&hosts::get_hosts_path(), &local_backup_path()).map(|_| ())
+}
+
+/// Restore the hosts file saved before the first change, delegated when needed
+pub fn restore() -> io::Result<()> {
+    let route = route();
+    if route != Route::Direct {
+        return delegate(route, Command::Restore);
+    }
+    restore_at(&hosts::get_hosts_path(), &local_backup_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = paths::data_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn mapping() -> Vec<(String, String)> {
+        vec![
+            ("140.82.112.3".to_string(), "github.com".to_string()),
+            ("185.199.108.133".to_string(), "raw.githubusercontent.com".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_command_roundtrip() {
+        for command in [Command::Apply(mapping()), Command::Disable, Command::Repair, Command::Restore] {
+            let encoded = command.encode();
+            let decoded = Command::read_from(&mut BufReader::new(encoded.as_bytes())).unwrap();
+            assert_eq!(decoded, command);
+        }
+
+        for bad in ["APPLY 0\n", "APPLY 2\n1.2.3.4 github.com\n", "APPLY x\n", "RM -RF\n", "DISABLE now\n"] {
+            assert!(Command::read_from(&mut BufReader::new(bad.as_bytes())).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_validate_mapping() {
+        assert!(validate_mapping(&mapping()).is_ok());
+        assert!(validate_mapping(&[]).is_err());
+        assert!(validate_mapping(&[("140.82.112.3".to_string(), "example.com".to_string())]).is_err());
+        assert!(validate_mapping(&[("not-an-ip".to_string(), "github.com".to_string())]).is_err());
+        assert!(validate_mapping(&[("10.0.0.1".to_string(), "github.com".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_apply_disable_restore() {
+        let dir = temp_dir("helper_files");
+        let hosts_path = dir.join("hosts");
+        let backup = dir.join(BACKUP_FILE);
+        let original = "127.0.0.1 localhost\n";
+        fs::write(&hosts_path, original).unwrap();
+
+        apply_mapping_at(&hosts_path, &backup, &mapping()).unwrap();
+        apply_mapping_at(&hosts_path, &backup, &mapping()[..1]).unwrap();
+        let content = fs::read_to_string(&hosts_path).unwrap();
+        assert!(content.starts_with(original));
+        assert_eq!(content.matches("FREE_TO_GITHUB START").count(), 1);
+        assert!(content.contains("140.82.112.3 github.com"));
+        assert!(!content.contains("raw.githubusercontent.com"));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+
+        disable_at(&hosts_path, &backup).unwrap();
+        assert!(!fs::read_to_string(&hosts_path).unwrap().contains("FREE_TO_GITHUB"));
+
+        fs::write(&hosts_path, "garbage\n").unwrap();
+        restore_at(&hosts_path, &backup).unwrap();
+        assert_eq!(fs::read_to_string(&hosts_path).unwrap(), original);
+        assert!(!backup.exists());
+        assert!(restore_at(&hosts_path, &backup).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_helper_socket() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        let dir = temp_dir("helper_socket");
+        let hosts_path = dir.join("hosts");
+        fs::write(&hosts_path, "127.0.0.1 localhost\n").unwrap();
+        let config = HelperConfig {
+            socket: dir.join("helper.sock"),
+            hosts_path: hosts_path.clone(),
+            backup: dir.join(BACKUP_FILE),
+            allowed_uids: vec![current_uid()],
+            allowed_gids: Vec::new(),
+        };
+        assert!(config.is_authorized(PeerCredentials { uid: 0, gid: 0 }));
+        assert!(!HelperConfig { allowed_uids: Vec::new(), ..config.clone() }
+            .is_authorized(PeerCredentials { uid: 12345, gid: 12345 }));
+
+        let helper = Helper::bind(config.clone()).unwrap();
+        let socket = helper.socket().to_path_buf();
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let handle = thread::spawn(move || helper.serve(&flag));
+        assert!(is_listening(&socket));
+
+        send_to(&socket, &Command::Apply(mapping())).unwrap();
+        assert!(fs::read_to_string(&hosts_path).unwrap().contains("140.82.112.3 github.com"));
+
+        let refused = Command::Apply(vec![("10.0.0.1".to_string(), "github.com".to_string())]);
+        assert!(send_to(&socket, &refused).is_err());
+
+        send_to(&socket, &Command::Disable).unwrap();
+        assert!(!fs::read_to_string(&hosts_path).unwrap().contains("FREE_TO_GITHUB"));
+
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap().unwrap();
+        assert!(!socket.exists());
+
+        // A stale socket file is not a running helper
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(socket.exists());
+        assert!(!is_listening(&socket));
+        assert_eq!(send_to(&socket, &Command::Disable).unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,502 @@
+//! Persistent latency history
+//!
+//! Every probe of a speed test is appended to a tab-separated file in the data
+//! directory, one measurement per line. The query API turns the connect probes
+//! into per-IP and per-domain trends so selection and UIs can look beyond one
+//! snapshot; throughput and git probes are kept alongside for inspection.
+//! Appends and compaction take a lock file, so several processes (CLI, GUI,
+//! daemon) can record without losing each other's lines, and compaction runs
+//! at most once per [`COMPACT_INTERVAL_SECS`].
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::network::{ProbeKind, ProbeOutcome};
+use crate::paths;
+
+/// File name of the history store inside the data directory
+const HISTORY_FILE: &str = "history.tsv";
+
+/// First line of a history file
+const HISTORY_HEADER: &str = "# free_to_github latency history v1";
+
+/// Number of recent samples trends are computed over by default
+pub const DEFAULT_TREND_WINDOW: usize = 20;
+
+/// Minimum time between two compactions of the store (one day)
+pub const COMPACT_INTERVAL_SECS: u64 = 24 * 3600;
+
+// Whether speed tests record their measurements
+static RECORDING_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn recording of speed test measurements on or off
+pub fn set_recording_enabled(enabled: bool) {
+    RECORDING_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Check whether speed tests record their measurements
+pub fn is_recording_enabled() -> bool {
+    RECORDING_ENABLED.load(Ordering::Relaxed)
+}
+
+/// One probe of one IP for one domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub domain: String,
+    pub ip: String,
+    pub kind: ProbeKind,
+    /// Latency in milliseconds, `None` when the probe failed (git probes store
+    /// the negotiation time, throughput probes the transfer time)
+    pub latency_ms: Option<u64>,
+    /// Sustained rate of a throughput probe in kilobits per second
+    pub kbps: Option<u64>,
+    /// Why the probe failed (`Success` when it did not)
+    pub outcome: ProbeOutcome,
+    /// Fingerprint of the network the probe ran on (empty when unknown)
+    pub network: String,
+}
+
+impl Measurement {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.timestamp,
+            self.domain,
+            self.ip,
+            self.kind.as_str(),
+            // Failures store the outcome name in place of the latency
+            self.latency_ms
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| self.outcome.as_str().to_string()),
+            self.network,
+            self.kbps.map(|k| k.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Measurement> {
+        let mut fields = line.split('\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let domain = fields.next()?.to_string();
+        let ip = fields.next()?.to_string();
+        let kind = ProbeKind::parse(fields.next()?)?;
+        let (latency_ms, outcome) = match fields.next()? {
+            // Failures recorded before outcomes were classified
+            "-" => (None, ProbeOutcome::Other),
+            value => match ProbeOutcome::parse(value) {
+                Some(outcome) => (None, outcome),
+                None => (Some(value.parse().ok()?), ProbeOutcome::Success),
+            },
+        };
+        let network = fields.next().unwrap_or("").to_string();
+        // Absent in records written before throughput probes were stored
+        let kbps = fields.next().and_then(|k| k.parse().ok());
+
+        Some(Measurement {
+            timestamp,
+            domain,
+            ip,
+            kind,
+            latency_ms,
+            kbps,
+            outcome,
+            network,
+        })
+    }
+}
+
+/// Trend over the most recent samples of an IP or domain
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trend {
+    /// Samples considered (at most the requested window)
+    pub samples: usize,
+    pub successes: usize,
+    /// Share of successful samples, 0.0 when there are none
+    pub success_rate: f64,
+    /// Median latency of the successful samples in the window
+    pub rolling_median_ms: Option<u64>,
+    /// Unix time of the latest successful sample in the whole history
+    pub last_seen_good: Option<u64>,
+}
+
+/// Retention and compaction settings
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Drop measurements older than this many seconds
+    pub max_age_secs: u64,
+    /// Keep at most this many recent measurements per (domain, IP)
+    pub max_per_ip: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 30 * 24 * 3600,
+            max_per_ip: 500,
+        }
+    }
+}
+
+/// Append-only measurement store backed by a single file
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    path: PathBuf,
+}
+
+/// Exclusive lock on a store, released when dropped
+struct StoreLock {
+    _file: fs::File,
+}
+
+impl HistoryStore {
+    /// Open a store at an explicit path (created on first append)
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Open the store in the default data directory
+    pub fn open_default() -> Self {
+        Self::open(paths::data_dir().join(HISTORY_FILE))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for the store's lock file; compaction replaces the data file
+    /// itself, so the lock lives next to it
+    fn lock(&self) -> io::Result<StoreLock> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("tsv.lock"))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            // SAFETY: the descriptor is owned by `file`, which outlives the call
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(StoreLock { _file: file })
+    }
+
+    /// Append measurements to the store
+    pub fn append(&self, measurements: &[Measurement]) -> io::Result<()> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+
+        let is_new = !self.path.exists();
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            writeln!(writer, "{}", HISTORY_HEADER)?;
+        }
+        for m in measurements {
+            writeln!(writer, "{}", m.to_line())?;
+        }
+        writer.flush()
+    }
+
+    /// Load every measurement, oldest first, skipping malformed lines
+    pub fn load(&self) -> io::Result<Vec<Measurement>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(Measurement::from_line)
+            .collect())
+    }
+
+    /// Measurements of the most recent speed test
+    pub fn last_run(&self) -> io::Result<Vec<Measurement>> {
+        let all = self.load()?;
+        let latest = all.iter().map(|m| m.timestamp).max();
+        Ok(all.into_iter().filter(|m| Some(m.timestamp) == latest).collect())
+    }
+
+    /// Connect probes only, which every trend is computed over
+    fn load_connects(&self) -> io::Result<Vec<Measurement>> {
+        let mut all = self.load()?;
+        all.retain(|m| m.kind == ProbeKind::TcpConnect);
+        Ok(all)
+    }
+
+    /// Trend for one IP across all domains over the last `window` samples
+    pub fn ip_trend(&self, ip: &str, window: usize) -> io::Result<Trend> {
+        let all = self.load_connects()?;
+        Ok(compute_trend(all.iter().filter(|m| m.ip == ip), window))
+    }
+
+    /// Trend for one domain across all its IPs over the last `window` samples
+    pub fn domain_trend(&self, domain: &str, window: usize) -> io::Result<Trend> {
+        let all = self.load_connects()?;
+        Ok(compute_trend(all.iter().filter(|m| m.domain == domain), window))
+    }
+
+    /// Per-IP trends of a domain, best median first
+    pub fn domain_ip_trends(&self, domain: &str, window: usize) -> io::Result<Vec<(String, Trend)>> {
+        let all = self.load_connects()?;
+        let mut ips: Vec<&str> = all
+            .iter()
+            .filter(|m| m.domain == domain)
+            .map(|m| m.ip.as_str())
+            .collect();
+        ips.sort_unstable();
+        ips.dedup();
+
+        let mut trends: Vec<(String, Trend)> = ips
+            .into_iter()
+            .map(|ip| {
+                let samples = all.iter().filter(|m| m.domain == domain && m.ip == ip);
+                (ip.to_string(), compute_trend(samples, window))
+            })
+            .collect();
+        trends.sort_by_key(|(_, t)| t.rolling_median_ms.unwrap_or(u64::MAX));
+        Ok(trends)
+    }
+
+    /// Trends of every (domain, IP) pair, from a single read of the store
+    pub fn trends_by_domain_ip(&self, window: usize) -> io::Result<HashMap<(String, String), Trend>> {
+        let all = self.load_connects()?;
+        let mut grouped: HashMap<(String, String), Vec<&Measurement>> = HashMap::new();
+        for m in &all {
+            grouped.entry((m.domain.clone(), m.ip.clone())).or_default().push(m);
+        }
+        Ok(grouped
+            .into_iter()
+            .map(|(key, samples)| (key, compute_trend(samples.into_iter(), window)))
+            .collect())
+    }
+
+    /// Apply the retention policy, rewriting the file; returns records removed
+    pub fn compact(&self, policy: &RetentionPolicy, now: u64) -> io::Result<usize> {
+        let _lock = self.lock()?;
+        self.compact_locked(policy, now)
+    }
+
+    /// Compact unless that was done less than [`COMPACT_INTERVAL_SECS`] ago;
+    /// returns the records removed, `None` when it was not due
+    pub fn compact_if_due(&self, policy: &RetentionPolicy, now: u64) -> io::Result<Option<usize>> {
+        let _lock = self.lock()?;
+        let marker = self.path.with_extension("tsv.compacted");
+        let last = fs::read_to_string(&marker).ok().and_then(|t| t.trim().parse::<u64>().ok());
+        if last.is_some_and(|last| now.saturating_sub(last) < COMPACT_INTERVAL_SECS) {
+            return Ok(None);
+        }
+        let removed = self.compact_locked(policy, now)?;
+        fs::write(&marker, now.to_string())?;
+        Ok(Some(removed))
+    }
+
+    fn compact_locked(&self, policy: &RetentionPolicy, now: u64) -> io::Result<usize> {
+        let all = self.load()?;
+        let cutoff = now.saturating_sub(policy.max_age_secs);
+
+        // Walk newest first so the per-IP cap keeps the most recent records
+        let mut per_key: std::collections::HashMap<(&str, &str), usize> = std::collections::HashMap::new();
+        let mut kept: Vec<&Measurement> = Vec::with_capacity(all.len());
+        for m in all.iter().rev() {
+            if m.timestamp < cutoff {
+                continue;
+            }
+            let count = per_key.entry((m.domain.as_str(), m.ip.as_str())).or_insert(0);
+            if *count < policy.max_per_ip {
+                *count += 1;
+                kept.push(m);
+            }
+        }
+        kept.reverse();
+
+        let removed = all.len() - kept.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        // Write to a temporary file first so a crash never loses the history
+        let tmp = self.path.with_extension("tsv.tmp");
+        {
+            let mut writer = BufWriter::new(fs::File::create(&tmp)?);
+            writeln!(writer, "{}", HISTORY_HEADER)?;
+            for m in &kept {
+                writeln!(writer, "{}", m.to_line())?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+
+        Ok(removed)
+    }
+}
+
+/// Compute a trend over the last `window` samples (samples given oldest first)
+fn compute_trend<'a>(samples: impl Iterator<Item = &'a Measurement>, window: usize) -> Trend {
+    let samples: Vec<&Measurement> = samples.collect();
+    let last_seen_good = samples
+        .iter()
+        .filter(|m| m.latency_ms.is_some())
+        .map(|m| m.timestamp)
+        .max();
+
+    let recent = &samples[samples.len().saturating_sub(window)..];
+    let mut latencies: Vec<u64> = recent.iter().filter_map(|m| m.latency_ms).collect();
+    latencies.sort_unstable();
+
+    let rolling_median_ms = match latencies.len() {
+        0 => None,
+        n if n % 2 == 1 => Some(latencies[n / 2]),
+        n => Some((latencies[n / 2 - 1] + latencies[n / 2]) / 2),
+    };
+
+    Trend {
+        samples: recent.len(),
+        successes: latencies.len(),
+        success_rate: if recent.is_empty() {
+            0.0
+        } else {
+            latencies.len() as f64 / recent.len() as f64
+        },
+        rolling_median_ms,
+        last_seen_good,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> HistoryStore {
+        let path = paths::data_dir().join(format!("{}.tsv", name));
+        let _ = fs::remove_file(&path);
+        HistoryStore::open(path)
+    }
+
+    fn sample(timestamp: u64, ip: &str, latency_ms: Option<u64>) -> Measurement {
+        Measurement {
+            timestamp,
+            domain: "github.com".to_string(),
+            ip: ip.to_string(),
+            kind: ProbeKind::TcpConnect,
+            latency_ms,
+            kbps: None,
+            outcome: if latency_ms.is_some() {
+                ProbeOutcome::Success
+            } else {
+                ProbeOutcome::Timeout
+            },
+            network: "home".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_append_load_roundtrip() {
+        let store = temp_store("history_roundtrip");
+        let measurements = vec![sample(100, "140.82.112.4", Some(80)), sample(101, "140.82.113.4", None)];
+        store.append(&measurements).unwrap();
+        store.append(&[sample(102, "140.82.112.4", Some(60))]).unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded[..2], measurements[..]);
+        assert_eq!(loaded[1].outcome, ProbeOutcome::Timeout);
+        assert_eq!(loaded[2].latency_ms, Some(60));
+        assert_eq!(store.last_run().unwrap().len(), 1);
+
+        // Throughput probes keep their rate; lines without it still load
+        let throughput = Measurement {
+            kind: ProbeKind::Throughput,
+            kbps: Some(48_000),
+            ..sample(103, "185.199.108.133", Some(700))
+        };
+        store.append(std::slice::from_ref(&throughput)).unwrap();
+        assert_eq!(store.last_run().unwrap(), vec![throughput]);
+        assert_eq!(Measurement::from_line("5\tgithub.com\t1.2.3.4\ttcp\t40\thome").unwrap().kbps, None);
+    }
+
+    #[test]
+    fn test_trends() {
+        let store = temp_store("history_trends");
+        store
+            .append(&[
+                sample(100, "140.82.112.4", Some(90)),
+                sample(200, "140.82.112.4", Some(50)),
+                sample(300, "140.82.112.4", None),
+                sample(400, "140.82.112.4", Some(70)),
+                sample(500, "140.82.113.4", None),
+            ])
+            .unwrap();
+
+        let trend = store.ip_trend("140.82.112.4", 10).unwrap();
+        assert_eq!(trend.samples, 4);
+        assert_eq!(trend.successes, 3);
+        assert_eq!(trend.rolling_median_ms, Some(70));
+        assert_eq!(trend.last_seen_good, Some(400));
+        assert!((trend.success_rate - 0.75).abs() < f64::EPSILON);
+
+        // Window limits the samples considered, not last-seen-good
+        let trend = store.ip_trend("140.82.112.4", 2).unwrap();
+        assert_eq!(trend.samples, 2);
+        assert_eq!(trend.rolling_median_ms, Some(70));
+
+        let dead = store.ip_trend("140.82.113.4", 10).unwrap();
+        assert_eq!(dead.success_rate, 0.0);
+        assert_eq!(dead.last_seen_good, None);
+
+        // Other probe kinds do not skew the latency trends
+        let git = Measurement {
+            kind: ProbeKind::GitSmartHttp,
+            ..sample(600, "140.82.112.4", Some(900))
+        };
+        store.append(&[git]).unwrap();
+        assert_eq!(store.ip_trend("140.82.112.4", 10).unwrap().rolling_median_ms, Some(70));
+
+        let per_ip = store.domain_ip_trends("github.com", 10).unwrap();
+        assert_eq!(per_ip[0].0, "140.82.112.4");
+        let all = store.trends_by_domain_ip(10).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[&("github.com".to_string(), "140.82.112.4".to_string())], per_ip[0].1);
+        assert_eq!(store.domain_trend("github.com", 10).unwrap().samples, 5);
+    }
+
+    #[test]
+    fn test_compact() {
+        let store = temp_store("history_compact");
+        let mut measurements: Vec<Measurement> = (0..10).map(|i| sample(1000 + i, "140.82.112.4", Some(i))).collect();
+        measurements.push(sample(10, "140.82.113.4", Some(1)));
+        store.append(&measurements).unwrap();
+
+        let policy = RetentionPolicy {
+            max_age_secs: 500,
+            max_per_ip: 3,
+        };
+        assert_eq!(store.compact(&policy, 1100).unwrap(), 8);
+
+        let kept = store.load().unwrap();
+        let timestamps: Vec<u64> = kept.iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, vec![1007, 1008, 1009]);
+
+        // Scheduled compaction runs once per interval
+        let _ = fs::remove_file(store.path().with_extension("tsv.compacted"));
+        store.append(&[sample(1200, "140.82.112.4", Some(5))]).unwrap();
+        assert_eq!(store.compact_if_due(&policy, 1200).unwrap(), Some(1));
+        store.append(&[sample(1300, "140.82.112.4", Some(5))]).unwrap();
+        assert_eq!(store.compact_if_due(&policy, 1300).unwrap(), None);
+        assert_eq!(store.load().unwrap().len(), 4);
+        assert_eq!(store.compact_if_due(&policy, 1200 + COMPACT_INTERVAL_SECS).unwrap(), Some(4));
+    }
+}
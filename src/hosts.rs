@@ -1,6 +1,6 @@
 use std::fs::{self, OpenOptions};
 use std::io::{self, Write, BufWriter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{OnceLock, Mutex};
 use std::collections::HashMap;
 
@@ -10,7 +10,10 @@ use std::time::Instant;
 #[cfg(debug_assertions)]
 use crate::logger;
 
-use crate::network;
+use std::net::IpAddr;
+
+use crate::network::{self, FamilyPolicy, RankedResults};
+use crate::{allowlist, netprofile, paths};
 
 const HOSTS_PATH_WINDOWS: &str = r"C:\Windows\System32\drivers\etc\hosts";
 const HOSTS_PATH_UNIX: &str = "/etc/hosts";
@@ -34,11 +37,366 @@ const DEFAULT_GITHUB_HOSTS: &[(&str, &str)] = &[
     ("140.82.113.22", "collector.github.com"),
 ];
 
-// Global cache for optimized IPs (domain -> best_ip)
-static OPTIMIZED_IPS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+/// File name of the optimized IP cache inside the data directory
+const OPTIMIZED_CACHE_FILE: &str = "optimized_ips.cache";
+
+/// First line of the optimized IP cache file
+const OPTIMIZED_CACHE_HEADER: &str = "# free_to_github optimized IPs v1";
+
+/// How long speed test results stay usable (24 hours)
+pub const OPTIMIZED_CACHE_TTL_SECS: u64 = 24 * 3600;
+
+/// Speed test results with the time they were produced
+#[derive(Debug, Clone, Default, PartialEq)]
+struct OptimizedIps {
+    /// domain -> reachable IPs with latency, fastest first
+    ranked: RankedResults,
+    /// Unix time the results were produced
+    saved_at: u64,
+}
+
+impl OptimizedIps {
+    fn is_fresh(&self, ttl_secs: u64, now: u64) -> bool {
+        !self.ranked.is_empty() && now.saturating_sub(self.saved_at) <= ttl_secs
+    }
+}
+
+// Global cache for optimized IPs, seeded from the cache file of a previous run
+static OPTIMIZED_IPS: OnceLock<Mutex<OptimizedIps>> = OnceLock::new();
+
+fn get_optimized_ips() -> &'static Mutex<OptimizedIps> {
+    OPTIMIZED_IPS.get_or_init(|| {
+        let cached = load_optimized_cache(&optimized_cache_path(), OPTIMIZED_CACHE_TTL_SECS, paths::unix_now());
+        Mutex::new(cached.unwrap_or_default())
+    })
+}
+
+/// Path of the file speed test results are persisted to
+pub fn optimized_cache_path() -> PathBuf {
+    paths::data_dir().join(OPTIMIZED_CACHE_FILE)
+}
+
+/// Write results to the cache file (`domain<TAB>ip<TAB>latency` lines)
+fn save_optimized_cache(path: &Path, optimized: &OptimizedIps) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut content = String::with_capacity(1024);
+    content.push_str(OPTIMIZED_CACHE_HEADER);
+    content.push_str(&format!("\nsaved_at={}\n", optimized.saved_at));
+
+    let mut domains: Vec<&String> = optimized.ranked.keys().collect();
+    domains.sort();
+    for domain in domains {
+        for (ip, latency) in &optimized.ranked[domain] {
+            content.push_str(&format!("{}\t{}\t{}\n", domain, ip, latency));
+        }
+    }
 
-fn get_optimized_ips() -> &'static Mutex<HashMap<String, String>> {
-    OPTIMIZED_IPS.get_or_init(|| Mutex::new(HashMap::new()))
+    // Write then rename so readers never see a half-written file
+    let tmp = path.with_extension("cache.tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+/// Read the cache file, returning `None` when it is missing, corrupt or stale
+fn load_optimized_cache(path: &Path, ttl_secs: u64, now: u64) -> Option<OptimizedIps> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+    if lines.next()? != OPTIMIZED_CACHE_HEADER {
+        return None;
+    }
+    let saved_at = lines.next()?.strip_prefix("saved_at=")?.parse().ok()?;
+
+    let mut ranked: RankedResults = HashMap::new();
+    for line in lines {
+        let mut fields = line.split('\t');
+        let (Some(domain), Some(ip), Some(latency)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if ip.parse::<IpAddr>().is_err() {
+            continue;
+        }
+        if let Ok(latency) = latency.parse::<u64>() {
+            ranked.entry(domain.to_string()).or_default().push((ip.to_string(), latency));
+        }
+    }
+
+    let optimized = OptimizedIps { ranked, saved_at };
+    optimized.is_fresh(ttl_secs, now).then_some(optimized)
+}
+
+/// Replace the in-process results and persist them for other processes
+fn store_optimized_ips(ranked: RankedResults) {
+    let mut optimized = get_optimized_ips().lock().unwrap();
+    *optimized = OptimizedIps {
+        ranked,
+        saved_at: paths::unix_now(),
+    };
+
+    if let Err(_e) = save_optimized_cache(&optimized_cache_path(), &optimized) {
+        #[cfg(debug_assertions)]
+        log::warn!("Failed to save optimized IP cache: {}", _e);
+    }
+
+    // Also keep the results as the known-good set of the current network
+    let network = netprofile::current_network_id();
+    if !network.is_empty() && !optimized.ranked.is_empty() {
+        if let Err(_e) = save_optimized_cache(&netprofile::profile_path(&network), &optimized) {
+            #[cfg(debug_assertions)]
+            log::warn!("Failed to save results for network {}: {}", network, _e);
+        }
+    }
+}
+
+/// Unix time the stored results of a network were produced, without loading them
+pub fn network_profile_saved_at(network_id: &str) -> Option<u64> {
+    load_optimized_cache(&netprofile::profile_path(network_id), u64::MAX, 0).map(|profile| profile.saved_at)
+}
+
+/// Load the stored results of a network as the current optimized IPs
+///
+/// Returns the Unix time the results were produced, or `None` when the
+/// network has no stored results.
+pub fn load_network_profile(network_id: &str) -> Option<u64> {
+    let profile = load_optimized_cache(&netprofile::profile_path(network_id), u64::MAX, 0)?;
+    let saved_at = profile.saved_at;
+
+    let mut optimized = get_optimized_ips().lock().unwrap();
+    *optimized = profile;
+    if let Err(_e) = save_optimized_cache(&optimized_cache_path(), &optimized) {
+        #[cfg(debug_assertions)]
+        log::warn!("Failed to save optimized IP cache: {}", _e);
+    }
+
+    Some(saved_at)
+}
+
+/// Order of IPv4 and IPv6 entries when several IPs are written per domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interleave {
+    /// Strictly by measured latency
+    #[default]
+    ByRank,
+    /// Alternate families, starting with the family of the fastest IP
+    Alternate,
+    /// All IPv4 entries before IPv6
+    V4First,
+    /// All IPv6 entries before IPv4
+    V6First,
+}
+
+impl Interleave {
+    /// Parse an order name (`rank`, `alternate`, `v4-first` or `v6-first`)
+    pub fn parse(name: &str) -> Option<Interleave> {
+        match name {
+            "rank" | "by-rank" => Some(Interleave::ByRank),
+            "alternate" => Some(Interleave::Alternate),
+            "v4-first" | "v4first" => Some(Interleave::V4First),
+            "v6-first" | "v6first" => Some(Interleave::V6First),
+            _ => None,
+        }
+    }
+}
+
+/// How many IPs are written per domain and in which order
+///
+/// Resolvers usually return every matching hosts entry and clients try them in
+/// turn, so writing several healthy IPs gives failover without a re-test.
+#[derive(Debug, Clone)]
+pub struct HostsLayout {
+    /// IPs written per family for domains without an override
+    pub default_top_n: usize,
+    /// Per-domain overrides of `default_top_n`
+    pub per_domain: HashMap<String, usize>,
+    pub interleave: Interleave,
+}
+
+impl Default for HostsLayout {
+    fn default() -> Self {
+        Self {
+            default_top_n: 1,
+            per_domain: HashMap::new(),
+            interleave: Interleave::default(),
+        }
+    }
+}
+
+impl HostsLayout {
+    /// Number of IPs per family written for a domain (at least one)
+    pub fn top_n_for(&self, domain: &str) -> usize {
+        self.per_domain
+            .get(domain)
+            .copied()
+            .unwrap_or(self.default_top_n)
+            .max(1)
+    }
+}
+
+// Global hosts layout used by the writer
+static HOSTS_LAYOUT: OnceLock<Mutex<HostsLayout>> = OnceLock::new();
+
+fn get_hosts_layout() -> &'static Mutex<HostsLayout> {
+    HOSTS_LAYOUT.get_or_init(|| Mutex::new(HostsLayout::default()))
+}
+
+/// Current hosts layout
+pub fn hosts_layout() -> HostsLayout {
+    get_hosts_layout().lock().unwrap().clone()
+}
+
+/// Replace the hosts layout
+pub fn set_hosts_layout(layout: HostsLayout) {
+    *get_hosts_layout().lock().unwrap() = layout;
+}
+
+/// Pick the IPs written for a domain from its ranked (reachable) list
+///
+/// Keeps the `top_n` best IPs of each family allowed by the policy and orders
+/// them according to `interleave`.
+fn select_hosts_ips(
+    ranked: &[(String, u64)],
+    family: FamilyPolicy,
+    top_n: usize,
+    interleave: Interleave,
+) -> Vec<String> {
+    let best_of = |want_v6: bool| -> Vec<&(String, u64)> {
+        ranked
+            .iter()
+            .filter(|(ip, _)| {
+                family.allows(ip) && ip.parse::<IpAddr>().is_ok_and(|addr| addr.is_ipv6() == want_v6)
+            })
+            .take(top_n)
+            .collect()
+    };
+    let (v4, v6) = (best_of(false), best_of(true));
+
+    let ordered: Vec<&(String, u64)> = match interleave {
+        Interleave::ByRank => {
+            let mut all: Vec<&(String, u64)> = v4.iter().chain(v6.iter()).copied().collect();
+            all.sort_by_key(|(_, latency)| *latency);
+            all
+        }
+        Interleave::V4First => v4.iter().chain(v6.iter()).copied().collect(),
+        Interleave::V6First => v6.iter().chain(v4.iter()).copied().collect(),
+        Interleave::Alternate => {
+            let v6_leads = match (v4.first(), v6.first()) {
+                (Some(a), Some(b)) => b.1 < a.1,
+                (None, Some(_)) => true,
+                _ => false,
+            };
+            let (lead, follow) = if v6_leads { (&v6, &v4) } else { (&v4, &v6) };
+            let mut all = Vec::with_capacity(lead.len() + follow.len());
+            for i in 0..lead.len().max(follow.len()) {
+                all.extend(lead.get(i).copied());
+                all.extend(follow.get(i).copied());
+            }
+            all
+        }
+    };
+
+    ordered.into_iter().map(|(ip, _)| ip.clone()).collect()
+}
+
+/// IPs the hosts writer uses for a domain entry
+///
+/// Rankings past the cache TTL are ignored, like everywhere else.
+fn selected_ips_for(
+    entry: &network::DomainEntry,
+    optimized: &OptimizedIps,
+    family: FamilyPolicy,
+    layout: &HostsLayout,
+) -> Vec<String> {
+    let fresh = optimized.is_fresh(OPTIMIZED_CACHE_TTL_SECS, paths::unix_now());
+    let selected: Vec<String> = match optimized.ranked.get(&entry.domain).filter(|_| fresh) {
+        Some(ranked) => select_hosts_ips(ranked, family, layout.top_n_for(&entry.domain), layout.interleave)
+            .into_iter()
+            // Never write an IP outside GitHub/Fastly address space
+            .filter(|ip| allowlist::validate(&entry.domain, ip).is_ok())
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if selected.is_empty() {
+        // Fallback to first built-in IP; imported IPs are only used once probed
+        entry.fallback_ip(family).cloned().into_iter().collect()
+    } else {
+        selected
+    }
+}
+
+/// Optimized IPs that fail allowlist validation and are kept out of the hosts file
+pub fn allowlist_violations() -> Vec<allowlist::Violation> {
+    let optimized = get_optimized_ips().lock().unwrap();
+    if !optimized.is_fresh(OPTIMIZED_CACHE_TTL_SECS, paths::unix_now()) {
+        return Vec::new();
+    }
+    let family = network::probe_config().family;
+    let layout = hosts_layout();
+
+    let mut violations = Vec::new();
+    for entry in network::get_domain_candidates() {
+        if let Some(ranked) = optimized.ranked.get(&entry.domain) {
+            let top_n = layout.top_n_for(&entry.domain);
+            for ip in select_hosts_ips(ranked, family, top_n, layout.interleave) {
+                if let Err(violation) = allowlist::validate(&entry.domain, &ip) {
+                    violations.push(violation);
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// IPs currently selected for a managed domain, in the order they are written
+///
+/// This is the optimized IP table shared by every resolution backend.
+pub fn selected_ips(domain: &str) -> Vec<String> {
+    let optimized = get_optimized_ips().lock().unwrap();
+    let family = network::probe_config().family;
+    let layout = hosts_layout();
+
+    network::get_domain_candidates()
+        .iter()
+        .find(|entry| entry.domain == domain)
+        .map(|entry| selected_ips_for(entry, &optimized, family, &layout))
+        .unwrap_or_default()
+}
+
+/// IPs a resolution backend should use for a name, or `None` for unmanaged names
+///
+/// Managed domains use their selected IPs. Names matching a group wildcard
+/// (e.g. `foo.githubusercontent.com`) share the IPs of the group's first
+/// managed member, which is the coherent set chosen for the whole group.
+pub fn managed_ips(name: &str) -> Option<Vec<IpAddr>> {
+    let domain = if network::is_managed_domain(name) {
+        name.to_string()
+    } else {
+        let group = network::domain_groups().into_iter().find(|g| g.matches(name))?;
+        network::get_domain_candidates()
+            .into_iter()
+            .find(|entry| group.matches(&entry.domain))?
+            .domain
+    };
+
+    let ips: Vec<IpAddr> = selected_ips(&domain).iter().filter_map(|ip| ip.parse().ok()).collect();
+    (!ips.is_empty()).then_some(ips)
+}
+
+/// `(ip, domain)` pairs from the optimized IPs (best N per family, in rank order)
+fn optimized_mapping() -> Vec<(String, String)> {
+    let optimized = get_optimized_ips().lock().unwrap();
+    let family = network::probe_config().family;
+    let layout = hosts_layout();
+
+    let mut mapping = Vec::new();
+    for entry in network::get_domain_candidates() {
+        for ip in selected_ips_for(&entry, &optimized, family, &layout) {
+            mapping.push((ip, entry.domain.clone()));
+        }
+    }
+    mapping
 }
 
 /// Build hosts content using optimized IPs if available, otherwise use defaults
@@ -47,21 +405,8 @@ fn build_hosts_content() -> Vec<u8> {
     writeln!(content, "\n{}", MARKER_START).unwrap();
     writeln!(content, "# Auto-optimized by Free to GitHub").unwrap();
     
-    let optimized = get_optimized_ips().lock().unwrap();
-    
-    // Use optimized IPs for domains that have been tested
-    for entry in network::get_domain_candidates() {
-        let ip = optimized
-            .get(&entry.domain)
-            .cloned()
-            .unwrap_or_else(|| {
-                // Fallback to first candidate IP
-                entry.candidate_ips.first().cloned().unwrap_or_default()
-            });
-        
-        if !ip.is_empty() {
-            writeln!(content, "{} {}", ip, entry.domain).unwrap();
-        }
+    for (ip, domain) in optimized_mapping() {
+        writeln!(content, "{} {}", ip, domain).unwrap();
     }
     
     writeln!(content, "{}", MARKER_END).unwrap();
@@ -81,46 +426,252 @@ fn build_default_hosts_content() -> Vec<u8> {
     content
 }
 
+/// `(ip, domain)` pairs the next enable would write
+///
+/// Applies the same rules as [`enable_optimized`] / [`enable`], including the
+/// strict allowlist check, so the pairs can be handed to another process (the
+/// privileged helper) to write.
+pub fn current_mapping(use_optimized: bool) -> io::Result<Vec<(String, String)>> {
+    if use_optimized && allowlist::is_strict() {
+        let violations = allowlist_violations();
+        if !violations.is_empty() {
+            return Err(allowlist::violations_error(&violations));
+        }
+    }
+
+    if use_optimized && has_optimized_ips() {
+        Ok(optimized_mapping())
+    } else {
+        Ok(DEFAULT_GITHUB_HOSTS
+            .iter()
+            .map(|(ip, domain)| (ip.to_string(), domain.to_string()))
+            .collect())
+    }
+}
+
+/// Managed block for explicit `(ip, domain)` pairs, including the markers
+pub fn render_block(mapping: &[(String, String)]) -> String {
+    let mut block = String::with_capacity(64 + mapping.len() * 40);
+    block.push_str(MARKER_START);
+    block.push('\n');
+    for (ip, domain) in mapping {
+        block.push_str(&format!("{} {}\n", ip, domain));
+    }
+    block.push_str(MARKER_END);
+    block.push('\n');
+    block
+}
+
+/// Hosts content with the managed block removed (what [`disable`] writes)
+pub fn strip_block(content: &str) -> String {
+    match (content.find(MARKER_START), content.find(MARKER_END)) {
+        (Some(start_pos), Some(end)) if end > start_pos => {
+            let end_pos = end + MARKER_END.len();
+            let before = content[..start_pos].trim_end_matches('\n');
+            let after = content[end_pos..].trim_start_matches('\n');
+
+            let mut new_content = String::with_capacity(before.len() + after.len() + 2);
+            new_content.push_str(before);
+            if !after.is_empty() {
+                new_content.push('\n');
+                new_content.push_str(after);
+            }
+            new_content
+        }
+        _ => content.to_string(),
+    }
+}
+
+/// Whether a line inside a managed block is one we could have written
+fn is_managed_line(line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return true;
+    }
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(ip), Some(domain), None) => ip.parse::<IpAddr>().is_ok() && network::is_managed_domain(domain),
+        _ => false,
+    }
+}
+
+/// Fix damaged managed blocks left behind by interrupted or concurrent writes
+///
+/// Removes every managed block, duplicated and unterminated ones included,
+/// and stray markers. A foreign line ends an unterminated block and is kept.
+/// The last complete block is written back once at the end, so a healthy
+/// file comes out unchanged apart from whitespace around the block.
+pub fn repair_content(content: &str) -> String {
+    let mut kept: Vec<&str> = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    let mut last_complete: Option<Vec<&str>> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed == MARKER_START {
+            current = Some(Vec::new());
+        } else if trimmed == MARKER_END {
+            if let Some(block) = current.take() {
+                last_complete = Some(block);
+            }
+        } else if let Some(block) = current.as_mut() {
+            if is_managed_line(line) {
+                block.push(trimmed);
+            } else {
+                current = None;
+                kept.push(line);
+            }
+        } else {
+            kept.push(line);
+        }
+    }
+
+    let mut repaired = kept.join("\n").trim_end_matches('\n').to_string();
+    if let Some(block) = last_complete {
+        repaired.push_str("\n\n");
+        repaired.push_str(MARKER_START);
+        repaired.push('\n');
+        for line in block.iter().filter(|l| !l.is_empty()) {
+            repaired.push_str(line);
+            repaired.push('\n');
+        }
+        repaired.push_str(MARKER_END);
+    }
+    repaired.push('\n');
+    repaired
+}
+
 /// Update optimized IPs from speed test results
 pub fn set_optimized_ips(results: HashMap<String, (String, u64)>) {
-    let mut optimized = get_optimized_ips().lock().unwrap();
-    optimized.clear();
-    
-    for (domain, (ip, _latency)) in results {
-        optimized.insert(domain, ip);
-    }
+    store_optimized_ips(
+        results
+            .into_iter()
+            .map(|(domain, best)| (domain, vec![best]))
+            .collect(),
+    );
+}
+
+/// Update optimized IPs from ranked speed test results (both IP families)
+pub fn set_ranked_ips(results: RankedResults) {
+    store_optimized_ips(
+        results
+            .into_iter()
+            .filter(|(_, ranked)| !ranked.is_empty())
+            .collect(),
+    );
 }
 
-/// Check if we have optimized IPs available
+/// Current ranked results per domain, as last stored or loaded from the cache
+pub fn ranked_ips() -> RankedResults {
+    get_optimized_ips().lock().unwrap().ranked.clone()
+}
+
+/// Check if we have fresh optimized IPs available (from this run or the cache)
 pub fn has_optimized_ips() -> bool {
     let optimized = get_optimized_ips().lock().unwrap();
-    !optimized.is_empty()
+    optimized.is_fresh(OPTIMIZED_CACHE_TTL_SECS, paths::unix_now())
 }
 
 /// Get current optimized IP for a domain (if any)
 pub fn get_optimized_ip(domain: &str) -> Option<String> {
     let optimized = get_optimized_ips().lock().unwrap();
-    optimized.get(domain).cloned()
+    optimized
+        .ranked
+        .get(domain)
+        .and_then(|ranked| ranked.first().map(|(ip, _)| ip.clone()))
 }
 
-/// Clear optimized IPs cache
+/// Pick up results another process saved to the cache file since we loaded ours
+///
+/// Long-running backends (the local DNS server) call this periodically so a
+/// speed test run from the GUI or CLI takes effect without a restart.
+/// Returns `true` when newer results were loaded.
+pub fn reload_optimized_ips() -> bool {
+    let Some(cached) = load_optimized_cache(&optimized_cache_path(), OPTIMIZED_CACHE_TTL_SECS, paths::unix_now()) else {
+        return false;
+    };
+    let mut optimized = get_optimized_ips().lock().unwrap();
+    if cached.saved_at <= optimized.saved_at {
+        return false;
+    }
+    *optimized = cached;
+    true
+}
+
+/// Unix time the current optimized IPs were produced (if any)
+pub fn optimized_ips_saved_at() -> Option<u64> {
+    let optimized = get_optimized_ips().lock().unwrap();
+    (!optimized.ranked.is_empty()).then_some(optimized.saved_at)
+}
+
+/// Clear optimized IPs cache, including the persisted copy
 pub fn clear_optimized_ips() {
     let mut optimized = get_optimized_ips().lock().unwrap();
-    optimized.clear();
+    *optimized = OptimizedIps::default();
+    let _ = fs::remove_file(optimized_cache_path());
+}
+
+// Hosts file used instead of the system one (tests, --hosts, the config file)
+static HOSTS_PATH_OVERRIDE: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+
+fn get_hosts_path_override() -> &'static Mutex<Option<PathBuf>> {
+    HOSTS_PATH_OVERRIDE.get_or_init(|| Mutex::new(None))
+}
+
+/// Use another hosts file, or the system one again with `None`
+pub fn set_hosts_path(path: Option<PathBuf>) {
+    *get_hosts_path_override().lock().unwrap() = path;
+}
+
+/// Hosts file every operation reads and writes
+pub fn get_hosts_path() -> PathBuf {
+    get_hosts_path_override().lock().unwrap().clone().unwrap_or_else(system_hosts_path)
+}
+
+/// Whether operations target a file other than the system hosts file
+pub fn is_hosts_path_overridden() -> bool {
+    get_hosts_path_override()
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|path| *path != system_hosts_path())
 }
 
-pub fn get_hosts_path() -> &'static str {
+/// The system hosts file, regardless of any override
+pub fn system_hosts_path() -> PathBuf {
     if cfg!(target_os = "windows") {
-        HOSTS_PATH_WINDOWS
+        PathBuf::from(HOSTS_PATH_WINDOWS)
     } else {
-        HOSTS_PATH_UNIX
+        PathBuf::from(HOSTS_PATH_UNIX)
     }
 }
 
+/// `(ip, domain)` pairs currently in the managed block of the hosts file
+pub fn managed_entries() -> io::Result<Vec<(String, String)>> {
+    let content = match fs::read_to_string(get_hosts_path()) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != MARKER_START)
+        .skip(1)
+        .take_while(|line| *line != MARKER_END)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect())
+}
+
 /// Ultra-fast check using find instead of contains
 pub fn is_enabled() -> io::Result<bool> {
     let path = get_hosts_path();
-    match fs::read_to_string(path) {
+    match fs::read_to_string(&path) {
         Ok(content) => {
             // Use find which is typically faster for single searches
             Ok(content.find(MARKER_START).is_some())
@@ -135,7 +686,12 @@ pub fn enable() -> io::Result<()> {
     enable_with_ips(false)
 }
 
-/// Enable with optimized IPs (uses speed test results if available)
+/// Enable with optimized IPs
+///
+/// Uses results from this run or the persisted cache, falling back to the
+/// defaults only when neither is available or the results are stale. IPs
+/// failing allowlist validation are skipped, or refused outright in strict
+/// mode.
 pub fn enable_optimized() -> io::Result<()> {
     enable_with_ips(true)
 }
@@ -143,9 +699,17 @@ pub fn enable_optimized() -> io::Result<()> {
 /// Internal enable function
 fn enable_with_ips(use_optimized: bool) -> io::Result<()> {
     let hosts_path = get_hosts_path();
+
+    // In strict mode a single rejected IP blocks the whole update
+    if use_optimized && allowlist::is_strict() {
+        let violations = allowlist_violations();
+        if !violations.is_empty() {
+            return Err(allowlist::violations_error(&violations));
+        }
+    }
     
     // Read current content
-    let content = fs::read_to_string(hosts_path)?;
+    let content = fs::read_to_string(&hosts_path)?;
     
     // If already enabled, disable first to update IPs
     if content.find(MARKER_START).is_some() {
@@ -166,7 +730,7 @@ fn enable_with_ips(use_optimized: bool) -> io::Result<()> {
     let file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(hosts_path)?;
+        .open(&hosts_path)?;
     let mut writer = BufWriter::with_capacity(1024, file);
     writer.write_all(&hosts_content)?;
     writer.flush()?;
@@ -183,48 +747,25 @@ pub fn disable() -> io::Result<()> {
     let start = Instant::now();
     
     let hosts_path = get_hosts_path();
-    let content = fs::read_to_string(hosts_path)?;
+    let content = fs::read_to_string(&hosts_path)?;
     
-    // Use find for faster string search
-    match (content.find(MARKER_START), content.find(MARKER_END)) {
-        (Some(start_pos), Some(end)) => {
-            let end_pos = end + MARKER_END.len();
-            
-            // Trim whitespace efficiently
-            let before = content[..start_pos].trim_end_matches('\n');
-            let after = content[end_pos..].trim_start_matches('\n');
-            
-            // Build new content with minimal allocations
-            let capacity = before.len() + after.len() + 2;
-            let mut new_content = String::with_capacity(capacity);
-            new_content.push_str(before);
-            
-            if !after.is_empty() {
-                new_content.push('\n');
-                new_content.push_str(after);
-            }
-            
-            fs::write(hosts_path, new_content.into_bytes())?;
-            #[cfg(debug_assertions)]
-            logger::log_hosts_operation("disable", start.elapsed().as_millis(), true);
-            Ok(())
-        }
-        _ => {
-            // Not enabled, nothing to do
-            #[cfg(debug_assertions)]
-            logger::log_hosts_operation("disable", start.elapsed().as_millis(), true);
-            Ok(())
-        }
+    if content.find(MARKER_START).is_some() && content.find(MARKER_END).is_some() {
+        fs::write(&hosts_path, strip_block(&content).into_bytes())?;
     }
+
+    // Not enabled means nothing to do
+    #[cfg(debug_assertions)]
+    logger::log_hosts_operation("disable", start.elapsed().as_millis(), true);
+    Ok(())
 }
 
 pub fn check_permission() -> Result<(), String> {
     let hosts_path = get_hosts_path();
-    if !Path::new(hosts_path).exists() {
-        return Err(format!("hosts 文件不存在: {}", hosts_path));
+    if !hosts_path.exists() {
+        return Err(format!("hosts 文件不存在: {}", hosts_path.display()));
     }
 
-    match OpenOptions::new().append(true).open(hosts_path) {
+    match OpenOptions::new().append(true).open(&hosts_path) {
         Ok(_) => Ok(()),
         Err(_) => {
             if cfg!(target_os = "windows") {
@@ -241,6 +782,16 @@ mod tests {
     use super::*;
     use std::time::Instant;
 
+    #[test]
+    fn test_hosts_path_override() {
+        // Naming the system file explicitly is not an override
+        set_hosts_path(Some(system_hosts_path()));
+        assert!(!is_hosts_path_overridden());
+        assert_eq!(get_hosts_path(), system_hosts_path());
+        set_hosts_path(None);
+        assert!(!is_hosts_path_overridden());
+    }
+
     #[test]
     fn test_enable_disable_performance() {
         let start = Instant::now();
@@ -307,4 +858,169 @@ mod tests {
         clear_optimized_ips();
         assert!(!has_optimized_ips());
     }
+
+    #[test]
+    fn test_select_hosts_ips() {
+        let ranked: Vec<(String, u64)> = [
+            "2606:50c0:8001::133",
+            "185.199.109.133",
+            "185.199.108.133",
+            "2606:50c0:8000::133",
+            "185.199.110.133",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, ip)| (ip.to_string(), 10 * i as u64))
+        .collect();
+        let ips = |v: &[&str]| v.iter().map(|ip| ip.to_string()).collect::<Vec<String>>();
+
+        // One per family (the default layout)
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::PreferFastest, 1, Interleave::ByRank),
+            ips(&["2606:50c0:8001::133", "185.199.109.133"])
+        );
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::V4Only, 1, Interleave::ByRank),
+            ips(&["185.199.109.133"])
+        );
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::V6Only, 1, Interleave::ByRank),
+            ips(&["2606:50c0:8001::133"])
+        );
+
+        // Top two per family with each interleaving rule
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::PreferFastest, 2, Interleave::ByRank),
+            ips(&["2606:50c0:8001::133", "185.199.109.133", "185.199.108.133", "2606:50c0:8000::133"])
+        );
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::PreferFastest, 2, Interleave::Alternate),
+            ips(&["2606:50c0:8001::133", "185.199.109.133", "2606:50c0:8000::133", "185.199.108.133"])
+        );
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::PreferFastest, 2, Interleave::V4First),
+            ips(&["185.199.109.133", "185.199.108.133", "2606:50c0:8001::133", "2606:50c0:8000::133"])
+        );
+        assert_eq!(
+            select_hosts_ips(&ranked, FamilyPolicy::V4Only, 3, Interleave::V6First),
+            ips(&["185.199.109.133", "185.199.108.133", "185.199.110.133"])
+        );
+    }
+
+    #[test]
+    fn test_hosts_layout_top_n() {
+        let mut layout = HostsLayout {
+            default_top_n: 2,
+            ..HostsLayout::default()
+        };
+        layout.per_domain.insert("github.com".to_string(), 3);
+        layout.per_domain.insert("api.github.com".to_string(), 0);
+
+        assert_eq!(layout.top_n_for("github.com"), 3);
+        assert_eq!(layout.top_n_for("gist.github.com"), 2);
+        assert_eq!(layout.top_n_for("api.github.com"), 1);
+    }
+
+    #[test]
+    fn test_optimized_cache_roundtrip() {
+        let path = paths::data_dir().join("optimized_roundtrip.cache");
+        let mut ranked: RankedResults = HashMap::new();
+        ranked.insert(
+            "github.com".to_string(),
+            vec![("140.82.113.4".to_string(), 40), ("140.82.112.4".to_string(), 55)],
+        );
+        ranked.insert(
+            "raw.githubusercontent.com".to_string(),
+            vec![("2606:50c0:8000::133".to_string(), 30)],
+        );
+        let optimized = OptimizedIps { ranked, saved_at: 1_000_000 };
+        save_optimized_cache(&path, &optimized).unwrap();
+
+        // Fresh within the TTL, ranked order preserved
+        let loaded = load_optimized_cache(&path, 3600, 1_000_100).unwrap();
+        assert_eq!(loaded, optimized);
+
+        // Stale after the TTL, missing when the file is gone
+        assert!(load_optimized_cache(&path, 3600, 1_000_000 + 3601).is_none());
+        fs::remove_file(&path).unwrap();
+        assert!(load_optimized_cache(&path, 3600, 1_000_100).is_none());
+    }
+
+    #[test]
+    fn test_selection_skips_disallowed_ips() {
+        let entry = network::get_domain_candidates()
+            .into_iter()
+            .find(|e| e.domain == "github.com")
+            .unwrap();
+        let mut ranked: RankedResults = HashMap::new();
+        ranked.insert(
+            "github.com".to_string(),
+            vec![("203.0.113.7".to_string(), 5), ("140.82.112.4".to_string(), 40)],
+        );
+        let optimized = OptimizedIps { ranked, saved_at: paths::unix_now() };
+        let layout = HostsLayout {
+            default_top_n: 2,
+            ..HostsLayout::default()
+        };
+
+        let selected = selected_ips_for(&entry, &optimized, FamilyPolicy::V4Only, &layout);
+        assert_eq!(selected, vec!["140.82.112.4".to_string()]);
+
+        // Only disallowed IPs left: fall back to the built-in IP
+        let layout = HostsLayout::default();
+        let selected = selected_ips_for(&entry, &optimized, FamilyPolicy::V4Only, &layout);
+        assert_eq!(selected, vec!["140.82.112.4".to_string()]);
+    }
+
+    #[test]
+    fn test_selection_ignores_expired_rankings() {
+        let entry = network::get_domain_candidates()
+            .into_iter()
+            .find(|e| e.domain == "github.com")
+            .unwrap();
+        let fallback = entry.fallback_ip(FamilyPolicy::V4Only).cloned().unwrap();
+        let fastest = entry.candidate_ips.iter().find(|ip| **ip != fallback).unwrap().clone();
+        let mut ranked: RankedResults = HashMap::new();
+        ranked.insert("github.com".to_string(), vec![(fastest.clone(), 5)]);
+        let layout = HostsLayout::default();
+
+        let optimized = OptimizedIps { ranked, saved_at: paths::unix_now() };
+        assert_eq!(selected_ips_for(&entry, &optimized, FamilyPolicy::V4Only, &layout), vec![fastest]);
+
+        // Past the TTL the built-in IP is written instead
+        let expired = OptimizedIps {
+            saved_at: paths::unix_now() - OPTIMIZED_CACHE_TTL_SECS - 1,
+            ..optimized
+        };
+        assert_eq!(selected_ips_for(&entry, &expired, FamilyPolicy::V4Only, &layout), vec![fallback]);
+    }
+
+    #[test]
+    fn test_managed_ips_cover_group_wildcards() {
+        // Unprobed names fall back to the group's built-in IP
+        let wildcard = managed_ips("private-user-images.githubusercontent.com").unwrap();
+        let member = managed_ips("raw.githubusercontent.com").unwrap();
+        assert_eq!(wildcard, member);
+        assert!(managed_ips("example.com").is_none());
+    }
+
+    #[test]
+    fn test_repair_content() {
+        let block = render_block(&[("140.82.112.3".to_string(), "github.com".to_string())]);
+        let healthy = format!("127.0.0.1 localhost\n\n{}", block);
+        assert_eq!(repair_content(&healthy), healthy);
+        assert_eq!(strip_block(&healthy), "127.0.0.1 localhost");
+
+        // Duplicated block plus an unterminated one cut off by a foreign line
+        let damaged = format!(
+            "127.0.0.1 localhost\n{}\n{}{}\n140.82.112.4 github.com\n10.0.0.2 intranet\n",
+            MARKER_START, block, MARKER_START
+        );
+        let repaired = repair_content(&damaged);
+        assert_eq!(repaired.matches(MARKER_START).count(), 1);
+        assert_eq!(repaired.matches(MARKER_END).count(), 1);
+        assert!(repaired.contains("10.0.0.2 intranet"));
+        assert!(!repaired.contains("140.82.112.4"));
+        assert!(repaired.trim_end().ends_with(MARKER_END));
+    }
 }
@@ -0,0 +1,403 @@
+//! Minimal HTTP/1.1 client
+//!
+//! Used for fetching remote lists and for HTTP-level probes. A request can be
+//! pinned to a specific IP while keeping the real host name for the `Host`
+//! header and TLS SNI, which is what per-candidate testing needs.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::network;
+use crate::proxy::{self, ProxyConfig};
+
+/// Upper bound for response bodies we are willing to buffer (8 MiB)
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// Maximum number of redirects followed by `get`
+const MAX_REDIRECTS: usize = 3;
+
+/// User agent sent with every request
+const USER_AGENT: &str = "free_to_github/0.1";
+
+/// Parsed `http://` or `https://` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    /// Parse an absolute http(s) URL
+    pub fn parse(url: &str) -> io::Result<Url> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid_input(format!("unsupported URL scheme: {}", url)));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rfind(':') {
+            // Bracketed IPv6 literal without a port, e.g. [::1]
+            Some(_) if authority.ends_with(']') => (authority, default_port),
+            Some(pos) => {
+                let port = authority[pos + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| invalid_input(format!("invalid port in URL: {}", url)))?;
+                (&authority[..pos], port)
+            }
+            None => (authority, default_port),
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid_input(format!("missing host in URL: {}", url)));
+        }
+
+        Ok(Url {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// HTTP response with a fully buffered body
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Plain or TLS-wrapped connection
+pub enum HttpStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl HttpStream {
+    /// Underlying TCP connection
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            HttpStream::Plain(s) => s,
+            HttpStream::Tls(s) => s.get_ref(),
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Plain(s) => s.read(buf),
+            HttpStream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for HttpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Plain(s) => s.write(buf),
+            HttpStream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HttpStream::Plain(s) => s.flush(),
+            HttpStream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+            };
+            let config = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .expect("ring provider supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+/// Wrap an established TCP connection in TLS for `host` and finish the handshake
+pub fn wrap_tls(tcp: TcpStream, host: &str) -> io::Result<HttpStream> {
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| invalid_input(format!("invalid TLS server name {}: {}", host, e)))?;
+    let conn = ClientConnection::new(tls_config(), server_name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut stream = StreamOwned::new(conn, tcp);
+
+    // Drive the handshake now so certificate errors surface here
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+
+    Ok(HttpStream::Tls(Box::new(stream)))
+}
+
+/// Connect to `target` (an IP literal or host name), optionally wrapping the
+/// connection in TLS for `host`
+pub fn connect(
+    target: &str,
+    port: u16,
+    host: &str,
+    tls: bool,
+    timeout: Duration,
+    proxy: Option<&ProxyConfig>,
+) -> io::Result<HttpStream> {
+    let tcp = proxy::connect(target, port, proxy, timeout)?;
+    tcp.set_read_timeout(Some(timeout))?;
+    tcp.set_write_timeout(Some(timeout))?;
+
+    if tls {
+        wrap_tls(tcp, host)
+    } else {
+        Ok(HttpStream::Plain(tcp))
+    }
+}
+
+/// Send a `GET` request for `path` with the given extra headers
+pub fn send_get<W: Write>(
+    stream: &mut W,
+    host: &str,
+    path: &str,
+    extra_headers: &[(&str, &str)],
+) -> io::Result<()> {
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n",
+        path, host, USER_AGENT
+    );
+    for (name, value) in extra_headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    stream.flush()
+}
+
+/// Read the status line and headers of a response
+pub fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Vec<(String, String)>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| invalid_data(format!("malformed status line: {}", line.trim_end())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed inside response headers",
+            ));
+        }
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = trimmed.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Ok((status, headers))
+}
+
+/// Read a complete response body according to its framing headers
+pub fn read_body<R: BufRead>(reader: &mut R, headers: &[(String, String)]) -> io::Result<Vec<u8>> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+
+    let mut body = Vec::new();
+    if header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size_str = line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size_str, 16)
+                .map_err(|_| invalid_data(format!("bad chunk size: {}", size_str)))?;
+            if size == 0 {
+                break;
+            }
+            if body.len() + size > MAX_BODY_BYTES {
+                return Err(invalid_data("response body too large".to_string()));
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(len) = header("Content-Length").and_then(|v| v.parse::<usize>().ok()) {
+        if len > MAX_BODY_BYTES {
+            return Err(invalid_data("response body too large".to_string()));
+        }
+        body.resize(len, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader
+            .take(MAX_BODY_BYTES as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > MAX_BODY_BYTES {
+            return Err(invalid_data("response body too large".to_string()));
+        }
+    }
+
+    Ok(body)
+}
+
+/// Perform a `GET` against a specific address, using `url` for host and path
+pub fn get_via(
+    addr: SocketAddr,
+    url: &Url,
+    timeout: Duration,
+    proxy: Option<&ProxyConfig>,
+) -> io::Result<Response> {
+    let target = addr.ip().to_string();
+    let mut stream = connect(&target, addr.port(), &url.host, url.tls, timeout, proxy)?;
+    request(&mut stream, url)
+}
+
+fn request(stream: &mut HttpStream, url: &Url) -> io::Result<Response> {
+    send_get(stream, &url.host, &url.path, &[])?;
+
+    let mut reader = BufReader::new(stream);
+    let (status, headers) = read_head(&mut reader)?;
+    let body = read_body(&mut reader, &headers)?;
+
+    Ok(Response { status, headers, body })
+}
+
+/// Fetch a URL following a few redirects
+///
+/// Honours the proxy in the probe configuration; with a proxy, host names are
+/// resolved by the proxy, otherwise by the system resolver.
+pub fn get(url: &str, timeout: Duration) -> io::Result<Response> {
+    let proxy = network::probe_config().proxy;
+    let mut current = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let parsed = Url::parse(&current)?;
+        let mut stream = connect(
+            &parsed.host,
+            parsed.port,
+            &parsed.host,
+            parsed.tls,
+            timeout,
+            proxy.as_ref(),
+        )?;
+
+        let response = request(&mut stream, &parsed)?;
+        match (response.status, response.header("Location")) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) => {
+                current = if location.starts_with("http://") || location.starts_with("https://") {
+                    location.to_string()
+                } else {
+                    let scheme = if parsed.tls { "https" } else { "http" };
+                    format!("{}://{}:{}{}", scheme, parsed.host, parsed.port, location)
+                };
+            }
+            _ => return Ok(response),
+        }
+    }
+
+    Err(invalid_data(format!("too many redirects fetching {}", url)))
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_parse_url() {
+        let url = Url::parse("https://raw.githubusercontent.com/a/b/hosts").unwrap();
+        assert!(url.tls);
+        assert_eq!(url.host, "raw.githubusercontent.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/a/b/hosts");
+
+        let url = Url::parse("http://127.0.0.1:8080").unwrap();
+        assert!(!url.tls);
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/");
+
+        let url = Url::parse("http://[::1]:8080/x").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8080);
+
+        assert!(Url::parse("ftp://example.com/").is_err());
+    }
+
+    #[test]
+    fn test_get_chunked_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            );
+        });
+
+        let response = get(&format!("http://{}/list", addr), Duration::from_secs(2)).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello world");
+    }
+}
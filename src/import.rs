@@ -0,0 +1,219 @@
+//! Importer for community-maintained GitHub hosts lists
+//!
+//! These snapshots are plain `ip domain` lists with header comments. Parsed
+//! entries only ever enter the candidate pool: they are probed like any other
+//! candidate and are never written to the hosts file directly.
+//!
+//! Imported candidates live in memory only. Sources listed in the settings
+//! file (`import = ...`) are re-imported before each speed test, so every
+//! run probes the current version of the list.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::{http, network};
+
+/// Timeout for downloading a remote hosts list
+const FETCH_TIMEOUT_MS: u64 = 10000;
+
+// Sources re-imported before each speed test
+static IMPORT_SOURCES: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+fn get_import_sources() -> &'static Mutex<Vec<String>> {
+    IMPORT_SOURCES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Files or URLs re-imported before each speed test
+pub fn import_sources() -> Vec<String> {
+    get_import_sources().lock().unwrap().clone()
+}
+
+/// Set the files or URLs re-imported before each speed test
+pub fn set_import_sources(sources: Vec<String>) {
+    *get_import_sources().lock().unwrap() = sources;
+}
+
+/// Parse a hosts list into `(ip, domain)` pairs
+///
+/// Tolerates a UTF-8 BOM, CRLF line endings, tabs and other Unicode whitespace,
+/// full-line and trailing `#` comments, and several domains on one line.
+/// Loopback, unspecified and malformed entries are dropped.
+pub fn parse_hosts_list(text: &str) -> Vec<(String, String)> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut entries = Vec::new();
+
+    for line in text.lines() {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+
+        let mut fields = line.split(char::is_whitespace).filter(|f| !f.is_empty());
+        let ip = match fields.next().and_then(|f| f.parse::<IpAddr>().ok()) {
+            Some(ip) if !ip.is_loopback() && !ip.is_unspecified() && !ip.is_multicast() => ip,
+            _ => continue,
+        };
+
+        for field in fields {
+            let domain = field.trim_end_matches('.').to_ascii_lowercase();
+            if is_valid_domain(&domain) && !entries.contains(&(ip.to_string(), domain.clone())) {
+                entries.push((ip.to_string(), domain));
+            }
+        }
+    }
+
+    entries
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'))
+}
+
+/// Import a hosts list from text under the given source label
+///
+/// Returns the number of candidates kept for managed domains.
+pub fn import_from_str(text: &str, source: &str) -> usize {
+    let entries = parse_hosts_list(text);
+    let kept = network::add_imported_candidates(source, &entries);
+
+    #[cfg(debug_assertions)]
+    log::info!("Imported {} candidates from {} ({} entries parsed)", kept, source, entries.len());
+
+    kept
+}
+
+/// Import a hosts list from a local file
+pub fn import_from_file(path: &Path) -> io::Result<usize> {
+    let bytes = fs::read(path)?;
+    let text = String::from_utf8_lossy(&bytes);
+    Ok(import_from_str(&text, &format!("file:{}", path.display())))
+}
+
+/// Import a hosts list from an http(s) URL
+pub fn import_from_url(url: &str) -> io::Result<usize> {
+    let response = http::get(url, Duration::from_millis(FETCH_TIMEOUT_MS))?;
+    if response.status != 200 {
+        return Err(io::Error::other(format!(
+            "fetching {} returned HTTP {}",
+            url, response.status
+        )));
+    }
+
+    let text = String::from_utf8_lossy(&response.body);
+    Ok(import_from_str(&text, url))
+}
+
+/// Import from a URL or a local file path
+pub fn import(source: &str) -> io::Result<usize> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        import_from_url(source)
+    } else {
+        import_from_file(Path::new(source))
+    }
+}
+
+/// Re-import every configured source: (source, candidates kept or error)
+///
+/// A source that fails keeps the candidates of its last successful import.
+pub fn import_configured() -> Vec<(String, io::Result<usize>)> {
+    import_sources()
+        .into_iter()
+        .map(|source| {
+            let result = import(&source);
+            #[cfg(debug_assertions)]
+            if let Err(_e) = &result {
+                log::warn!("Import of {} failed: {}", source, _e);
+            }
+            (source, result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosts_list_quirks() {
+        let text = "\u{feff}# GitHub Host Start\r\n\
+                    140.82.121.3\t\tgithub.com   # updated\r\n\
+                    \r\n\
+                    185.199.110.133 raw.githubusercontent.com cloud.githubusercontent.com\n\
+                    \u{3000}140.82.121.5\u{3000}api.github.com.\n\
+                    127.0.0.1 localhost\n\
+                    0.0.0.0 github.com\n\
+                    not-an-ip github.com\n\
+                    140.82.121.3 GitHub.com\n\
+                    # Update time: 2025-01-01T00:00:00+08:00\n";
+
+        let entries = parse_hosts_list(text);
+        assert_eq!(
+            entries,
+            vec![
+                ("140.82.121.3".to_string(), "github.com".to_string()),
+                ("185.199.110.133".to_string(), "raw.githubusercontent.com".to_string()),
+                ("185.199.110.133".to_string(), "cloud.githubusercontent.com".to_string()),
+                ("140.82.121.5".to_string(), "api.github.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_tags_source() {
+        let source = "test:import";
+        let kept = import_from_str("140.82.121.4 gist.github.com\n1.1.1.1 example.org\n", source);
+        assert_eq!(kept, 1);
+
+        let gist = network::get_domain_candidates()
+            .into_iter()
+            .find(|d| d.domain == "gist.github.com")
+            .unwrap();
+        assert_eq!(
+            gist.source_of("140.82.121.4"),
+            network::CandidateSource::Imported(source.to_string())
+        );
+        // Imported IPs never become the unprobed fallback
+        assert_ne!(
+            gist.fallback_ip(network::FamilyPolicy::PreferFastest).map(String::as_str),
+            Some("140.82.121.4")
+        );
+
+        network::remove_imported_source(source);
+    }
+
+    #[test]
+    fn test_import_configured_from_settings() {
+        let path = crate::paths::data_dir().join("community_hosts.txt");
+        fs::write(&path, "140.82.121.9 codeload.github.com\n").unwrap();
+        let missing = crate::paths::data_dir().join("missing_hosts.txt");
+
+        let settings = crate::config::Settings::parse(&format!(
+            "import = {}\nimport = {}\n",
+            path.display(),
+            missing.display()
+        ))
+        .unwrap();
+        settings.apply().unwrap();
+        assert_eq!(import_sources().len(), 2);
+
+        let results = import_configured();
+        assert_eq!(results[0].1.as_ref().unwrap(), &1);
+        assert!(results[1].1.is_err());
+        let codeload = network::get_domain_candidates()
+            .into_iter()
+            .find(|d| d.domain == "codeload.github.com")
+            .unwrap();
+        assert!(codeload.candidate_ips.contains(&"140.82.121.9".to_string()));
+
+        set_import_sources(Vec::new());
+        network::remove_imported_source(&format!("file:{}", path.display()));
+    }
+}
@@ -1,6 +1,27 @@
+pub mod allowlist;
+pub mod compare;
+pub mod config;
+pub mod daemon;
+pub mod dns;
+pub mod elevate;
+pub mod gitprobe;
+pub mod helper;
+pub mod history;
 pub mod hosts;
+pub mod http;
+pub mod import;
+pub mod localproxy;
 pub mod logger;
+pub mod netprofile;
 pub mod network;
+pub mod pac;
+pub mod paths;
+pub mod proxy;
+pub mod rpc;
+pub mod service;
+pub mod sni;
+pub mod ssh;
+pub mod throughput;
 
 /// Logging macros exported for all binaries to use
 #[macro_export]
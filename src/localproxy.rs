@@ -0,0 +1,470 @@
+//! Local HTTP CONNECT proxy mode
+//!
+//! Users without admin rights cannot edit the hosts file. Instead they point
+//! clients at this proxy (`HTTPS_PROXY=http://127.0.0.1:8787`): CONNECT
+//! requests for managed domains are tunneled to the best IP from the
+//! optimized IP table (`hosts::managed_ips`), falling back to the next IP
+//! when one does not answer. Other hosts are tunneled normally or refused,
+//! depending on the policy. Every tunnel produces a connection record.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::proxy::{self, ProxyConfig};
+use crate::{hosts, logger, network};
+
+/// Default listen address
+pub const DEFAULT_BIND: &str = "127.0.0.1:8787";
+
+/// Timeout for each upstream connect attempt (milliseconds)
+const CONNECT_TIMEOUT_MS: u64 = 5000;
+
+/// Timeout for reading the client's request head (milliseconds)
+const REQUEST_TIMEOUT_MS: u64 = 10_000;
+
+/// How often the accept loop checks for a stop request (milliseconds)
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// How often results saved by other processes are picked up (seconds)
+const RELOAD_INTERVAL_SECS: u64 = 30;
+
+/// What to do with CONNECT requests for hosts this tool does not manage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmanagedPolicy {
+    /// Tunnel to the host as resolved by the system
+    #[default]
+    Passthrough,
+    /// Answer 403 so only GitHub traffic uses the proxy
+    Refuse,
+}
+
+/// Settings for the local proxy
+#[derive(Debug, Clone)]
+pub struct LocalProxyConfig {
+    pub bind: SocketAddr,
+    pub unmanaged: UnmanagedPolicy,
+    pub connect_timeout_ms: u64,
+    /// Corporate proxy outgoing tunnels go through, if any
+    pub upstream: Option<ProxyConfig>,
+}
+
+impl Default for LocalProxyConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.parse().expect("valid default bind address"),
+            unmanaged: UnmanagedPolicy::default(),
+            connect_timeout_ms: CONNECT_TIMEOUT_MS,
+            upstream: network::probe_config().proxy,
+        }
+    }
+}
+
+/// How a tunnel was routed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Managed domain, connected to this optimized IP
+    Optimized(IpAddr),
+    /// Unmanaged host, connected as resolved by the system
+    Passthrough,
+    /// Refused by policy
+    Refused,
+}
+
+/// One proxied connection, reported when it ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionRecord {
+    pub client: SocketAddr,
+    pub host: String,
+    pub port: u16,
+    pub route: Route,
+    /// Why the tunnel could not be opened, if it failed
+    pub error: Option<String>,
+    /// Time to open the upstream connection
+    pub connect_ms: u64,
+    /// Bytes sent from the client to the server
+    pub bytes_up: u64,
+    /// Bytes sent from the server to the client
+    pub bytes_down: u64,
+    pub duration_ms: u64,
+}
+
+impl std::fmt::Display for ConnectionRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let route = match &self.route {
+            Route::Optimized(ip) => format!("优选 {}", ip),
+            Route::Passthrough => "直连".to_string(),
+            Route::Refused => "已拒绝".to_string(),
+        };
+        write!(f, "{} {}:{} [{}]", self.client, self.host, self.port, route)?;
+        match &self.error {
+            Some(error) => write!(f, " 失败: {}", error),
+            None => write!(
+                f,
+                " 连接 {}ms, ↑{}B ↓{}B, 持续 {}ms",
+                self.connect_ms, self.bytes_up, self.bytes_down, self.duration_ms
+            ),
+        }
+    }
+}
+
+/// Target of a CONNECT request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectTarget {
+    /// Lowercase host name or IP literal
+    pub host: String,
+    pub port: u16,
+}
+
+/// Parse a request line; only `CONNECT host:port HTTP/1.x` is accepted
+pub fn parse_connect_line(line: &str) -> Option<ConnectTarget> {
+    let mut parts = line.split_whitespace();
+    let (method, authority, version) = (parts.next()?, parts.next()?, parts.next()?);
+    if !method.eq_ignore_ascii_case("CONNECT") || !version.starts_with("HTTP/1.") {
+        return None;
+    }
+
+    let (host, port) = authority.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some(ConnectTarget {
+        host: host.to_ascii_lowercase(),
+        port: port.parse().ok()?,
+    })
+}
+
+/// Resolver used for managed names
+pub type Resolver = dyn Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync;
+
+/// Callback receiving a record for every finished connection
+pub type ConnectionLogger = dyn Fn(&ConnectionRecord) + Send + Sync;
+
+/// Local HTTP CONNECT proxy
+pub struct LocalProxy {
+    listener: TcpListener,
+    config: LocalProxyConfig,
+    resolve: Arc<Resolver>,
+    log: Arc<ConnectionLogger>,
+    /// Reload the optimized IP cache written by other processes
+    reload_cache: bool,
+}
+
+impl LocalProxy {
+    /// Bind the listen address, routing managed names by the optimized IPs
+    pub fn bind(config: LocalProxyConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.bind)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            config,
+            resolve: Arc::new(hosts::managed_ips),
+            log: Arc::new(|_| {}),
+            reload_cache: true,
+        })
+    }
+
+    /// Use a different resolver for managed names
+    pub fn with_resolver<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync + 'static,
+    {
+        self.resolve = Arc::new(resolve);
+        self.reload_cache = false;
+        self
+    }
+
+    /// Receive a record for every finished connection
+    pub fn with_logger<F>(mut self, log: F) -> Self
+    where
+        F: Fn(&ConnectionRecord) + Send + Sync + 'static,
+    {
+        self.log = Arc::new(log);
+        self
+    }
+
+    /// Address actually bound (useful with port 0)
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept clients until `stop` is set, one thread per connection
+    pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+        let mut last_reload = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            if self.reload_cache && last_reload.elapsed() >= Duration::from_secs(RELOAD_INTERVAL_SECS) {
+                hosts::reload_optimized_ips();
+                last_reload = Instant::now();
+            }
+
+            let (client, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let config = self.config.clone();
+            let resolve = Arc::clone(&self.resolve);
+            let log = Arc::clone(&self.log);
+            thread::spawn(move || {
+                if let Some(record) = handle_client(client, peer, &config, &*resolve) {
+                    logger::log_connection_metrics(
+                        &format!("{}:{}", record.host, record.port),
+                        u128::from(record.connect_ms),
+                        record.error.is_none(),
+                    );
+                    log(&record);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Serve one client; returns `None` when it did not send a usable request
+fn handle_client(
+    client: TcpStream,
+    peer: SocketAddr,
+    config: &LocalProxyConfig,
+    resolve: &Resolver,
+) -> Option<ConnectionRecord> {
+    let start = Instant::now();
+    client.set_nonblocking(false).ok()?;
+    client
+        .set_read_timeout(Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))
+        .ok()?;
+
+    let mut reader = BufReader::new(client.try_clone().ok()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    // Skip the remaining headers (Host, Proxy-Connection, User-Agent, ...)
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut client = client;
+    let Some(target) = parse_connect_line(&request_line) else {
+        // Plain-HTTP proxying is not offered; HTTPS clients only send CONNECT
+        let _ = client.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\nContent-Length: 0\r\n\r\n");
+        return None;
+    };
+
+    let mut record = ConnectionRecord {
+        client: peer,
+        host: target.host.clone(),
+        port: target.port,
+        route: Route::Passthrough,
+        error: None,
+        connect_ms: 0,
+        bytes_up: 0,
+        bytes_down: 0,
+        duration_ms: 0,
+    };
+
+    let timeout = Duration::from_millis(config.connect_timeout_ms);
+    let connected = match resolve(&target.host) {
+        Some(ips) => connect_first(&ips, target.port, config.upstream.as_ref(), timeout).map(|(ip, stream)| {
+            record.route = Route::Optimized(ip);
+            stream
+        }),
+        None if config.unmanaged == UnmanagedPolicy::Refuse => {
+            record.route = Route::Refused;
+            let _ = client.write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+            record.error = Some("not a managed domain".to_string());
+            record.duration_ms = start.elapsed().as_millis() as u64;
+            return Some(record);
+        }
+        None => proxy::connect(&target.host, target.port, config.upstream.as_ref(), timeout),
+    };
+    record.connect_ms = start.elapsed().as_millis() as u64;
+
+    let server = match connected {
+        Ok(server) => server,
+        Err(e) => {
+            let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n");
+            record.error = Some(e.to_string());
+            record.duration_ms = start.elapsed().as_millis() as u64;
+            return Some(record);
+        }
+    };
+
+    if client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").is_err() {
+        record.error = Some("client went away".to_string());
+        return Some(record);
+    }
+
+    // Data the client sent right after the request head is already buffered
+    let buffered = reader.buffer().to_vec();
+    let (bytes_up, bytes_down) = relay(client, server, &buffered);
+    record.bytes_up = bytes_up;
+    record.bytes_down = bytes_down;
+    record.duration_ms = start.elapsed().as_millis() as u64;
+    Some(record)
+}
+
+/// Connect to the first IP that answers, in ranking order
+pub(crate) fn connect_first(
+    ips: &[IpAddr],
+    port: u16,
+    upstream: Option<&ProxyConfig>,
+    timeout: Duration,
+) -> io::Result<(IpAddr, TcpStream)> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no IP available");
+    for ip in ips {
+        match proxy::connect(&ip.to_string(), port, upstream, timeout) {
+            Ok(stream) => return Ok((*ip, stream)),
+            Err(e) => {
+                #[cfg(debug_assertions)]
+                log::warn!("Local proxy: {}:{} failed ({}), trying next IP", ip, port, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Copy data both ways until either side closes; returns (up, down) byte counts
+pub(crate) fn relay(client: TcpStream, mut server: TcpStream, buffered: &[u8]) -> (u64, u64) {
+    let _ = client.set_read_timeout(None);
+    let _ = server.set_read_timeout(None);
+    if server.write_all(buffered).is_err() {
+        return (0, 0);
+    }
+    let (Ok(mut client_read), Ok(mut server_write)) = (client.try_clone(), server.try_clone()) else {
+        return (0, 0);
+    };
+
+    let upload = thread::spawn(move || {
+        let sent = io::copy(&mut client_read, &mut server_write).unwrap_or(0);
+        let _ = server_write.shutdown(Shutdown::Write);
+        sent
+    });
+    let (mut server_read, mut client_write) = (server, client);
+    let down = io::copy(&mut server_read, &mut client_write).unwrap_or(0);
+    let _ = client_write.shutdown(Shutdown::Write);
+
+    (buffered.len() as u64 + upload.join().unwrap_or(0), down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    /// Stand-in server echoing whatever it receives
+    fn spawn_echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let mut writer = stream;
+                    let _ = io::copy(&mut reader, &mut writer);
+                });
+            }
+        });
+        port
+    }
+
+    fn spawn_proxy(unmanaged: UnmanagedPolicy) -> (SocketAddr, Arc<Mutex<Vec<ConnectionRecord>>>) {
+        let config = LocalProxyConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            unmanaged,
+            connect_timeout_ms: 1000,
+            upstream: None,
+        };
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&records);
+        // 127.0.0.2 has no listener, so the proxy must fail over to 127.0.0.1
+        let proxy = LocalProxy::bind(config)
+            .unwrap()
+            .with_resolver(|name: &str| {
+                (name == "github.com").then(|| vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()])
+            })
+            .with_logger(move |record| sink.lock().unwrap().push(record.clone()));
+        let addr = proxy.local_addr().unwrap();
+        thread::spawn(move || proxy.serve(&AtomicBool::new(false)));
+        (addr, records)
+    }
+
+    fn send_connect(proxy: SocketAddr, authority: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(proxy).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        write!(stream, "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", authority, authority).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 && line.trim_end() != "" {
+            line.clear();
+        }
+        (stream, status)
+    }
+
+    fn wait_for_records(records: &Mutex<Vec<ConnectionRecord>>) -> Vec<ConnectionRecord> {
+        for _ in 0..50 {
+            let current = records.lock().unwrap().clone();
+            if !current.is_empty() {
+                return current;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn test_parse_connect_line() {
+        let target = parse_connect_line("CONNECT GitHub.com:443 HTTP/1.1\r\n").unwrap();
+        assert_eq!(target, ConnectTarget { host: "github.com".into(), port: 443 });
+        let target = parse_connect_line("CONNECT [2001:db8::1]:443 HTTP/1.1").unwrap();
+        assert_eq!(target.host, "2001:db8::1");
+        assert!(parse_connect_line("GET http://github.com/ HTTP/1.1").is_none());
+        assert!(parse_connect_line("CONNECT github.com HTTP/1.1").is_none());
+    }
+
+    #[test]
+    fn test_tunnels_managed_domain_to_optimized_ip() {
+        let port = spawn_echo_server();
+        let (proxy, records) = spawn_proxy(UnmanagedPolicy::Refuse);
+
+        let (mut stream, status) = send_connect(proxy, &format!("github.com:{}", port));
+        assert!(status.starts_with("HTTP/1.1 200"));
+        stream.write_all(b"ping").unwrap();
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ping");
+        drop(stream);
+
+        let records = wait_for_records(&records);
+        assert_eq!(records[0].route, Route::Optimized("127.0.0.1".parse().unwrap()));
+        assert_eq!(records[0].bytes_up, 4);
+        assert!(records[0].error.is_none());
+    }
+
+    #[test]
+    fn test_unmanaged_policy() {
+        let port = spawn_echo_server();
+
+        let (proxy, records) = spawn_proxy(UnmanagedPolicy::Refuse);
+        let (_stream, status) = send_connect(proxy, &format!("127.0.0.1:{}", port));
+        assert!(status.starts_with("HTTP/1.1 403"));
+        assert_eq!(wait_for_records(&records)[0].route, Route::Refused);
+
+        let (proxy, _records) = spawn_proxy(UnmanagedPolicy::Passthrough);
+        let (_stream, status) = send_connect(proxy, &format!("127.0.0.1:{}", port));
+        assert!(status.starts_with("HTTP/1.1 200"));
+    }
+}
@@ -1,4 +1,25 @@
-use free_to_github::hosts::{enable, disable, is_enabled, check_permission};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use free_to_github::hosts::{self, is_enabled, check_permission, has_optimized_ips, allowlist_violations};
+use free_to_github::config::{self, Settings};
+use free_to_github::elevate;
+use free_to_github::helper::{self, enable_optimized, disable};
+use free_to_github::compare::{self, CompareConfig, PathReport};
+use free_to_github::daemon::{self, DaemonConfig, DaemonState};
+use free_to_github::dns::{self, DnsConfig, DnsServer};
+use free_to_github::history::{self, HistoryStore};
+use free_to_github::import;
+use free_to_github::localproxy::{LocalProxy, LocalProxyConfig, UnmanagedPolicy};
+use free_to_github::netprofile::{self, ProfileStatus};
+use free_to_github::pac::{self, PacConfig, PacServer};
+#[cfg(unix)]
+use free_to_github::rpc;
+use free_to_github::sni::{SniConfig, SniForwarder};
+use free_to_github::service::{self, ServiceKind, ServiceMode, ServiceOptions};
+use free_to_github::ssh;
+use free_to_github::network::{self, FailureSummary, ProbeKind, RankedResults};
 
 #[cfg(debug_assertions)]
 use free_to_github::logger;
@@ -6,18 +27,68 @@ use free_to_github::logger;
 #[cfg(debug_assertions)]
 use free_to_github::{info, warn, error};
 
-fn enable_cmd() -> std::io::Result<()> {
+/// Output level: 0 = quiet, 1 = normal, 2 = verbose (-v), 3 = logs on stderr (-vv)
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Which IPs `enable` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnableMode {
+    /// Cached results of the current network while fresh, defaults otherwise
+    Auto,
+    /// Run a speed test first
+    Optimized,
+    /// Built-in IPs, no speed test
+    Default,
+    /// Cached results only; fails when there are none
+    FromCache,
+}
+
+fn enable_cmd(mode: EnableMode) -> std::io::Result<()> {
     #[cfg(debug_assertions)]
-    info!("CLI: enable command initiated");
+    info!("CLI: enable command initiated ({:?})", mode);
     
-    enable()?;
-    println!("✓ GitHub 加速已启用!");
-    println!("提示: 可能需要刷新DNS缓存:");
-    if cfg!(target_os = "windows") {
-        println!("  运行命令: ipconfig /flushdns");
+    // Speed test results of the current network are used while fresh, defaults otherwise
+    netprofile::check_network_change();
+    match mode {
+        EnableMode::Optimized => {
+            run_speed_test()?;
+        }
+        EnableMode::FromCache if !has_optimized_ips() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "当前网络没有可用的测速结果 (或已过期), 请先运行 speedtest, 或改用 enable --optimized",
+            ));
+        }
+        _ => {}
+    }
+
+    let optimized = mode != EnableMode::Default && has_optimized_ips();
+    let rejected = if optimized { allowlist_violations() } else { Vec::new() };
+    if optimized {
+        enable_optimized()?;
     } else {
-        println!("  运行命令: sudo systemd-resolve --flush-caches (Linux)");
-        println!("           或 sudo dscacheutil -flushcache (macOS)");
+        helper::enable()?;
+    }
+    for violation in &rejected {
+        println!("警告: 已跳过不在 GitHub 地址空间内的 IP: {}", violation);
+    }
+    match mode {
+        EnableMode::Optimized => println!("✓ GitHub 加速已启用 (使用本次测速结果)!"),
+        _ if optimized => println!("✓ GitHub 加速已启用 (使用缓存的测速结果)!"),
+        _ => println!("✓ GitHub 加速已启用 (默认 IP)!"),
+    }
+    if verbosity() >= 1 {
+        println!("提示: 可能需要刷新DNS缓存:");
+        if cfg!(target_os = "windows") {
+            println!("  运行命令: ipconfig /flushdns");
+        } else {
+            println!("  运行命令: sudo systemd-resolve --flush-caches (Linux)");
+            println!("           或 sudo dscacheutil -flushcache (macOS)");
+        }
     }
     #[cfg(debug_assertions)]
     info!("CLI: enable command completed successfully");
@@ -25,6 +96,190 @@ fn enable_cmd() -> std::io::Result<()> {
     Ok(())
 }
 
+/// Draw a one-line progress bar on stderr
+fn draw_progress(done: usize, total: usize, domain: &str) {
+    const WIDTH: usize = 30;
+    let filled = (done * WIDTH).checked_div(total).unwrap_or(0).min(WIDTH);
+    eprint!(
+        "\r[{}{}] {:>3}/{:<3} {:<40}",
+        "#".repeat(filled),
+        ".".repeat(WIDTH - filled),
+        done,
+        total,
+        domain
+    );
+}
+
+/// Run a speed test with a progress bar and store the results
+fn run_speed_test() -> std::io::Result<RankedResults> {
+    // Community lists from the config file join the candidates of this run
+    for (source, result) in import::import_configured() {
+        match result {
+            Ok(kept) if verbosity() >= 2 => println!("已从 {} 导入 {} 个候选 IP", source, kept),
+            Ok(_) => {}
+            Err(e) => eprintln!("警告: 导入 {} 失败: {}", source, e),
+        }
+    }
+
+    let show_progress = verbosity() >= 1;
+    if show_progress {
+        eprintln!("正在测速, 请稍候...");
+    }
+    let progress: network::SharedProgressCallback = Arc::new(move |done: usize, total: usize, domain: &str| {
+        if show_progress {
+            draw_progress(done, total, domain);
+        }
+    });
+    let results = network::test_all_domains_ranked(Some(progress));
+    if show_progress {
+        eprintln!();
+    }
+
+    let failures = network::last_failure_summary();
+    if results.values().all(|ranked| ranked.is_empty()) {
+        let mut message = "所有域名测速均失败".to_string();
+        if failures.failed() > 0 {
+            message = format!("{} ({})", message, failures.describe());
+        }
+        if failures.local_network_down() {
+            message.push_str(", 本地网络似乎不可用");
+        }
+        return Err(std::io::Error::other(message));
+    }
+    if failures.failed() > 0 && verbosity() >= 1 {
+        println!("{}/{} 次探测失败 ({})", failures.failed(), failures.probes, failures.describe());
+    }
+
+    hosts::set_ranked_ips(results.clone());
+    Ok(results)
+}
+
+/// Print results, fastest domain first; all ranked IPs in verbose mode
+fn print_results(results: &RankedResults) {
+    let mut rows: Vec<(&String, &Vec<(String, u64)>)> =
+        results.iter().filter(|(_, ranked)| !ranked.is_empty()).collect();
+    rows.sort_by_key(|(domain, ranked)| (ranked[0].1, domain.as_str()));
+
+    // Verbose output adds each IP's record over recent speed tests
+    let trends = if verbosity() >= 2 {
+        HistoryStore::open_default()
+            .trends_by_domain_ip(history::DEFAULT_TREND_WINDOW)
+            .unwrap_or_default()
+    } else {
+        Default::default()
+    };
+
+    println!("{:<36} {:<40} {:>8}  质量", "域名", "IP", "延迟");
+    for (domain, ranked) in rows {
+        let shown = if verbosity() >= 2 { ranked.len() } else { 1 };
+        for (i, (ip, latency)) in ranked.iter().take(shown).enumerate() {
+            let name = if i == 0 { domain.as_str() } else { "" };
+            let trend = match trends.get(&(domain.clone(), ip.clone())) {
+                Some(trend) if trend.samples > 1 => format!(
+                    "  近 {} 次: 成功 {:.0}%, 中位 {}",
+                    trend.samples,
+                    trend.success_rate * 100.0,
+                    trend.rolling_median_ms.map(|m| format!("{}ms", m)).unwrap_or_else(|| "-".to_string())
+                ),
+                _ => String::new(),
+            };
+            println!(
+                "{:<36} {:<40} {:>6}ms  {}{}",
+                name,
+                ip,
+                latency,
+                network::get_quality_rating(*latency),
+                trend
+            );
+        }
+    }
+}
+
+fn speedtest_cmd() -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: speedtest command initiated");
+
+    let results = run_speed_test()?;
+    print_results(&results);
+    if verbosity() >= 1 {
+        println!();
+        println!("提示: 运行 enable --from-cache 使用这些结果");
+    }
+    Ok(())
+}
+
+fn import_cmd(sources: &[String]) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: import command initiated");
+
+    let mut total = 0;
+    for source in sources {
+        let kept = import::import(source)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", source, e)))?;
+        println!("✓ 已从 {} 导入 {} 个 GitHub 候选 IP", source, kept);
+        total += kept;
+    }
+    if total == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "列表中没有加速域名的可用条目",
+        ));
+    }
+
+    // Imported IPs are only candidates: probe them together with the built-in ones
+    let results = run_speed_test()?;
+    print_results(&results);
+    if verbosity() >= 1 {
+        println!();
+        println!("提示: 导入只对本次测速有效; 在配置文件中添加 import = <文件或网址> 可在每次测速前自动导入");
+    }
+    Ok(())
+}
+
+fn results_cmd() -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: results command initiated");
+
+    let results = hosts::ranked_ips();
+    let Some(saved_at) = hosts::optimized_ips_saved_at() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "暂无测速结果, 请先运行 speedtest",
+        ));
+    };
+
+    let age = free_to_github::paths::unix_now().saturating_sub(saved_at);
+    let freshness = if has_optimized_ips() { "" } else { ", 已过期" };
+    println!("测速时间: {} 分钟前{}", age / 60, freshness);
+    println!();
+    print_results(&results);
+    Ok(())
+}
+
+fn domains_cmd() {
+    #[cfg(debug_assertions)]
+    info!("CLI: domains command initiated");
+
+    let mut entries = network::get_domain_candidates();
+    entries.sort_by(|a, b| a.domain.cmp(&b.domain));
+    println!("{:<36} {:<18} {:>6}", "域名", "分组", "候选 IP");
+    for entry in &entries {
+        println!(
+            "{:<36} {:<18} {:>6}",
+            entry.domain,
+            entry.group.as_deref().unwrap_or("-"),
+            entry.candidate_ips.len()
+        );
+        if verbosity() >= 2 {
+            for ip in &entry.candidate_ips {
+                println!("    {}", ip);
+            }
+        }
+    }
+    println!();
+    println!("共 {} 个域名", entries.len());
+}
+
 fn disable_cmd() -> std::io::Result<()> {
     #[cfg(debug_assertions)]
     info!("CLI: disable command initiated");
@@ -38,7 +293,7 @@ fn disable_cmd() -> std::io::Result<()> {
     Ok(())
 }
 
-fn status() -> std::io::Result<()> {
+fn status(verbose: bool, config_path: &std::path::Path) -> std::io::Result<()> {
     #[cfg(debug_assertions)]
     info!("CLI: status command initiated");
     
@@ -51,9 +306,444 @@ fn status() -> std::io::Result<()> {
         #[cfg(debug_assertions)]
         info!("CLI: Status check returned: disabled");
     }
+
+    let network = netprofile::current_network_id();
+    match netprofile::profile_status(&network) {
+        ProfileStatus::Applied { .. } => println!("网络: {} (有可用的测速结果)", network),
+        ProfileStatus::Stale { .. } => println!("网络: {} (测速结果已过期)", network),
+        ProfileStatus::NoData { .. } => println!("网络: {} (暂无测速数据)", network),
+        ProfileStatus::Unknown => println!("网络: 未知"),
+    }
+
+    // Failure reasons of the last speed test, from the history store
+    if let Ok(last_run) = HistoryStore::open_default().last_run() {
+        let summary = FailureSummary::from_outcomes(
+            last_run.iter().filter(|m| m.kind == ProbeKind::TcpConnect).map(|m| m.outcome),
+        );
+        if summary.failed() > 0 {
+            println!("上次测速: {}/{} 次探测失败 ({})", summary.failed(), summary.probes, summary.describe());
+            if summary.local_network_down() {
+                println!("  本地网络似乎不可用, 请检查网络连接");
+            }
+        }
+    }
+
+    // The system service keeps its status in its own state directory
+    let system_status = std::path::Path::new(service::SYSTEM_STATE_DIR).join(daemon::STATUS_FILE);
+    let daemon_status = daemon::load_status().or_else(|| daemon::load_status_from(&system_status));
+    if let Some(status) = daemon_status.filter(|s| s.state != DaemonState::Stopped || s.last_run.is_some()) {
+        let now = free_to_github::paths::unix_now();
+        let ago = |t: u64| format!("{} 秒前", now.saturating_sub(t));
+        print!("后台服务: {}", status.state.label());
+        // A next run long in the past means the daemon died without cleaning up
+        if status.next_run.is_some_and(|next| now > next + 300) {
+            print!(" (可能已停止)");
+        }
+        println!();
+        if let Some(last) = status.last_run {
+            println!("  上次运行: {} ({})", ago(last), status.last_outcome.as_deref().unwrap_or("-"));
+        }
+        if let Some(next) = status.next_run.filter(|next| *next >= now) {
+            println!("  下次运行: {} 秒后", next - now);
+        }
+    }
+
+    if verbose {
+        println!();
+        println!("hosts 文件: {}", hosts::get_hosts_path().display());
+        println!("数据目录: {}", free_to_github::paths::data_dir().display());
+        let loaded = if config_path.exists() { "" } else { " (不存在, 使用默认设置)" };
+        println!("配置文件: {}{}", config_path.display(), loaded);
+        match helper::check_write_access() {
+            Ok(()) => println!("写入权限: 有"),
+            Err(_) => println!("写入权限: 无 (需要管理员权限, 特权助手或 --elevate)"),
+        }
+        match hosts::optimized_ips_saved_at() {
+            Some(saved_at) => println!(
+                "测速结果: {} 分钟前{}",
+                free_to_github::paths::unix_now().saturating_sub(saved_at) / 60,
+                if has_optimized_ips() { "" } else { " (已过期)" }
+            ),
+            None => println!("测速结果: 无"),
+        }
+        let entries = hosts::managed_entries()?;
+        if !entries.is_empty() {
+            println!("当前写入的条目:");
+            for (ip, domain) in entries {
+                println!("  {:<40} {}", ip, domain);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compare_cmd() {
+    #[cfg(debug_assertions)]
+    info!("CLI: compare command initiated");
+
+    println!("正在对比系统 DNS 与优选 IP, 请稍候...");
+    println!();
+    println!("{:<32} {:<18} {:>8} {:>6} {:<18} {:>8} {:>6}  结论", "域名", "系统 DNS", "延迟", "丢包", "优选 IP", "延迟", "丢包");
+
+    let format_latency = |path: &PathReport| match path.latency_ms {
+        Some(latency) if path.tls_valid != Some(false) => format!("{}ms", latency),
+        Some(_) => "证书无效".to_string(),
+        None => path.failure.map(|f| f.label()).unwrap_or("-").to_string(),
+    };
+
+    for report in compare::compare_all(&CompareConfig::default()) {
+        println!(
+            "{:<32} {:<18} {:>8} {:>5.0}% {:<18} {:>8} {:>5.0}%  {}",
+            report.domain,
+            report.system.ip.as_deref().unwrap_or("-"),
+            format_latency(&report.system),
+            report.system.loss * 100.0,
+            report.optimized.ip.as_deref().unwrap_or("-"),
+            format_latency(&report.optimized),
+            report.optimized.loss * 100.0,
+            report.verdict.label()
+        );
+    }
+}
+
+fn ssh_cmd(remove: bool) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: ssh command initiated (remove: {})", remove);
+
+    let path = ssh::ssh_config_path();
+    if remove {
+        ssh::remove_ssh_config(&path)?;
+        println!("✓ 已从 {} 移除 SSH 配置", path.display());
+        return Ok(());
+    }
+
+    println!("正在探测 SSH 端口...");
+    let report = ssh::probe_routes();
+    for (name, probe) in [("github.com:22", &report.direct), ("ssh.github.com:443", &report.over_443)] {
+        match probe.latency_ms() {
+            Some(latency) => println!("  {:<20} {}ms", name, latency),
+            None => println!("  {:<20} 不可用 ({})", name, probe.outcome.label()),
+        }
+    }
+
+    ssh::apply_ssh_config(&path, report.route)?;
+    match report.route {
+        ssh::SshRoute::Port443 => println!("✓ 已在 {} 中配置 github.com 走 ssh.github.com:443", path.display()),
+        ssh::SshRoute::Direct => println!("✓ github.com:22 可用, 无需修改 SSH 配置"),
+        ssh::SshRoute::Unknown => {
+            eprintln!("✗ 两个 SSH 端口均不可用, 未修改 {}", path.display());
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn dns_cmd(bind: Option<&String>, upstreams: Option<&String>) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: dns command initiated");
+
+    let mut config = DnsConfig::default();
+    if let Some(bind) = bind {
+        config.bind = dns::parse_bind_addr(bind)?;
+    }
+    if let Some(upstreams) = upstreams {
+        config.upstreams = upstreams.split(',').map(dns::parse_upstream).collect::<std::io::Result<_>>()?;
+    }
+
+    let upstream_list: Vec<String> = config.upstreams.iter().map(|u| u.to_string()).collect();
+    let server = DnsServer::bind(config)?;
+    println!("✓ 本地 DNS 已启动: {}", server.local_addr()?);
+    println!("  GitHub 域名使用优选 IP, 其他域名转发至 {}", upstream_list.join(", "));
+    println!("  将系统或应用的 DNS 指向该地址即可生效, 按 Ctrl+C 退出");
+    if !server.local_addr()?.ip().is_loopback() {
+        eprintln!("⚠ 警告: 监听在非本机地址, 局域网内任何人都能通过它解析任意域名");
+        eprintln!("  仅本机使用时请监听 127.0.0.1, 并确认防火墙已限制来源");
+    }
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    server.serve(&stop)
+}
+
+fn proxy_cmd(args: &[String]) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: proxy command initiated");
+
+    let mut config = LocalProxyConfig::default();
+    for arg in args {
+        if arg == "--refuse" {
+            config.unmanaged = UnmanagedPolicy::Refuse;
+        } else {
+            config.bind = dns::parse_bind_addr(arg)?;
+        }
+    }
+
+    let proxy = LocalProxy::bind(config.clone())?.with_logger(|record| println!("{}", record));
+    let addr = proxy.local_addr()?;
+    println!("✓ 本地代理已启动: {} (无需管理员权限)", addr);
+    println!("  设置 HTTPS_PROXY=http://{} 后, GitHub 连接将使用优选 IP", addr);
+    if config.unmanaged == UnmanagedPolicy::Refuse {
+        println!("  非 GitHub 域名的连接将被拒绝");
+    }
+    println!("  按 Ctrl+C 退出");
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    proxy.serve(&stop)
+}
+
+fn sni_cmd(bind: Option<&String>) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: sni command initiated");
+
+    let mut config = SniConfig::default();
+    if let Some(bind) = bind {
+        config.bind = dns::parse_bind_addr(bind)?;
+    }
+
+    let forwarder = SniForwarder::bind(config)?.with_logger(|record| println!("{}", record));
+    println!("✓ SNI 转发已启动: {}", forwarder.local_addr()?);
+    println!("  将 GitHub 域名解析到该地址 (或通过防火墙重定向 443 端口) 即可生效");
+    println!("  连接失败时自动尝试下一个优选 IP, 按 Ctrl+C 退出");
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    forwarder.serve(&stop)
+}
+
+fn pac_cmd(args: &[String]) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: pac command initiated");
+
+    let mut config = PacConfig::default();
+    let mut serve = false;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--serve" {
+            serve = true;
+            if let Some(bind) = rest.as_slice().first().filter(|a| !a.starts_with("--")) {
+                config.bind = dns::parse_bind_addr(bind)?;
+                rest.next();
+            }
+        } else {
+            config.directive = pac::proxy_directive(arg)?;
+        }
+    }
+
+    if !serve {
+        print!("{}", pac::current_pac(&config.directive));
+        return Ok(());
+    }
+
+    let server = PacServer::bind(config)?;
+    println!("✓ PAC 服务已启动: {}", server.url()?);
+    println!("  在浏览器或系统代理设置中填入该地址作为自动配置脚本, 按 Ctrl+C 退出");
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    server.serve(&stop)
+}
+
+/// Serve the JSON-RPC control interface next to the daemon
+#[cfg(unix)]
+fn start_rpc_server(allowed_uids: Vec<u32>, allowed_gids: Vec<u32>) {
+    let config = rpc::RpcConfig {
+        allowed_uids,
+        allowed_gids,
+        ..rpc::RpcConfig::default()
+    };
+    match rpc::RpcServer::bind(config) {
+        Ok(server) => {
+            println!("  控制接口: {}", server.socket().display());
+            std::thread::spawn(move || {
+                let stop = std::sync::atomic::AtomicBool::new(false);
+                server.serve(&stop)
+            });
+        }
+        Err(e) => eprintln!("警告: 控制接口启动失败: {}", e),
+    }
+}
+
+#[cfg(not(unix))]
+fn start_rpc_server(_allowed_uids: Vec<u32>, _allowed_gids: Vec<u32>) {}
+
+/// Arguments of the daemon command
+#[derive(Debug, Default, PartialEq)]
+struct DaemonArgs {
+    once: bool,
+    interval_minutes: Option<u64>,
+    rpc_allowed_uids: Vec<u32>,
+    rpc_allowed_gids: Vec<u32>,
+}
+
+fn parse_daemon_args(args: &[String]) -> Result<DaemonArgs, String> {
+    let mut parsed = DaemonArgs::default();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let mut id = |what: &str| -> Result<u32, String> {
+            let value = rest.next().ok_or_else(|| format!("{} 需要一个{}", arg, what))?;
+            value.parse().map_err(|_| format!("无效的 {}: {}", what, value))
+        };
+        match arg.as_str() {
+            "--once" => parsed.once = true,
+            "--rpc-allow-uid" => parsed.rpc_allowed_uids.push(id("UID")?),
+            "--rpc-allow-gid" => parsed.rpc_allowed_gids.push(id("GID")?),
+            flag if flag.starts_with('-') => {
+                return Err(format!("daemon 不支持选项 {} (可用: --once, --rpc-allow-uid, --rpc-allow-gid)", flag))
+            }
+            minutes if parsed.interval_minutes.is_none() => {
+                let minutes = minutes.parse().ok().filter(|m| *m > 0);
+                parsed.interval_minutes = Some(minutes.ok_or_else(|| format!("无效的间隔分钟数: {}", arg))?);
+            }
+            extra => return Err(format!("daemon 的参数过多: {}", extra)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn daemon_cmd(args: DaemonArgs) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: daemon command initiated");
+
+    let mut config = DaemonConfig::default();
+    if args.once {
+        let outcome = daemon::run_once(&config)?;
+        println!("✓ {}", outcome.describe());
+        return Ok(());
+    }
+    if let Some(minutes) = args.interval_minutes {
+        config.interval_secs = minutes * 60;
+    }
+
+    println!("✓ 后台服务已启动, 每 {} 分钟测速一次", config.interval_secs / 60);
+    println!(
+        "  新 IP 连续 {} 轮快 {}% 以上才会更新 hosts, 按 Ctrl+C 退出",
+        config.rounds,
+        (config.margin * 100.0) as u32
+    );
+    start_rpc_server(args.rpc_allowed_uids, args.rpc_allowed_gids);
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    daemon::run(&config, &stop);
+    Ok(())
+}
+
+fn service_cmd(args: &[String]) -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: service command initiated");
+
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+    let action = args.first().map(String::as_str).unwrap_or("status");
+    let mut modes = Vec::new();
+    let mut kind = ServiceKind::Daemon;
+    let mut root = None;
+    let mut daemon_args = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--timer" => kind = ServiceKind::Timer,
+            "--rpc-allow-uid" | "--rpc-allow-gid" => {
+                let id = rest.next().filter(|v| v.parse::<u32>().is_ok());
+                let id = id.ok_or_else(|| invalid(format!("{} 需要一个数字 ID", arg)))?;
+                daemon_args.extend([arg.clone(), id.clone()]);
+            }
+            "--root" => root = Some(rest.next().ok_or_else(|| invalid("--root 需要一个目录".to_string()))?),
+            mode => modes.push(ServiceMode::parse(mode).ok_or_else(|| {
+                invalid(format!("未知模式: {} (可选 hosts, proxy, dns)", mode))
+            })?),
+        }
+    }
+
+    // Let the user who installs the service through sudo reach its control socket
+    if daemon_args.is_empty() {
+        if let Some(uid) = std::env::var("SUDO_UID").ok().filter(|v| v.parse::<u32>().is_ok()) {
+            daemon_args = vec!["--rpc-allow-uid".to_string(), uid];
+        }
+    }
+
+    let options_for = |mode: ServiceMode| {
+        let mut options = ServiceOptions::new(mode);
+        options.kind = kind;
+        options.daemon_args = daemon_args.clone();
+        if let Some(root) = root {
+            options.root = root.into();
+        }
+        options
+    };
+
+    match action {
+        "install" => {
+            if modes.len() > 1 {
+                return Err(invalid("install 一次只能安装一种模式".to_string()));
+            }
+            let mode = modes.first().copied().unwrap_or(ServiceMode::Hosts);
+            let options = options_for(mode);
+            if !mode.is_user_unit() && options.root == std::path::Path::new("/") {
+                check_permission_exit();
+            }
+            for path in service::install(&options)? {
+                println!("✓ 已写入 {}", path.display());
+            }
+            if options.root == std::path::Path::new("/") {
+                println!("✓ 服务已启用并启动");
+            }
+        }
+        "uninstall" => {
+            if modes.is_empty() {
+                modes = ServiceMode::ALL.to_vec();
+            }
+            for mode in modes {
+                for path in service::uninstall(&options_for(mode))? {
+                    println!("✓ 已删除 {}", path.display());
+                }
+            }
+        }
+        "status" => {
+            if modes.is_empty() {
+                modes = ServiceMode::ALL.to_vec();
+            }
+            for mode in modes {
+                let units = service::status(&options_for(mode));
+                if units.is_empty() {
+                    println!("{:<6} 未安装", mode.as_str());
+                }
+                for unit in units {
+                    let active = unit.active.as_deref().unwrap_or("-");
+                    println!("{:<6} {} ({}) {}", mode.as_str(), unit.name, active, unit.path.display());
+                }
+            }
+        }
+        other => return Err(invalid(format!("未知操作: {} (可选 install, uninstall, status)", other))),
+    }
     Ok(())
 }
 
+fn repair_cmd() -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: repair command initiated");
+
+    helper::repair()?;
+    println!("✓ hosts 文件中的加速配置已修复");
+    Ok(())
+}
+
+fn restore_cmd() -> std::io::Result<()> {
+    #[cfg(debug_assertions)]
+    info!("CLI: restore command initiated");
+
+    helper::restore()?;
+    println!("✓ hosts 文件已恢复为首次修改前的备份");
+    Ok(())
+}
+
+/// Exit unless hosts can be written, directly or through the privileged helper
+fn check_write_access_exit() {
+    if let Err(msg) = helper::check_write_access() {
+        eprintln!("错误: {}", msg);
+        if cfg!(target_os = "linux") {
+            eprintln!("提示: 可加 --elevate 通过 pkexec/sudo -A 授权, 或先以 root 身份启动 free_to_github_helper");
+        } else {
+            eprintln!("提示: 也可以先以 root 身份启动 free_to_github_helper");
+        }
+        std::process::exit(1);
+    }
+}
+
 fn check_permission_exit() {
     if let Err(msg) = check_permission() {
         eprintln!("错误: {}", msg);
@@ -61,42 +751,258 @@ fn check_permission_exit() {
     }
 }
 
+/// Every subcommand, for usage errors and suggestions
+const COMMANDS: &[&str] = &[
+    "speedtest", "import", "enable", "disable", "results", "domains", "status", "repair", "restore", "compare",
+    "ssh", "dns", "proxy", "sni", "pac", "daemon", "service", "help",
+];
+
 fn print_help() {
     println!("Free to GitHub - 本地 GitHub 访问加速工具");
     println!();
     println!("用法:");
-    println!("  free_to_github [命令]");
+    println!("  free_to_github_cli [全局选项] <命令> [参数]");
     println!();
     println!("命令:");
-    println!("  enable   启用 GitHub 加速");
-    println!("  disable  禁用 GitHub 加速");
-    println!("  status   查看当前状态");
-    println!("  help     显示帮助信息");
+    println!("  speedtest  测试所有 GitHub 域名的候选 IP 并保存结果");
+    println!("  import     导入社区维护的 hosts 列表作为候选 IP 并测速 (import <文件或网址>...)");
+    println!("  enable     启用 GitHub 加速 (enable [--optimized|--default|--from-cache])");
+    println!("               --optimized   先测速, 再使用本次结果");
+    println!("               --default     使用内置 IP, 不测速");
+    println!("               --from-cache  只使用已保存的测速结果, 没有则报错");
+    println!("               不加选项时有新鲜的测速结果就使用, 否则使用内置 IP");
+    println!("  disable    禁用 GitHub 加速");
+    println!("  results    查看已保存的测速结果 (-v 显示全部候选 IP 及近期成功率)");
+    println!("  domains    列出加速的域名及分组 (-v 显示候选 IP)");
+    println!("  status     查看当前状态 (status --verbose 显示详细信息)");
+    println!("  repair     修复损坏或重复的加速配置块");
+    println!("  restore    恢复首次修改前备份的 hosts 文件");
+    println!("  compare    对比系统 DNS 与优选 IP 的连接质量");
+    println!("  ssh        探测 SSH 端口并按需配置 ~/.ssh/config (ssh off 移除)");
+    println!("  dns        运行本地 DNS 服务代替修改 hosts (dns [监听地址] [上游,上游]; :53 只监听本机)");
+    println!("  proxy      运行本地 HTTPS 代理, 无需管理员权限 (proxy [监听地址] [--refuse])");
+    println!("  sni        运行按 SNI 转发的透明 TCP 转发器 (sni [监听地址])");
+    println!("  daemon     后台定期测速, 明显更快时自动更新 (daemon [间隔分钟] | daemon --once)");
+    println!("               --rpc-allow-uid/--rpc-allow-gid <ID>  允许其他用户/组连接控制接口 (可重复)");
+    println!("  service    注册为 systemd 服务 (service install|uninstall|status [hosts|proxy|dns] [--timer] [--root 目录])");
+    println!("               install 可加 --rpc-allow-uid/--rpc-allow-gid, 经 sudo 安装时自动允许调用者");
+    println!("  pac        输出 PAC 脚本, 或以 --serve [监听地址] 在本地提供 (pac [代理地址])");
+    println!("  help       显示帮助信息");
     println!();
-    println!("注意: 需要管理员/root 权限运行");
+    println!("全局选项:");
+    println!("  --config <文件>  配置文件 (默认 {})", config::default_config_path().display());
+    println!("  --hosts <文件>   要修改的 hosts 文件 (默认系统 hosts)");
+    println!("  -v, --verbose    输出更多信息, -vv 同时在终端输出日志");
+    println!("  -q, --quiet      只输出结果和错误");
+    println!("  --elevate        没有权限时通过 pkexec 或 sudo -A 只提权写入 hosts 这一步 (Linux)");
+    println!();
+    println!("注意: enable/disable/repair/restore 需要管理员/root 权限运行, 或由已启动的");
+    println!("      free_to_github_helper 代为写入, 或加 --elevate; dns 高端口与 proxy 无需");
 }
 
-fn main() {
-    // Initialize file logger for debugging (debug builds only)
+/// Options accepted before or after the subcommand
+#[derive(Debug, Default)]
+struct GlobalOptions {
+    config: Option<PathBuf>,
+    hosts: Option<PathBuf>,
+    verbose: u8,
+    quiet: bool,
+    elevate: bool,
+}
+
+/// Take the global options out of `args`, leaving the command and its arguments
+fn parse_global_options(args: Vec<String>) -> Result<(GlobalOptions, Vec<String>), String> {
+    let mut options = GlobalOptions::default();
+    let mut rest = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |what: &str| -> Result<PathBuf, String> {
+            inline
+                .clone()
+                .or_else(|| iter.next())
+                .filter(|v| !v.is_empty() && !v.starts_with('-'))
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} 需要一个{}", flag, what))
+        };
+        match flag.as_str() {
+            "--config" => options.config = Some(value("配置文件路径")?),
+            "--hosts" => options.hosts = Some(value("hosts 文件路径")?),
+            "-v" | "--verbose" => options.verbose += 1,
+            "-vv" => options.verbose += 2,
+            "-q" | "--quiet" => options.quiet = true,
+            "--elevate" => options.elevate = true,
+            _ => rest.push(arg),
+        }
+    }
+    if options.quiet && options.verbose > 0 {
+        return Err("--quiet 不能与 --verbose 同时使用".to_string());
+    }
+    Ok((options, rest))
+}
+
+/// Edit distance, for "did you mean" suggestions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Closest known command to a mistyped one, if any is close enough
+fn suggest_command(command: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|name| (edit_distance(command, name), *name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Reject unknown flags and surplus arguments of a command
+fn check_args(command: &str, args: &[String], max_positional: usize, flags: &[&str]) -> Result<(), String> {
+    let mut positional = 0;
+    for arg in args {
+        if arg.starts_with('-') {
+            if !flags.contains(&arg.as_str()) {
+                return Err(if flags.is_empty() {
+                    format!("{} 不支持选项 {}", command, arg)
+                } else {
+                    format!("{} 不支持选项 {} (可用: {})", command, arg, flags.join(", "))
+                });
+            }
+        } else {
+            positional += 1;
+            if positional > max_positional {
+                return Err(format!("{} 的参数过多: {}", command, arg));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn usage_error(message: &str) -> ! {
     #[cfg(debug_assertions)]
-    {
+    warn!("CLI: usage error: {}", message);
+    eprintln!("错误: {}", message);
+    eprintln!("运行 free_to_github_cli help 查看用法");
+    std::process::exit(2);
+}
+
+fn enable_mode(args: &[String]) -> Result<EnableMode, String> {
+    check_args("enable", args, 0, &["--optimized", "--default", "--from-cache"])?;
+    let mut mode = EnableMode::Auto;
+    for arg in args {
+        let next = match arg.as_str() {
+            "--optimized" => EnableMode::Optimized,
+            "--default" => EnableMode::Default,
+            _ => EnableMode::FromCache,
+        };
+        if mode != EnableMode::Auto && mode != next {
+            return Err("--optimized, --default 和 --from-cache 只能选一个".to_string());
+        }
+        mode = next;
+    }
+    Ok(mode)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // Re-launched through pkexec/sudo by an unprivileged parent: only write hosts
+    if args.first().map(String::as_str) == Some(elevate::ELEVATED_ARG) {
+        #[cfg(debug_assertions)]
+        let _ = logger::FileLogger::init();
+        if let Err(e) = elevate::handle_elevated_request() {
+            #[cfg(debug_assertions)]
+            error!("CLI: elevated hosts write failed: {}", e);
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (options, args) = parse_global_options(args).unwrap_or_else(|e| usage_error(&e));
+    let level = if options.quiet { 0 } else { 1 + options.verbose.min(2) };
+    VERBOSITY.store(level, Ordering::Relaxed);
+
+    if level >= 3 {
+        env_logger::Builder::new().filter_level(log::LevelFilter::Debug).init();
+    } else {
+        // Initialize file logger for debugging (debug builds only)
+        #[cfg(debug_assertions)]
         let _ = logger::FileLogger::init();
-        info!("CLI application started");
     }
-    
-    let args: Vec<String> = std::env::args().collect();
-    
-    if args.len() < 2 {
+    #[cfg(debug_assertions)]
+    info!("CLI application started");
+
+    // Settings file first, so --hosts on the command line wins over it
+    let config_path = options.config.clone().unwrap_or_else(config::default_config_path);
+    let settings = match &options.config {
+        Some(path) => Settings::load(path),
+        None => Settings::load_default(),
+    };
+    if let Err(e) = settings.and_then(|settings| settings.apply()) {
+        eprintln!("错误: 配置文件无效: {}", e);
+        std::process::exit(2);
+    }
+    if let Some(path) = options.hosts {
+        hosts::set_hosts_path(Some(path));
+    }
+    // Allow the hosts-writing step to be re-launched elevated
+    if options.elevate {
+        elevate::set_auto_elevate(true);
+    }
+
+    let Some(command) = args.first() else {
         print_help();
         return;
-    }
+    };
+    let rest = &args[1..];
 
-    let command = &args[1];
+    let checked = |max_positional: usize, flags: &[&str]| {
+        if let Err(e) = check_args(command, rest, max_positional, flags) {
+            usage_error(&e);
+        }
+    };
 
     match command.as_str() {
+        "speedtest" => {
+            checked(0, &[]);
+            if let Err(e) = speedtest_cmd() {
+                #[cfg(debug_assertions)]
+                error!("CLI: speedtest command failed: {}", e);
+                eprintln!("测速失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "import" => {
+            checked(usize::MAX, &[]);
+            if rest.is_empty() {
+                usage_error("import 需要至少一个文件或网址");
+            }
+            if let Err(e) = import_cmd(rest) {
+                #[cfg(debug_assertions)]
+                error!("CLI: import command failed: {}", e);
+                eprintln!("导入失败: {}", e);
+                std::process::exit(1);
+            }
+        }
         "enable" => {
-            check_permission_exit();
-            if let Err(e) = enable_cmd() {
+            let mode = enable_mode(rest).unwrap_or_else(|e| usage_error(&e));
+            check_write_access_exit();
+            if let Err(e) = enable_cmd(mode) {
                 #[cfg(debug_assertions)]
                 error!("CLI: enable command failed: {}", e);
                 eprintln!("启用失败: {}", e);
@@ -104,7 +1010,8 @@ fn main() {
             }
         }
         "disable" => {
-            check_permission_exit();
+            checked(0, &[]);
+            check_write_access_exit();
             if let Err(e) = disable_cmd() {
                 #[cfg(debug_assertions)]
                 error!("CLI: disable command failed: {}", e);
@@ -112,24 +1019,216 @@ fn main() {
                 std::process::exit(1);
             }
         }
+        "results" => {
+            checked(0, &[]);
+            if let Err(e) = results_cmd() {
+                #[cfg(debug_assertions)]
+                error!("CLI: results command failed: {}", e);
+                eprintln!("查看测速结果失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "domains" => {
+            checked(0, &[]);
+            domains_cmd();
+        }
+        "repair" => {
+            checked(0, &[]);
+            check_write_access_exit();
+            if let Err(e) = repair_cmd() {
+                #[cfg(debug_assertions)]
+                error!("CLI: repair command failed: {}", e);
+                eprintln!("修复失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "restore" => {
+            checked(0, &[]);
+            check_write_access_exit();
+            if let Err(e) = restore_cmd() {
+                #[cfg(debug_assertions)]
+                error!("CLI: restore command failed: {}", e);
+                eprintln!("恢复失败: {}", e);
+                std::process::exit(1);
+            }
+        }
         "status" => {
-            if let Err(e) = status() {
+            // --verbose is taken as a global option, so it shows up as verbosity
+            checked(0, &[]);
+            if let Err(e) = status(verbosity() >= 2, &config_path) {
                 #[cfg(debug_assertions)]
                 error!("CLI: status command failed: {}", e);
                 eprintln!("查询状态失败: {}", e);
                 std::process::exit(1);
             }
         }
+        "compare" => {
+            checked(0, &[]);
+            compare_cmd();
+        }
+        "ssh" => {
+            checked(1, &[]);
+            let remove = match rest.first().map(String::as_str) {
+                None => false,
+                Some("off") => true,
+                Some(other) => usage_error(&format!("ssh 只接受 off, 而不是 {}", other)),
+            };
+            if let Err(e) = ssh_cmd(remove) {
+                #[cfg(debug_assertions)]
+                error!("CLI: ssh command failed: {}", e);
+                eprintln!("配置 SSH 失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "dns" => {
+            checked(2, &[]);
+            if let Err(e) = dns_cmd(rest.first(), rest.get(1)) {
+                #[cfg(debug_assertions)]
+                error!("CLI: dns command failed: {}", e);
+                eprintln!("本地 DNS 运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "proxy" => {
+            checked(1, &["--refuse"]);
+            if let Err(e) = proxy_cmd(rest) {
+                #[cfg(debug_assertions)]
+                error!("CLI: proxy command failed: {}", e);
+                eprintln!("本地代理运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "sni" => {
+            checked(1, &[]);
+            if let Err(e) = sni_cmd(rest.first()) {
+                #[cfg(debug_assertions)]
+                error!("CLI: sni command failed: {}", e);
+                eprintln!("SNI 转发运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "pac" => {
+            checked(2, &["--serve"]);
+            if let Err(e) = pac_cmd(rest) {
+                #[cfg(debug_assertions)]
+                error!("CLI: pac command failed: {}", e);
+                eprintln!("PAC 生成失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "daemon" => {
+            let args = parse_daemon_args(rest).unwrap_or_else(|e| usage_error(&e));
+            check_write_access_exit();
+            if let Err(e) = daemon_cmd(args) {
+                #[cfg(debug_assertions)]
+                error!("CLI: daemon command failed: {}", e);
+                eprintln!("后台服务运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "service" => {
+            if let Err(e) = service_cmd(rest) {
+                #[cfg(debug_assertions)]
+                error!("CLI: service command failed: {}", e);
+                eprintln!("服务操作失败: {}", e);
+                std::process::exit(1);
+            }
+        }
         "help" | "--help" | "-h" => {
             print_help();
         }
         _ => {
             #[cfg(debug_assertions)]
             warn!("CLI: Unknown command: {}", command);
-            eprintln!("未知命令: {}", command);
-            println!();
-            print_help();
-            std::process::exit(1);
+            match suggest_command(command) {
+                Some(name) => usage_error(&format!("未知命令: {} (是否想输入 {}?)", command, name)),
+                None => usage_error(&format!("未知命令: {}", command)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_global_options() {
+        let (options, rest) =
+            parse_global_options(args(&["-v", "status", "--config=/tmp/a.conf", "--hosts", "/tmp/hosts", "-vv"])).unwrap();
+        assert_eq!(rest, args(&["status"]));
+        assert_eq!(options.config, Some(PathBuf::from("/tmp/a.conf")));
+        assert_eq!(options.hosts, Some(PathBuf::from("/tmp/hosts")));
+        assert_eq!(options.verbose, 3);
+        assert!(!options.quiet);
+
+        for (bad, hint) in [
+            (&["-q", "-v", "status"][..], "--quiet 不能与 --verbose"),
+            (&["status", "--config"][..], "--config 需要一个配置文件路径"),
+            (&["--config", "-v", "status"][..], "--config 需要一个配置文件路径"),
+            (&["--hosts=", "enable"][..], "--hosts 需要一个hosts 文件路径"),
+        ] {
+            let err = parse_global_options(args(bad)).unwrap_err();
+            assert!(err.contains(hint), "{:?} -> {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_enable_mode() {
+        for (list, mode) in [
+            (&[][..], EnableMode::Auto),
+            (&["--optimized"][..], EnableMode::Optimized),
+            (&["--default"][..], EnableMode::Default),
+            (&["--from-cache", "--from-cache"][..], EnableMode::FromCache),
+        ] {
+            assert_eq!(enable_mode(&args(list)), Ok(mode), "{:?}", list);
+        }
+
+        for (bad, hint) in [
+            (&["--optimized", "--default"][..], "只能选一个"),
+            (&["--from-cache", "--optimized"][..], "只能选一个"),
+            (&["--fast"][..], "enable 不支持选项 --fast"),
+            (&["now"][..], "enable 的参数过多: now"),
+        ] {
+            let err = enable_mode(&args(bad)).unwrap_err();
+            assert!(err.contains(hint), "{:?} -> {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_check_args() {
+        assert!(check_args("ssh", &args(&["--remove"]), 0, &["--remove"]).is_ok());
+        assert!(check_args("import", &args(&["a", "b", "c"]), usize::MAX, &[]).is_ok());
+        assert!(check_args("dns", &args(&[":5353", "1.1.1.1"]), 2, &[]).is_ok());
+
+        for (list, max, flags, error) in [
+            (&["extra"][..], 0, &[][..], "status 的参数过多: extra"),
+            (&["a", "b", "c"][..], 2, &[][..], "status 的参数过多: c"),
+            (&["--all"][..], 0, &[][..], "status 不支持选项 --all"),
+            (&["--all"][..], 0, &["--remove"][..], "status 不支持选项 --all (可用: --remove)"),
+        ] {
+            assert_eq!(check_args("status", &args(list), max, flags), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_suggest_command() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("status", "status"), 0);
+
+        for (typo, suggestion) in [
+            ("speedtset", Some("speedtest")),
+            ("enabel", Some("enable")),
+            ("stauts", Some("status")),
+            ("resuls", Some("results")),
+            ("frobnicate", None),
+        ] {
+            assert_eq!(suggest_command(typo), suggestion, "{}", typo);
         }
     }
 }
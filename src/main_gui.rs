@@ -1,10 +1,12 @@
 #![windows_subsystem = "windows"]
 
 use eframe::egui;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::thread;
-use free_to_github::{hosts, network};
+use free_to_github::{compare, config, elevate, helper, hosts, import, netprofile, network, rpc};
+use serde_json::json;
 
 #[cfg(debug_assertions)]
 use free_to_github::{info, error};
@@ -29,6 +31,16 @@ enum SpeedTestState {
     Completed,
 }
 
+/// Status line describing which results apply to the current network
+fn network_status_message(status: &netprofile::ProfileStatus) -> String {
+    match status {
+        netprofile::ProfileStatus::Applied { .. } => "已加载当前网络的测速结果".to_string(),
+        netprofile::ProfileStatus::Stale { .. } => "当前网络的测速结果已过期, 建议重新测速".to_string(),
+        netprofile::ProfileStatus::NoData { .. } => "当前网络暂无测速数据, 建议先测速".to_string(),
+        netprofile::ProfileStatus::Unknown => "就绪".to_string(),
+    }
+}
+
 struct GitHubAcceleratorApp {
     status_message: Arc<Mutex<String>>,
     is_enabled: Arc<Mutex<bool>>,
@@ -42,23 +54,70 @@ struct GitHubAcceleratorApp {
     speed_test_progress: Arc<Mutex<(usize, usize)>>,  // (completed, total)
     speed_test_current: Arc<Mutex<String>>,           // Currently testing domain
     speed_test_results: Arc<Mutex<Vec<SpeedTestResult>>>,
+    speed_test_failures: Arc<Mutex<network::FailureSummary>>,
     has_optimized_ips: Arc<Mutex<bool>>,
+
+    // Before/after comparison state (None while running or never run)
+    comparison: Arc<Mutex<Option<Vec<compare::DomainComparison>>>>,
+    comparing: Arc<Mutex<bool>>,
+    show_comparison: bool,
+
+    // Community hosts list import (file path or URL being typed)
+    import_source: String,
+    importing: Arc<Mutex<bool>>,
+    import_result: Arc<Mutex<Option<String>>>,
+
+    // Control socket of a running daemon; when set, the daemon owns the state
+    rpc_socket: Option<PathBuf>,
+}
+
+/// Status reported by a running daemon: (enabled, can write hosts, has optimized IPs)
+fn rpc_status(socket: &std::path::Path) -> Option<(bool, bool, bool)> {
+    let status = rpc::call(socket, "status", json!({})).ok()?;
+    Some((
+        status["enabled"].as_bool().unwrap_or(false),
+        status["can_write_hosts"].as_bool().unwrap_or(false),
+        status["has_optimized_ips"].as_bool().unwrap_or(false),
+    ))
 }
 
 impl Default for GitHubAcceleratorApp {
     fn default() -> Self {
-        let is_enabled = match hosts::is_enabled() {
-            Ok(enabled) => enabled,
-            Err(_) => false,
-        };
+        // Same settings file as the CLI (hosts path, probing, import sources)
+        let config_error = config::Settings::load_default()
+            .and_then(|settings| settings.apply())
+            .err()
+            .map(|e| format!("配置文件无效: {}", e));
+        let is_enabled = hosts::is_enabled().unwrap_or_default();
 
-        let has_permission = hosts::check_permission().is_ok();
+        // Without write access the privileged helper or an elevated re-launch
+        // (pkexec / sudo -A) can make the change instead
+        elevate::set_auto_elevate(true);
+        let has_permission = helper::check_write_access().is_ok();
+        // Results of the current network (or the cache file) from an earlier speed test
+        let network_status = netprofile::check_network_change();
+        let has_optimized_ips = hosts::has_optimized_ips();
+        let mut status_message = network_status
+            .as_ref()
+            .map(network_status_message)
+            .unwrap_or_else(|| "就绪".to_string());
+
+        // Act as a client of a running daemon (this user's or the system
+        // service) instead of owning the state
+        let rpc_socket = rpc::find_server();
+        let (is_enabled, has_permission, has_optimized_ips) = match rpc_socket.as_deref().and_then(rpc_status) {
+            Some(status) => {
+                status_message = "已连接到后台服务".to_string();
+                status
+            }
+            None => (is_enabled, has_permission, has_optimized_ips),
+        };
         
         Self {
-            status_message: Arc::new(Mutex::new("就绪".to_string())),
+            status_message: Arc::new(Mutex::new(status_message)),
             is_enabled: Arc::new(Mutex::new(is_enabled)),
             has_permission: Arc::new(Mutex::new(has_permission)),
-            error_message: Arc::new(Mutex::new(None)),
+            error_message: Arc::new(Mutex::new(config_error)),
             last_status_check: Arc::new(Mutex::new(Instant::now())),
             visuals_initialized: false,
             
@@ -67,7 +126,18 @@ impl Default for GitHubAcceleratorApp {
             speed_test_progress: Arc::new(Mutex::new((0, 0))),
             speed_test_current: Arc::new(Mutex::new(String::new())),
             speed_test_results: Arc::new(Mutex::new(Vec::new())),
-            has_optimized_ips: Arc::new(Mutex::new(false)),
+            speed_test_failures: Arc::new(Mutex::new(network::FailureSummary::default())),
+            has_optimized_ips: Arc::new(Mutex::new(has_optimized_ips)),
+
+            comparison: Arc::new(Mutex::new(None)),
+            comparing: Arc::new(Mutex::new(false)),
+            show_comparison: false,
+
+            import_source: String::new(),
+            importing: Arc::new(Mutex::new(false)),
+            import_result: Arc::new(Mutex::new(None)),
+
+            rpc_socket,
         }
     }
 }
@@ -97,10 +167,20 @@ impl eframe::App for GitHubAcceleratorApp {
         };
         
         if should_check {
-            // Update status cache
-            if let Ok(enabled) = hosts::is_enabled() {
+            if let Some((enabled, _, has_optimized)) = self.rpc_socket.as_deref().and_then(rpc_status) {
+                *self.is_enabled.lock().unwrap() = enabled;
+                *self.has_optimized_ips.lock().unwrap() = has_optimized;
+            } else if let Ok(enabled) = hosts::is_enabled() {
+                // Update status cache
                 *self.is_enabled.lock().unwrap() = enabled;
             }
+            // Switch to the results of the new network after a network change
+            // (a daemon we are connected to does this itself)
+            let network_status = if self.rpc_socket.is_none() { netprofile::check_network_change() } else { None };
+            if let Some(status) = network_status {
+                *self.has_optimized_ips.lock().unwrap() = hosts::has_optimized_ips();
+                *self.status_message.lock().unwrap() = network_status_message(&status);
+            }
             *self.last_status_check.lock().unwrap() = Instant::now();
         }
         
@@ -111,6 +191,7 @@ impl eframe::App for GitHubAcceleratorApp {
         let speed_test_state = self.speed_test_state.lock().unwrap().clone();
         let has_optimized = *self.has_optimized_ips.lock().unwrap();
         let speed_results = self.speed_test_results.lock().unwrap().clone();
+        let speed_failures = self.speed_test_failures.lock().unwrap().clone();
         let (progress_done, progress_total) = *self.speed_test_progress.lock().unwrap();
         let current_testing = self.speed_test_current.lock().unwrap().clone();
         
@@ -141,7 +222,7 @@ impl eframe::App for GitHubAcceleratorApp {
                         ui.label(egui::RichText::new("⚠️").size(24.0));
                         ui.vertical(|ui| {
                             ui.label(egui::RichText::new("需要管理员权限").size(15.0).color(egui::Color32::from_rgb(255, 200, 100)));
-                            ui.label(egui::RichText::new("请以管理员身份运行").size(11.0).color(egui::Color32::from_rgb(200, 200, 200)));
+                            ui.label(egui::RichText::new("请以管理员身份运行, 或先启动 free_to_github_helper").size(11.0).color(egui::Color32::from_rgb(200, 200, 200)));
                         });
                         ui.add_space(100.0);
                     });
@@ -199,7 +280,9 @@ impl eframe::App for GitHubAcceleratorApp {
                 ui.add_space(20.0);
                 
                 // Speed test results display (when completed)
-                if speed_test_state == SpeedTestState::Completed && !speed_results.is_empty() {
+                if speed_test_state == SpeedTestState::Completed
+                    && (!speed_results.is_empty() || speed_failures.failed() > 0)
+                {
                     ui.vertical_centered(|ui| {
                         let frame = egui::Frame::default()
                             .fill(egui::Color32::from_rgb(25, 50, 90))
@@ -223,6 +306,22 @@ impl eframe::App for GitHubAcceleratorApp {
                                     });
                                 });
                             }
+
+                            // Why probes failed: blocked IPs vs. a local network problem
+                            if speed_failures.failed() > 0 {
+                                ui.add_space(5.0);
+                                ui.label(egui::RichText::new(format!(
+                                    "⚠ 失败 {}/{}: {}",
+                                    speed_failures.failed(),
+                                    speed_failures.probes,
+                                    speed_failures.describe()
+                                ))
+                                .size(11.0).color(egui::Color32::from_rgb(255, 140, 60)));
+                                if speed_failures.local_network_down() {
+                                    ui.label(egui::RichText::new("本地网络似乎不可用, 请检查网络连接")
+                                        .size(11.0).color(egui::Color32::from_rgb(255, 80, 80)));
+                                }
+                            }
                         });
                     });
                     ui.add_space(15.0);
@@ -299,7 +398,7 @@ impl eframe::App for GitHubAcceleratorApp {
                 
                 // Utility buttons area - improved spacing and rounded corners
                 ui.horizontal(|ui| {
-                    ui.add_space(50.0);  // Better left spacing
+                    ui.add_space(14.0);  // Left spacing for four buttons
                     
                     // Refresh DNS button - tech blue with rounded corners
                     let dns_btn = egui::Button::new(
@@ -340,7 +439,55 @@ impl eframe::App for GitHubAcceleratorApp {
                     if ui.add_enabled(is_enabled, github_btn).clicked() {
                         self.open_github();
                     }
+                    
+                    ui.add_space(18.0);
+                    
+                    // Before/after comparison button - teal
+                    let comparing = *self.comparing.lock().unwrap();
+                    let compare_btn = egui::Button::new(
+                        egui::RichText::new(if comparing { "⏳ 对比中" } else { "📈 对比" }).size(13.0).color(egui::Color32::WHITE)
+                    )
+                    .fill(egui::Color32::from_rgb(60, 170, 170))
+                    .rounding(8.0)
+                    .min_size(egui::vec2(90.0, 40.0));
+                    
+                    if ui.add_enabled(!comparing, compare_btn).clicked() {
+                        self.start_comparison();
+                    }
                 });
+
+                ui.add_space(15.0);
+
+                // Community hosts list import - extra candidates for the next speed test
+                let importing = *self.importing.lock().unwrap();
+                ui.horizontal(|ui| {
+                    ui.add_space(30.0);
+                    ui.add(egui::TextEdit::singleline(&mut self.import_source)
+                        .hint_text("hosts 列表文件路径或网址")
+                        .desired_width(330.0));
+                    ui.add_space(10.0);
+
+                    let import_btn = egui::Button::new(
+                        egui::RichText::new(if importing { "⏳ 导入中" } else { "📥 导入" }).size(13.0).color(egui::Color32::WHITE)
+                    )
+                    .fill(egui::Color32::from_rgb(120, 140, 200))
+                    .rounding(8.0)
+                    .min_size(egui::vec2(90.0, 30.0));
+
+                    // The daemon probes its own candidate pool, which a local import would not reach
+                    let response = ui.add_enabled(!importing && self.rpc_socket.is_none(), import_btn);
+                    let response = if self.rpc_socket.is_some() {
+                        response.on_disabled_hover_text("已连接后台服务: 请在配置文件中添加 import = <文件或网址>")
+                    } else {
+                        response
+                    };
+                    if response.clicked() {
+                        self.start_import();
+                    }
+                });
+                if let Some(result) = self.import_result.lock().unwrap().as_ref() {
+                    ui.label(egui::RichText::new(result).size(11.0).color(egui::Color32::from_rgb(120, 255, 160)));
+                }
                 
                 ui.add_space(25.0);
                 
@@ -359,10 +506,113 @@ impl eframe::App for GitHubAcceleratorApp {
                 ui.add_space(10.0);
             });
         });
+
+        self.show_comparison_window(ctx);
     }
 }
 
 impl GitHubAcceleratorApp {
+    /// Import a community hosts list as candidates for the next speed test
+    fn start_import(&mut self) {
+        let source = self.import_source.trim().to_string();
+        if source.is_empty() {
+            *self.error_message.lock().unwrap() = Some("请输入 hosts 列表文件路径或网址".to_string());
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        info!("User started import from {}", source);
+
+        *self.importing.lock().unwrap() = true;
+        *self.import_result.lock().unwrap() = None;
+        let importing = Arc::clone(&self.importing);
+        let import_result = Arc::clone(&self.import_result);
+        let error_message = Arc::clone(&self.error_message);
+        thread::spawn(move || {
+            match import::import(&source) {
+                Ok(0) => *error_message.lock().unwrap() = Some("列表中没有加速域名的可用条目".to_string()),
+                Ok(kept) => {
+                    *import_result.lock().unwrap() = Some(format!("✓ 已导入 {} 个候选 IP, 下次测速时生效", kept));
+                    *error_message.lock().unwrap() = None;
+                }
+                Err(e) => {
+                    #[cfg(debug_assertions)]
+                    error!("Import from {} failed: {}", source, e);
+                    *error_message.lock().unwrap() = Some(format!("导入失败: {}", e));
+                }
+            }
+            *importing.lock().unwrap() = false;
+        });
+    }
+
+    /// Compare system DNS against the selected IPs in a background thread
+    fn start_comparison(&mut self) {
+        #[cfg(debug_assertions)]
+        info!("User started before/after comparison");
+
+        *self.comparing.lock().unwrap() = true;
+        *self.comparison.lock().unwrap() = None;
+        self.show_comparison = true;
+
+        let comparison = Arc::clone(&self.comparison);
+        let comparing = Arc::clone(&self.comparing);
+        thread::spawn(move || {
+            let report = compare::compare_all(&compare::CompareConfig::default());
+            *comparison.lock().unwrap() = Some(report);
+            *comparing.lock().unwrap() = false;
+        });
+    }
+
+    /// Side-by-side comparison report
+    fn show_comparison_window(&mut self, ctx: &egui::Context) {
+        if !self.show_comparison {
+            return;
+        }
+        let report = self.comparison.lock().unwrap().clone();
+
+        let path_text = |path: &compare::PathReport| {
+            let latency = match path.latency_ms {
+                Some(_) if path.tls_valid == Some(false) => "证书无效".to_string(),
+                Some(latency) => format!("{}ms", latency),
+                None => path.failure.map(|f| f.label()).unwrap_or("-").to_string(),
+            };
+            format!("{} {} 丢包{:.0}%", path.ip.as_deref().unwrap_or("-"), latency, path.loss * 100.0)
+        };
+
+        egui::Window::new("📈 系统 DNS vs 优选 IP")
+            .open(&mut self.show_comparison)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| match report {
+                None => {
+                    ui.label("正在对比, 请稍候...");
+                    ctx.request_repaint_after(Duration::from_millis(200));
+                }
+                Some(report) => {
+                    egui::Grid::new("comparison_grid").striped(true).show(ui, |ui| {
+                        ui.label(egui::RichText::new("域名").strong());
+                        ui.label(egui::RichText::new("系统 DNS").strong());
+                        ui.label(egui::RichText::new("优选 IP").strong());
+                        ui.label(egui::RichText::new("结论").strong());
+                        ui.end_row();
+
+                        for row in &report {
+                            let color = match row.verdict {
+                                compare::Verdict::Improved => egui::Color32::from_rgb(80, 220, 120),
+                                compare::Verdict::Worse | compare::Verdict::BothFailed => egui::Color32::from_rgb(255, 80, 80),
+                                _ => egui::Color32::from_rgb(180, 180, 200),
+                            };
+                            ui.label(egui::RichText::new(&row.domain).size(11.0));
+                            ui.label(egui::RichText::new(path_text(&row.system)).size(11.0));
+                            ui.label(egui::RichText::new(path_text(&row.optimized)).size(11.0));
+                            ui.label(egui::RichText::new(row.verdict.label()).size(11.0).color(color));
+                            ui.end_row();
+                        }
+                    });
+                }
+            });
+    }
+
     /// Start speed test in background thread
     fn start_speed_test(&mut self) {
         #[cfg(debug_assertions)]
@@ -373,6 +623,7 @@ impl GitHubAcceleratorApp {
         *self.speed_test_progress.lock().unwrap() = (0, 0);
         *self.speed_test_current.lock().unwrap() = String::new();
         *self.speed_test_results.lock().unwrap() = Vec::new();
+        *self.speed_test_failures.lock().unwrap() = network::FailureSummary::default();
         *self.error_message.lock().unwrap() = None;
         
         // Clone Arc references for the thread
@@ -380,7 +631,10 @@ impl GitHubAcceleratorApp {
         let progress = Arc::clone(&self.speed_test_progress);
         let current = Arc::clone(&self.speed_test_current);
         let results = Arc::clone(&self.speed_test_results);
+        let failures = Arc::clone(&self.speed_test_failures);
         let has_optimized = Arc::clone(&self.has_optimized_ips);
+        let error_message = Arc::clone(&self.error_message);
+        let rpc_socket = self.rpc_socket.clone();
         
         // Run speed test in background
         thread::spawn(move || {
@@ -393,27 +647,51 @@ impl GitHubAcceleratorApp {
                 })
             };
             
-            // Run the test
-            let test_results = network::test_all_domains_parallel(Some(progress_cb));
+            // Run the test, on the daemon when one is running; the daemon
+            // stores its results itself
+            let test_results = match rpc_socket {
+                Some(socket) => rpc::run_speed_test(&socket, |done, total, domain| progress_cb(done, total, domain)),
+                None => {
+                    import::import_configured();
+                    let results = network::test_all_domains_ranked(Some(progress_cb));
+                    if results.values().all(|ranked| ranked.is_empty()) {
+                        // Keep the previous results when every probe failed
+                        Err(std::io::Error::other(network::last_failure_summary().describe()))
+                    } else {
+                        hosts::set_ranked_ips(results.clone());
+                        Ok(results)
+                    }
+                }
+            };
+            let test_results = match test_results {
+                Ok(results) => results,
+                Err(e) => {
+                    *error_message.lock().unwrap() = Some(format!("测速失败: {}", e));
+                    *failures.lock().unwrap() = network::last_failure_summary();
+                    *state.lock().unwrap() = SpeedTestState::Idle;
+                    *current.lock().unwrap() = String::new();
+                    return;
+                }
+            };
             
-            // Convert results for display
+            // Convert results for display (best IP per domain)
             let mut display_results: Vec<SpeedTestResult> = test_results
                 .iter()
-                .map(|(domain, (ip, latency))| SpeedTestResult {
-                    domain: domain.clone(),
-                    ip: ip.clone(),
-                    latency_ms: *latency,
+                .filter_map(|(domain, ranked)| {
+                    ranked.first().map(|(ip, latency)| SpeedTestResult {
+                        domain: domain.clone(),
+                        ip: ip.clone(),
+                        latency_ms: *latency,
+                    })
                 })
                 .collect();
             
             // Sort by latency
             display_results.sort_by_key(|r| r.latency_ms);
             
-            // Update hosts module with optimized IPs
-            hosts::set_optimized_ips(test_results);
-            
             // Update UI state
             *results.lock().unwrap() = display_results;
+            *failures.lock().unwrap() = network::last_failure_summary();
             *has_optimized.lock().unwrap() = true;
             *state.lock().unwrap() = SpeedTestState::Completed;
             *current.lock().unwrap() = String::new();
@@ -427,10 +705,13 @@ impl GitHubAcceleratorApp {
         // Use optimized IPs if available
         let use_optimized = *self.has_optimized_ips.lock().unwrap();
         
-        let result = if use_optimized {
-            hosts::enable_optimized()
-        } else {
-            hosts::enable()
+        let result = match &self.rpc_socket {
+            Some(socket) => {
+                let mode = if use_optimized { "optimized" } else { "default" };
+                rpc::call(socket, "enable", json!({ "mode": mode })).map(|_| ())
+            }
+            None if use_optimized => helper::enable_optimized(),
+            None => helper::enable(),
         };
         
         match result {
@@ -462,7 +743,11 @@ impl GitHubAcceleratorApp {
         #[cfg(debug_assertions)]
         info!("User triggered disable acceleration");
         
-        match hosts::disable() {
+        let result = match &self.rpc_socket {
+            Some(socket) => rpc::call(socket, "disable", json!({})).map(|_| ()),
+            None => helper::disable(),
+        };
+        match result {
             Ok(_) => {
                 *self.is_enabled.lock().unwrap() = false;
                 *self.status_message.lock().unwrap() = "✓ 加速已禁用!".to_string();
@@ -554,7 +839,7 @@ fn main() -> Result<(), eframe::Error> {
     
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([520.0, 680.0])  // Increased height for speed test results
+            .with_inner_size([520.0, 730.0])  // Increased height for speed test results
             .with_resizable(false)
             .with_decorations(true),
         ..Default::default()
@@ -572,6 +857,7 @@ fn main() -> Result<(), eframe::Error> {
 }
 
 fn setup_custom_fonts(ctx: &egui::Context) {
+    #[cfg_attr(not(target_os = "windows"), allow(unused_mut))]
     let mut fonts = egui::FontDefinitions::default();
     
     // Add Chinese font support (using system built-in Microsoft YaHei)
@@ -0,0 +1,106 @@
+//! Privileged helper: the only component that needs root
+//!
+//! Started as root (by hand, via sudo or as a system service), it writes the
+//! hosts file on behalf of unprivileged GUI/CLI processes. See
+//! `free_to_github::helper` for the protocol.
+
+#[cfg(debug_assertions)]
+use free_to_github::{info, error};
+
+fn print_help() {
+    println!("Free to GitHub 特权助手 - 代替图形界面/命令行写入 hosts 文件");
+    println!();
+    println!("用法:");
+    println!("  free_to_github_helper [选项]");
+    println!();
+    println!("选项:");
+    println!("  --socket 路径     监听的 Unix 套接字 (默认 /run/free-to-github/helper.sock)");
+    println!("  --hosts 路径      要管理的 hosts 文件 (默认系统 hosts)");
+    println!("  --backup 路径     首次修改前的 hosts 备份位置");
+    println!("  --allow-uid UID   允许该用户发送命令 (可重复, root 总是允许)");
+    println!("  --allow-gid GID   允许该主组的用户发送命令 (可重复)");
+    println!();
+    println!("经 sudo/pkexec 启动时, 调用者的用户会被自动允许");
+}
+
+#[cfg(unix)]
+fn parse_args(args: &[String]) -> std::io::Result<free_to_github::helper::HelperConfig> {
+    use std::io::{Error, ErrorKind};
+    use std::path::PathBuf;
+
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+    let mut config = free_to_github::helper::HelperConfig::default();
+
+    // The user who asked for elevation is the one who will run the UI
+    for var in ["SUDO_UID", "PKEXEC_UID"] {
+        if let Some(uid) = std::env::var(var).ok().and_then(|v| v.parse().ok()) {
+            config.allowed_uids.push(uid);
+        }
+    }
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| invalid(format!("{} 需要一个参数", arg)));
+        match arg.as_str() {
+            "--socket" => config.socket = PathBuf::from(value()?),
+            "--hosts" => config.hosts_path = PathBuf::from(value()?),
+            "--backup" => config.backup = PathBuf::from(value()?),
+            "--allow-uid" => {
+                let v = value()?;
+                config.allowed_uids.push(v.parse().map_err(|_| invalid(format!("无效的 UID: {}", v)))?);
+            }
+            "--allow-gid" => {
+                let v = value()?;
+                config.allowed_gids.push(v.parse().map_err(|_| invalid(format!("无效的 GID: {}", v)))?);
+            }
+            other => return Err(invalid(format!("未知选项: {}", other))),
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(unix)]
+fn run(args: &[String]) -> std::io::Result<()> {
+    use free_to_github::helper::Helper;
+
+    let config = parse_args(args)?;
+    if std::fs::OpenOptions::new().append(true).open(&config.hosts_path).is_err() {
+        eprintln!("警告: 无法写入 {}, 助手通常需要以 root 身份运行", config.hosts_path.display());
+    }
+
+    let helper = Helper::bind(config.clone())?;
+    println!("✓ 特权助手已启动, 监听 {}", helper.socket().display());
+    println!("  管理 {}, 允许 root 及 UID {:?}", config.hosts_path.display(), config.allowed_uids);
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    helper.serve(&stop)
+}
+
+#[cfg(not(unix))]
+fn run(_args: &[String]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "特权助手需要 Unix 域套接字, 当前平台不支持",
+    ))
+}
+
+fn main() {
+    #[cfg(debug_assertions)]
+    {
+        let _ = free_to_github::logger::FileLogger::init();
+        info!("Helper started");
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "help" || a == "--help" || a == "-h") {
+        print_help();
+        return;
+    }
+
+    if let Err(e) = run(&args) {
+        #[cfg(debug_assertions)]
+        error!("Helper failed: {}", e);
+        eprintln!("特权助手启动失败: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,319 @@
+//! Per-network result profiles
+//!
+//! The best IPs differ between office, home and tethered networks. Each speed
+//! test result is stored under a fingerprint of the network it ran on (default
+//! gateway IP and MAC, interface name and local subnet), so switching networks
+//! re-applies the last known-good set for that network instead of whatever the
+//! previous network produced.
+
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use crate::{helper, hosts, paths};
+
+/// Directory inside the data directory holding one result file per network
+const PROFILES_DIR: &str = "profiles";
+
+// Route flags from <linux/route.h>
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+
+// How long to wait for the kernel to resolve the gateway's ARP entry
+#[cfg(target_os = "linux")]
+const ARP_WAIT: Duration = Duration::from_millis(300);
+
+/// Identity of the network the machine is currently attached to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkFingerprint {
+    /// Interface carrying the default route
+    pub interface: String,
+    pub gateway_ip: Ipv4Addr,
+    /// Gateway MAC from the ARP table (`None` when it could not be resolved)
+    pub gateway_mac: Option<String>,
+    /// Local subnet of the interface in CIDR notation (`None` when unknown)
+    pub subnet: Option<String>,
+}
+
+impl NetworkFingerprint {
+    /// Stable identifier used to key stored results and history records
+    pub fn id(&self) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            self.interface,
+            self.gateway_ip,
+            self.gateway_mac.as_deref().unwrap_or("-"),
+            self.subnet.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// Parse a `/proc/net/route` address field (hex of the address in memory order)
+fn parse_route_addr(field: &str) -> Option<Ipv4Addr> {
+    u32::from_str_radix(field, 16).ok().map(|v| Ipv4Addr::from(v.to_ne_bytes()))
+}
+
+/// Find the default route in `/proc/net/route` content: (interface, gateway)
+///
+/// When several default routes exist the one with the lowest metric wins.
+pub fn parse_default_route(route: &str) -> Option<(String, Ipv4Addr)> {
+    route
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 {
+                return None;
+            }
+            let destination = parse_route_addr(fields[1])?;
+            let gateway = parse_route_addr(fields[2])?;
+            let flags = u32::from_str_radix(fields[3], 16).ok()?;
+            let metric: u32 = fields[6].parse().ok()?;
+            let mask = parse_route_addr(fields[7])?;
+
+            let is_default = destination.is_unspecified() && mask.is_unspecified();
+            let usable = flags & RTF_UP != 0 && flags & RTF_GATEWAY != 0;
+            (is_default && usable).then(|| (metric, fields[0].to_string(), gateway))
+        })
+        .min_by_key(|(metric, _, _)| *metric)
+        .map(|(_, interface, gateway)| (interface, gateway))
+}
+
+/// Find the directly connected subnet of `interface` containing `gateway`
+pub fn parse_subnet(route: &str, interface: &str, gateway: Ipv4Addr) -> Option<String> {
+    route
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[0] != interface {
+                return None;
+            }
+            let destination = parse_route_addr(fields[1])?;
+            let flags = u32::from_str_radix(fields[3], 16).ok()?;
+            let mask = parse_route_addr(fields[7])?;
+            if flags & RTF_GATEWAY != 0 || mask.is_unspecified() {
+                return None;
+            }
+
+            let mask_bits = u32::from(mask);
+            let contains = u32::from(gateway) & mask_bits == u32::from(destination) & mask_bits;
+            contains.then(|| (mask_bits.count_ones(), destination))
+        })
+        .max_by_key(|(prefix, _)| *prefix)
+        .map(|(prefix, destination)| format!("{}/{}", destination, prefix))
+}
+
+/// Look up the MAC address of `ip` in `/proc/net/arp` content
+pub fn parse_arp_mac(arp: &str, ip: Ipv4Addr) -> Option<String> {
+    arp.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[0].parse::<Ipv4Addr>().ok()? != ip {
+            return None;
+        }
+        // Incomplete entries carry an all-zero address
+        let mac = fields[3].to_ascii_lowercase();
+        (mac != "00:00:00:00:00:00").then_some(mac)
+    })
+}
+
+/// Build a fingerprint from the route and ARP tables
+pub fn fingerprint_from(route: &str, arp: &str) -> Option<NetworkFingerprint> {
+    let (interface, gateway_ip) = parse_default_route(route)?;
+    Some(NetworkFingerprint {
+        subnet: parse_subnet(route, &interface, gateway_ip),
+        gateway_mac: parse_arp_mac(arp, gateway_ip),
+        interface,
+        gateway_ip,
+    })
+}
+
+/// Fingerprint of the current network (`None` without a default route)
+///
+/// The gateway's ARP entry expires when idle; a missing one is resolved by
+/// sending the gateway a datagram, so the same network keeps the same id.
+#[cfg(target_os = "linux")]
+pub fn current_fingerprint() -> Option<NetworkFingerprint> {
+    let read_arp = || std::fs::read_to_string("/proc/net/arp").unwrap_or_default();
+    let route = std::fs::read_to_string("/proc/net/route").ok()?;
+    let mut fingerprint = fingerprint_from(&route, &read_arp())?;
+    if fingerprint.gateway_mac.is_none() {
+        // Port 9 is discard; only the neighbour lookup matters
+        if let Ok(socket) = std::net::UdpSocket::bind("0.0.0.0:0") {
+            let _ = socket.send_to(&[0], (fingerprint.gateway_ip, 9));
+        }
+        let deadline = std::time::Instant::now() + ARP_WAIT;
+        while fingerprint.gateway_mac.is_none() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+            fingerprint.gateway_mac = parse_arp_mac(&read_arp(), fingerprint.gateway_ip);
+        }
+    }
+    Some(fingerprint)
+}
+
+/// Fingerprint of the current network (`None` without a default route)
+#[cfg(not(target_os = "linux"))]
+pub fn current_fingerprint() -> Option<NetworkFingerprint> {
+    None
+}
+
+/// Identifier of the current network, empty when it cannot be fingerprinted
+pub fn current_network_id() -> String {
+    current_fingerprint().map(|fp| fp.id()).unwrap_or_default()
+}
+
+/// Path of the stored results for a network
+pub fn profile_path(network_id: &str) -> PathBuf {
+    let stem: String = network_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    paths::data_dir().join(PROFILES_DIR).join(format!("{}.cache", stem))
+}
+
+/// What happened to the optimized IPs after a network check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileStatus {
+    /// The last known-good set of this network was loaded
+    Applied { network: String, saved_at: u64 },
+    /// This network has results but they are past the cache TTL; they are
+    /// not loaded and a new speed test is needed
+    Stale { network: String, saved_at: u64 },
+    /// This network has never been tested; a speed test is needed
+    NoData { network: String },
+    /// The network could not be fingerprinted (no default route)
+    Unknown,
+}
+
+// Network seen by the last check
+static LAST_NETWORK: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+fn get_last_network() -> &'static Mutex<Option<String>> {
+    LAST_NETWORK.get_or_init(|| Mutex::new(None))
+}
+
+/// Classify the stored results of a network without loading them
+pub fn profile_status(network_id: &str) -> ProfileStatus {
+    if network_id.is_empty() {
+        return ProfileStatus::Unknown;
+    }
+    let now = paths::unix_now();
+    match hosts::network_profile_saved_at(network_id) {
+        Some(saved_at) if now.saturating_sub(saved_at) <= hosts::OPTIMIZED_CACHE_TTL_SECS => ProfileStatus::Applied {
+            network: network_id.to_string(),
+            saved_at,
+        },
+        Some(saved_at) => ProfileStatus::Stale {
+            network: network_id.to_string(),
+            saved_at,
+        },
+        None => ProfileStatus::NoData {
+            network: network_id.to_string(),
+        },
+    }
+}
+
+/// Load the stored results for a network into the optimized IP set
+///
+/// Only fresh results are loaded; stale ones are reported but leave the
+/// current optimized IPs untouched.
+pub fn apply_profile(network_id: &str) -> ProfileStatus {
+    let status = profile_status(network_id);
+    if matches!(status, ProfileStatus::Applied { .. }) && hosts::load_network_profile(network_id).is_none() {
+        // Removed between the two reads
+        return ProfileStatus::NoData {
+            network: network_id.to_string(),
+        };
+    }
+    status
+}
+
+/// Detect a network change and switch to that network's results
+///
+/// Returns `None` while the network stays the same. On a change, the
+/// network's fresh last known-good set is loaded and rewritten into the hosts
+/// file when acceleration is enabled; `Stale` and `NoData` flag that a speed
+/// test is needed while the current results stay in place. The first call
+/// reports the current network and loads its results, but never writes the
+/// hosts file: starting a process is not a network change.
+pub fn check_network_change() -> Option<ProfileStatus> {
+    let current = current_network_id();
+    let previous = {
+        let mut last = get_last_network().lock().unwrap();
+        if last.as_deref() == Some(current.as_str()) {
+            return None;
+        }
+        last.replace(current.clone())
+    };
+
+    let status = apply_profile(&current);
+    let changed = previous.is_some();
+    if changed && matches!(status, ProfileStatus::Applied { .. }) && hosts::is_enabled().unwrap_or(false) {
+        if let Err(_e) = helper::enable_optimized() {
+            #[cfg(debug_assertions)]
+            log::warn!("Failed to re-apply results for network {}: {}", current, _e);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    log::info!("Network check: {:?}", status);
+
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+        wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+        eth0\t00000000\t010010AC\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+        eth0\t000010AC\t00000000\t0001\t0\t0\t100\t0000FFFF\t0\t0\t0\n\
+        wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0\n";
+
+    const ARP: &str = "IP address       HW type     Flags       HW address            Mask     Device\n\
+        172.16.0.1       0x1         0x2         AA:BB:CC:DD:EE:01     *        eth0\n\
+        192.168.1.1      0x1         0x0         00:00:00:00:00:00     *        wlan0\n";
+
+    #[test]
+    fn test_fingerprint_from_proc_tables() {
+        let fp = fingerprint_from(ROUTE, ARP).unwrap();
+        assert_eq!(fp.interface, "eth0");
+        assert_eq!(fp.gateway_ip, Ipv4Addr::new(172, 16, 0, 1));
+        assert_eq!(fp.gateway_mac.as_deref(), Some("aa:bb:cc:dd:ee:01"));
+        assert_eq!(fp.subnet.as_deref(), Some("172.16.0.0/16"));
+        assert_eq!(fp.id(), "eth0/172.16.0.1/aa:bb:cc:dd:ee:01/172.16.0.0/16");
+
+        // Same addressing behind another router is another network
+        let other_router = ARP.replace("AA:BB:CC:DD:EE:01", "AA:BB:CC:DD:EE:02");
+        let other = fingerprint_from(ROUTE, &other_router).unwrap();
+        assert_eq!((&other.interface, other.gateway_ip, &other.subnet), (&fp.interface, fp.gateway_ip, &fp.subnet));
+        assert_ne!(other.id(), fp.id());
+
+        let unresolved = fingerprint_from(ROUTE, "IP address       HW type     Flags       HW address\n").unwrap();
+        assert_eq!(unresolved.gateway_mac, None);
+        assert_eq!(unresolved.id(), "eth0/172.16.0.1/-/172.16.0.0/16");
+
+        // Incomplete ARP entries leave the MAC unknown
+        assert_eq!(parse_arp_mac(ARP, Ipv4Addr::new(192, 168, 1, 1)), None);
+        assert_eq!(
+            parse_subnet(ROUTE, "wlan0", Ipv4Addr::new(192, 168, 1, 1)).as_deref(),
+            Some("192.168.1.0/24")
+        );
+        assert_eq!(fingerprint_from("Iface\tDestination\n", ARP), None);
+    }
+
+    #[test]
+    fn test_profile_path_is_file_name_safe() {
+        let path = profile_path("eth0/172.16.0.1/172.16.0.0/16");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name, "eth0_172.16.0.1_172.16.0.0_16.cache");
+        assert_eq!(apply_profile(""), ProfileStatus::Unknown);
+        assert_eq!(
+            profile_status("never-tested"),
+            ProfileStatus::NoData { network: "never-tested".to_string() }
+        );
+    }
+}
@@ -4,26 +4,422 @@
 //! - Test TCP connection latency to GitHub IPs
 //! - Select the fastest IP for each domain
 //! - Support multiple candidate IPs per domain
+//! - Merge candidates imported from external hosts lists
+//! - Probe IPv4 and IPv6 candidates under a family policy
+//! - Measure through an upstream SOCKS5 or HTTP CONNECT proxy
+//! - Probe each unique endpoint once per run and share the result
+//! - Record every measurement in the persistent history store
+//! - Rank bulk-transfer domains by download throughput
+//! - Optionally rank git domains by smart-HTTP negotiation time
+//! - Tag measurements with the network they were taken on
+//! - Classify probe failures (timeout, refused, reset, unreachable, TLS)
+//! - Assign one coherent IP set to each group of related domains
 
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 
+use crate::history::{self, HistoryStore, Measurement, RetentionPolicy};
+use crate::{http, netprofile};
+use crate::paths;
+use crate::proxy::{self, ProxyConfig};
+use crate::gitprobe::{self, GitProbeConfig, GitProbeResult};
+use crate::throughput::{self, ThroughputConfig, ThroughputResult};
+
 /// Connection timeout for latency test (milliseconds)
 const CONNECT_TIMEOUT_MS: u64 = 3000;
 
 /// Port to use for testing (HTTPS)
 const TEST_PORT: u16 = 443;
 
+/// IP family preference for probing and hosts entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FamilyPolicy {
+    /// Only probe and write IPv4 (A) entries
+    V4Only,
+    /// Only probe and write IPv6 (AAAA) entries
+    V6Only,
+    /// Probe both families and write the best of each, fastest first
+    #[default]
+    PreferFastest,
+}
+
+impl FamilyPolicy {
+    /// Check whether an IP literal belongs to an allowed family
+    pub fn allows(&self, ip: &str) -> bool {
+        match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => *self != FamilyPolicy::V6Only,
+            Ok(IpAddr::V6(_)) => *self != FamilyPolicy::V4Only,
+            Err(_) => false,
+        }
+    }
+
+    /// Parse a policy name (`v4`, `v6` or `fastest`)
+    pub fn parse(name: &str) -> Option<FamilyPolicy> {
+        match name {
+            "v4" | "v4-only" | "ipv4" => Some(FamilyPolicy::V4Only),
+            "v6" | "v6-only" | "ipv6" => Some(FamilyPolicy::V6Only),
+            "fastest" | "prefer-fastest" | "auto" => Some(FamilyPolicy::PreferFastest),
+            _ => None,
+        }
+    }
+}
+
+/// Settings shared by all probes
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    pub port: u16,
+    pub timeout_ms: u64,
+    pub family: FamilyPolicy,
+    /// Upstream proxy every probe connection is tunneled through
+    pub proxy: Option<ProxyConfig>,
+    /// Download settings for domains ranked by throughput
+    pub throughput: ThroughputConfig,
+    /// Smart-HTTP settings for domains ranked by git negotiation time
+    pub git: GitProbeConfig,
+    /// Per-domain ranking signal replacing the catalog default
+    pub ranking_overrides: HashMap<String, RankingSignal>,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            port: TEST_PORT,
+            timeout_ms: CONNECT_TIMEOUT_MS,
+            family: FamilyPolicy::default(),
+            proxy: None,
+            throughput: ThroughputConfig::default(),
+            git: GitProbeConfig::default(),
+            ranking_overrides: HashMap::new(),
+        }
+    }
+}
+
+// Global probe configuration used by the parallel speed test and hosts writer
+static PROBE_CONFIG: OnceLock<Mutex<ProbeConfig>> = OnceLock::new();
+
+fn get_probe_config() -> &'static Mutex<ProbeConfig> {
+    PROBE_CONFIG.get_or_init(|| Mutex::new(ProbeConfig::default()))
+}
+
+/// Current probe configuration
+pub fn probe_config() -> ProbeConfig {
+    get_probe_config().lock().unwrap().clone()
+}
+
+/// Replace the probe configuration
+pub fn set_probe_config(config: ProbeConfig) {
+    *get_probe_config().lock().unwrap() = config;
+}
+
+/// Kind of probe performed against an endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProbeKind {
+    /// Plain TCP connect latency
+    TcpConnect,
+    /// Bounded download measuring sustained throughput
+    Throughput,
+    /// Git smart-HTTP ref advertisement
+    GitSmartHttp,
+}
+
+impl ProbeKind {
+    /// Stable name used in stored measurements
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeKind::TcpConnect => "tcp",
+            ProbeKind::Throughput => "throughput",
+            ProbeKind::GitSmartHttp => "git",
+        }
+    }
+
+    /// Parse a stored probe kind name
+    pub fn parse(name: &str) -> Option<ProbeKind> {
+        match name {
+            "tcp" => Some(ProbeKind::TcpConnect),
+            "throughput" => Some(ProbeKind::Throughput),
+            "git" => Some(ProbeKind::GitSmartHttp),
+            _ => None,
+        }
+    }
+}
+
+/// Classified result of a single probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ProbeOutcome {
+    Success,
+    /// No answer within the timeout (often a silently dropped or blocked IP)
+    Timeout,
+    /// The host answered with a reset to the connection attempt
+    Refused,
+    /// An established connection was reset or cut off
+    Reset,
+    /// No route to the network or host (often the local network is down)
+    Unreachable,
+    /// TLS handshake or certificate validation failed
+    TlsFailure,
+    /// Any other error
+    Other,
+}
+
+impl ProbeOutcome {
+    /// Classify an I/O error returned by a probe
+    pub fn classify(err: &io::Error) -> ProbeOutcome {
+        if err
+            .get_ref()
+            .is_some_and(|inner| inner.downcast_ref::<rustls::Error>().is_some())
+        {
+            return ProbeOutcome::TlsFailure;
+        }
+
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ProbeOutcome::Timeout,
+            io::ErrorKind::ConnectionRefused => ProbeOutcome::Refused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ProbeOutcome::Reset,
+            io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::AddrNotAvailable => ProbeOutcome::Unreachable,
+            _ => ProbeOutcome::Other,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == ProbeOutcome::Success
+    }
+
+    /// Stable name used in stored measurements
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeOutcome::Success => "ok",
+            ProbeOutcome::Timeout => "timeout",
+            ProbeOutcome::Refused => "refused",
+            ProbeOutcome::Reset => "reset",
+            ProbeOutcome::Unreachable => "unreachable",
+            ProbeOutcome::TlsFailure => "tls",
+            ProbeOutcome::Other => "error",
+        }
+    }
+
+    /// Parse a stored outcome name
+    pub fn parse(name: &str) -> Option<ProbeOutcome> {
+        match name {
+            "ok" => Some(ProbeOutcome::Success),
+            "timeout" => Some(ProbeOutcome::Timeout),
+            "refused" => Some(ProbeOutcome::Refused),
+            "reset" => Some(ProbeOutcome::Reset),
+            "unreachable" => Some(ProbeOutcome::Unreachable),
+            "tls" => Some(ProbeOutcome::TlsFailure),
+            "error" => Some(ProbeOutcome::Other),
+            _ => None,
+        }
+    }
+
+    /// Display label for the user interfaces
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProbeOutcome::Success => "成功",
+            ProbeOutcome::Timeout => "超时",
+            ProbeOutcome::Refused => "连接被拒绝",
+            ProbeOutcome::Reset => "连接被重置",
+            ProbeOutcome::Unreachable => "网络不可达",
+            ProbeOutcome::TlsFailure => "TLS 握手失败",
+            ProbeOutcome::Other => "其他错误",
+        }
+    }
+}
+
+/// Outcome of one probe with the time it took to succeed or fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeSample {
+    pub outcome: ProbeOutcome,
+    /// Latency on success, time to failure otherwise
+    pub elapsed_ms: u64,
+}
+
+impl ProbeSample {
+    /// Latency when the probe succeeded
+    pub fn latency_ms(&self) -> Option<u64> {
+        self.outcome.is_success().then_some(self.elapsed_ms)
+    }
+}
+
+/// Failure counts of a speed test, grouped by outcome
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FailureSummary {
+    /// Probes performed
+    pub probes: usize,
+    /// (outcome, count) of failed probes, most frequent first
+    pub failures: Vec<(ProbeOutcome, usize)>,
+}
+
+impl FailureSummary {
+    pub fn from_outcomes(outcomes: impl IntoIterator<Item = ProbeOutcome>) -> FailureSummary {
+        let mut probes = 0;
+        let mut counts: HashMap<ProbeOutcome, usize> = HashMap::new();
+        for outcome in outcomes {
+            probes += 1;
+            if !outcome.is_success() {
+                *counts.entry(outcome).or_insert(0) += 1;
+            }
+        }
+
+        let mut failures: Vec<(ProbeOutcome, usize)> = counts.into_iter().collect();
+        failures.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        FailureSummary { probes, failures }
+    }
+
+    /// Number of failed probes
+    pub fn failed(&self) -> usize {
+        self.failures.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Every probe failed and the errors point at the local network rather
+    /// than at GitHub blocking individual IPs
+    pub fn local_network_down(&self) -> bool {
+        self.probes > 0
+            && self.failed() == self.probes
+            && self
+                .failures
+                .iter()
+                .all(|(outcome, _)| *outcome == ProbeOutcome::Unreachable)
+    }
+
+    /// One-line summary such as `超时 3, 连接被拒绝 1`
+    pub fn describe(&self) -> String {
+        self.failures
+            .iter()
+            .map(|(outcome, count)| format!("{} {}", outcome.label(), count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Failure summary of the last speed test
+static LAST_FAILURE_SUMMARY: OnceLock<Mutex<FailureSummary>> = OnceLock::new();
+
+fn get_last_failure_summary() -> &'static Mutex<FailureSummary> {
+    LAST_FAILURE_SUMMARY.get_or_init(|| Mutex::new(FailureSummary::default()))
+}
+
+/// Failure summary of the last speed test in this process
+pub fn last_failure_summary() -> FailureSummary {
+    get_last_failure_summary().lock().unwrap().clone()
+}
+
+/// Cache key: IP, port, probe kind and what the probe fetches (URL or path)
+type ProbeKey = (String, u16, ProbeKind, String);
+
+/// Per-run probe cache so each unique endpoint is probed only once
+///
+/// Many domains share candidates (the `*.githubusercontent.com` family, the
+/// 140.82.x.4 addresses), so results are computed once and fanned out to every
+/// domain that uses them. Concurrent lookups of the same endpoint wait for the
+/// first probe instead of connecting again.
+pub struct ProbeCache<T = ProbeSample> {
+    entries: Mutex<HashMap<ProbeKey, Arc<OnceLock<T>>>>,
+}
+
+impl<T: Clone> ProbeCache<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the cached result for an endpoint, running `probe` on first use
+    pub fn get_or_probe<F: FnOnce() -> T>(&self, ip: &str, port: u16, kind: ProbeKind, probe: F) -> T {
+        self.get_or_probe_target(ip, port, kind, "", probe)
+    }
+
+    /// Like [`ProbeCache::get_or_probe`] for probes fetching `target` (a URL
+    /// or path), so different objects on the same endpoint are not confused
+    pub fn get_or_probe_target<F: FnOnce() -> T>(
+        &self,
+        ip: &str,
+        port: u16,
+        kind: ProbeKind,
+        target: &str,
+        probe: F,
+    ) -> T {
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            Arc::clone(
+                entries
+                    .entry((ip.to_string(), port, kind, target.to_string()))
+                    .or_insert_with(|| Arc::new(OnceLock::new())),
+            )
+        };
+
+        // Lock released above, so other endpoints are probed concurrently
+        cell.get_or_init(probe).clone()
+    }
+
+    /// Number of unique endpoints looked up so far
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Default for ProbeCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Result of a single IP latency test
 #[derive(Debug, Clone)]
 pub struct LatencyResult {
     pub ip: String,
     pub domain: String,
+    /// Latency when the probe succeeded
     pub latency_ms: Option<u64>,
-    pub success: bool,
+    pub outcome: ProbeOutcome,
+    /// Latency on success, time to failure otherwise
+    pub elapsed_ms: u64,
+}
+
+impl LatencyResult {
+    fn from_sample(ip: &str, domain: &str, sample: ProbeSample) -> Self {
+        Self {
+            ip: ip.to_string(),
+            domain: domain.to_string(),
+            latency_ms: sample.latency_ms(),
+            outcome: sample.outcome,
+            elapsed_ms: sample.elapsed_ms,
+        }
+    }
+}
+
+/// Where a candidate IP came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CandidateSource {
+    /// Shipped with the application
+    Builtin,
+    /// Imported from an external hosts list (label of the list)
+    Imported(String),
+}
+
+/// Signal used to rank a domain's candidate IPs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankingSignal {
+    /// TCP connect latency
+    Latency,
+    /// Sustained download throughput, for bulk-transfer domains; latency
+    /// decides while throughput probes are off or the domain has no object
+    Throughput,
+    /// Git smart-HTTP negotiation time (opt-in for the git domains)
+    GitNegotiation,
 }
 
 /// Domain with multiple candidate IPs
@@ -31,205 +427,427 @@ pub struct LatencyResult {
 pub struct DomainEntry {
     pub domain: String,
     pub candidate_ips: Vec<String>,
+    pub sources: HashMap<String, CandidateSource>,
+    pub ranking: RankingSignal,
+    /// Name of the domain group this entry belongs to, if any
+    pub group: Option<String>,
     pub best_ip: Option<String>,
     pub best_latency_ms: Option<u64>,
 }
 
-/// GitHub domains with multiple candidate IPs for smart selection
-pub fn get_domain_candidates() -> Vec<DomainEntry> {
-    vec![
-        DomainEntry {
-            domain: "github.com".to_string(),
-            candidate_ips: vec![
-                "140.82.112.4".to_string(),
-                "140.82.113.4".to_string(),
-                "140.82.114.4".to_string(),
-                "20.205.243.166".to_string(),
-                "20.27.177.113".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "api.github.com".to_string(),
-            candidate_ips: vec![
-                "140.82.112.6".to_string(),
-                "140.82.113.6".to_string(),
-                "140.82.114.6".to_string(),
-                "20.205.243.168".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "gist.github.com".to_string(),
-            candidate_ips: vec![
-                "140.82.112.4".to_string(),
-                "140.82.113.4".to_string(),
-                "140.82.114.4".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "raw.githubusercontent.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.133".to_string(),
-                "185.199.109.133".to_string(),
-                "185.199.110.133".to_string(),
-                "185.199.111.133".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "codeload.github.com".to_string(),
-            candidate_ips: vec![
-                "140.82.112.10".to_string(),
-                "140.82.113.10".to_string(),
-                "140.82.114.10".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "github.githubassets.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.154".to_string(),
-                "185.199.109.154".to_string(),
-                "185.199.110.154".to_string(),
-                "185.199.111.154".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "assets-cdn.github.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.153".to_string(),
-                "185.199.109.153".to_string(),
-                "185.199.110.153".to_string(),
-                "185.199.111.153".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "cloud.githubusercontent.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.133".to_string(),
-                "185.199.109.133".to_string(),
-                "185.199.110.133".to_string(),
-                "185.199.111.133".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "avatars.githubusercontent.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.133".to_string(),
-                "185.199.109.133".to_string(),
-                "185.199.110.133".to_string(),
-                "185.199.111.133".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "github.global.ssl.fastly.net".to_string(),
-            candidate_ips: vec![
-                "199.232.69.194".to_string(),
-                "151.101.1.194".to_string(),
-                "151.101.65.194".to_string(),
-                "151.101.129.194".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "githubstatus.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.153".to_string(),
-                "185.199.109.153".to_string(),
-                "185.199.110.153".to_string(),
-                "185.199.111.153".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "collector.github.com".to_string(),
-            candidate_ips: vec![
-                "140.82.112.22".to_string(),
-                "140.82.113.22".to_string(),
-                "140.82.114.22".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-        DomainEntry {
-            domain: "objects.githubusercontent.com".to_string(),
-            candidate_ips: vec![
-                "185.199.108.133".to_string(),
-                "185.199.109.133".to_string(),
-                "185.199.110.133".to_string(),
-                "185.199.111.133".to_string(),
-            ],
-            best_ip: None,
-            best_latency_ms: None,
-        },
-    ]
-}
-
-/// Test TCP connection latency to a single IP
-pub fn test_ip_latency(ip: &str) -> Option<u64> {
-    let addr = format!("{}:{}", ip, TEST_PORT);
-    let socket_addr = match addr.to_socket_addrs() {
-        Ok(mut addrs) => match addrs.next() {
-            Some(addr) => addr,
-            None => return None,
-        },
-        Err(_) => return None,
-    };
+impl DomainEntry {
+    /// Source of a candidate IP (built-in unless it was imported)
+    pub fn source_of(&self, ip: &str) -> CandidateSource {
+        self.sources.get(ip).cloned().unwrap_or(CandidateSource::Builtin)
+    }
 
-    let timeout = Duration::from_millis(CONNECT_TIMEOUT_MS);
-    let start = Instant::now();
+    /// Built-in IP used for untested domains, the only kind applied without probing
+    ///
+    /// This is the first built-in IPv4 candidate, or IPv6 under `V6Only`, since an
+    /// unprobed AAAA entry would stall clients on networks without IPv6.
+    pub fn fallback_ip(&self, family: FamilyPolicy) -> Option<&String> {
+        let want_v6 = family == FamilyPolicy::V6Only;
+        self.candidate_ips.iter().find(|ip| {
+            self.source_of(ip) == CandidateSource::Builtin
+                && ip.parse::<IpAddr>().is_ok_and(|addr| addr.is_ipv6() == want_v6)
+        })
+    }
+}
 
-    match TcpStream::connect_timeout(&socket_addr, timeout) {
-        Ok(_stream) => {
-            let elapsed = start.elapsed().as_millis() as u64;
-            Some(elapsed)
-        }
-        Err(_) => None,
+/// Built-in GitHub domains with multiple candidate IPs for smart selection
+const BUILTIN_CANDIDATES: &[(&str, &[&str])] = &[
+    ("github.com", &["140.82.112.4", "140.82.113.4", "140.82.114.4", "20.205.243.166", "20.27.177.113"]),
+    ("api.github.com", &["140.82.112.6", "140.82.113.6", "140.82.114.6", "20.205.243.168"]),
+    ("gist.github.com", &["140.82.112.4", "140.82.113.4", "140.82.114.4"]),
+    ("raw.githubusercontent.com", &["185.199.108.133", "185.199.109.133", "185.199.110.133", "185.199.111.133", "2606:50c0:8000::133", "2606:50c0:8001::133", "2606:50c0:8002::133", "2606:50c0:8003::133"]),
+    ("codeload.github.com", &["140.82.112.10", "140.82.113.10", "140.82.114.10"]),
+    ("github.githubassets.com", &["185.199.108.154", "185.199.109.154", "185.199.110.154", "185.199.111.154", "2606:50c0:8000::154", "2606:50c0:8001::154", "2606:50c0:8002::154", "2606:50c0:8003::154"]),
+    ("assets-cdn.github.com", &["185.199.108.153", "185.199.109.153", "185.199.110.153", "185.199.111.153", "2606:50c0:8000::153", "2606:50c0:8001::153", "2606:50c0:8002::153", "2606:50c0:8003::153"]),
+    ("cloud.githubusercontent.com", &["185.199.108.133", "185.199.109.133", "185.199.110.133", "185.199.111.133", "2606:50c0:8000::133", "2606:50c0:8001::133", "2606:50c0:8002::133", "2606:50c0:8003::133"]),
+    ("avatars.githubusercontent.com", &["185.199.108.133", "185.199.109.133", "185.199.110.133", "185.199.111.133", "2606:50c0:8000::133", "2606:50c0:8001::133", "2606:50c0:8002::133", "2606:50c0:8003::133"]),
+    ("github.global.ssl.fastly.net", &["199.232.69.194", "151.101.1.194", "151.101.65.194", "151.101.129.194"]),
+    ("githubstatus.com", &["185.199.108.153", "185.199.109.153", "185.199.110.153", "185.199.111.153", "2606:50c0:8000::153", "2606:50c0:8001::153", "2606:50c0:8002::153", "2606:50c0:8003::153"]),
+    ("collector.github.com", &["140.82.112.22", "140.82.113.22", "140.82.114.22"]),
+    ("objects.githubusercontent.com", &["185.199.108.133", "185.199.109.133", "185.199.110.133", "185.199.111.133", "2606:50c0:8000::133", "2606:50c0:8001::133", "2606:50c0:8002::133", "2606:50c0:8003::133"]),
+];
+
+/// Imported `(ip, domain)` pairs keyed by source label
+type ImportedCandidates = HashMap<String, Vec<(String, String)>>;
+
+/// Bulk-transfer domains, ranked by throughput instead of connect latency
+const BULK_TRANSFER_DOMAINS: &[&str] = &[
+    "codeload.github.com",
+    "objects.githubusercontent.com",
+    "raw.githubusercontent.com",
+];
+
+/// Domains that should land on the same edge, as `(name, patterns)`
+///
+/// A pattern is either an exact domain or `*.suffix`. Cookies and redirects
+/// between `github.com` and `gist.github.com` behave best on one edge, and
+/// the `githubusercontent.com` hosts share the same Fastly addresses.
+const DOMAIN_GROUPS: &[(&str, &[&str])] = &[
+    ("github-web", &["github.com", "gist.github.com"]),
+    ("githubusercontent", &["*.githubusercontent.com"]),
+];
+
+/// A group of domains ranked together and assigned the same IPs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainGroup {
+    pub name: String,
+    /// Exact domains or `*.suffix` wildcards
+    pub patterns: Vec<String>,
+}
+
+impl DomainGroup {
+    /// Check whether a domain belongs to this group
+    pub fn matches(&self, domain: &str) -> bool {
+        self.patterns.iter().any(|pattern| match pattern.strip_prefix("*.") {
+            Some(suffix) => domain
+                .strip_suffix(suffix)
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+            None => pattern == domain,
+        })
     }
 }
 
-/// Test all candidate IPs for a domain and find the fastest one
-pub fn find_best_ip_for_domain(entry: &mut DomainEntry) -> Option<LatencyResult> {
-    let mut best_result: Option<LatencyResult> = None;
+/// Domain groups declared in the catalog
+pub fn domain_groups() -> Vec<DomainGroup> {
+    DOMAIN_GROUPS
+        .iter()
+        .map(|(name, patterns)| DomainGroup {
+            name: name.to_string(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        })
+        .collect()
+}
 
-    for ip in &entry.candidate_ips {
-        if let Some(latency) = test_ip_latency(ip) {
-            let result = LatencyResult {
-                ip: ip.clone(),
-                domain: entry.domain.clone(),
-                latency_ms: Some(latency),
-                success: true,
+/// Name of the group a domain belongs to
+pub fn group_of(domain: &str) -> Option<String> {
+    domain_groups().into_iter().find(|g| g.matches(domain)).map(|g| g.name)
+}
+
+// Candidates imported from external hosts lists
+static IMPORTED_CANDIDATES: OnceLock<Mutex<ImportedCandidates>> = OnceLock::new();
+
+fn get_imported_candidates() -> &'static Mutex<ImportedCandidates> {
+    IMPORTED_CANDIDATES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Check whether a domain is managed by this tool
+pub fn is_managed_domain(domain: &str) -> bool {
+    BUILTIN_CANDIDATES.iter().any(|(d, _)| *d == domain)
+}
+
+/// Add imported `(ip, domain)` pairs to the candidate pool under a source label
+///
+/// Entries for unmanaged domains are ignored. Importing the same source again
+/// replaces its previous entries. Returns the number of entries kept.
+pub fn add_imported_candidates(source: &str, entries: &[(String, String)]) -> usize {
+    let kept: Vec<(String, String)> = entries
+        .iter()
+        .filter(|(_, domain)| is_managed_domain(domain))
+        .cloned()
+        .collect();
+    let count = kept.len();
+
+    let mut imported = get_imported_candidates().lock().unwrap();
+    if kept.is_empty() {
+        imported.remove(source);
+    } else {
+        imported.insert(source.to_string(), kept);
+    }
+
+    count
+}
+
+/// Remove all candidates imported from a source
+pub fn remove_imported_source(source: &str) {
+    let mut imported = get_imported_candidates().lock().unwrap();
+    imported.remove(source);
+}
+
+/// Remove every imported candidate
+pub fn clear_imported_candidates() {
+    let mut imported = get_imported_candidates().lock().unwrap();
+    imported.clear();
+}
+
+/// GitHub domains with their built-in and imported candidate IPs
+pub fn get_domain_candidates() -> Vec<DomainEntry> {
+    let imported = get_imported_candidates().lock().unwrap();
+
+    BUILTIN_CANDIDATES
+        .iter()
+        .map(|(domain, ips)| {
+            let mut entry = DomainEntry {
+                domain: domain.to_string(),
+                candidate_ips: ips.iter().map(|ip| ip.to_string()).collect(),
+                sources: ips
+                    .iter()
+                    .map(|ip| (ip.to_string(), CandidateSource::Builtin))
+                    .collect(),
+                ranking: if BULK_TRANSFER_DOMAINS.contains(domain) {
+                    RankingSignal::Throughput
+                } else {
+                    RankingSignal::Latency
+                },
+                group: group_of(domain),
+                best_ip: None,
+                best_latency_ms: None,
             };
 
-            match &best_result {
-                None => best_result = Some(result),
-                Some(current_best) => {
-                    if let Some(current_latency) = current_best.latency_ms {
-                        if latency < current_latency {
-                            best_result = Some(result);
-                        }
+            // Append imported IPs after the built-in ones, keeping the first source seen
+            let mut labels: Vec<&String> = imported.keys().collect();
+            labels.sort();
+            for label in labels {
+                for (ip, imported_domain) in &imported[label] {
+                    if imported_domain == domain && !entry.sources.contains_key(ip) {
+                        entry.candidate_ips.push(ip.clone());
+                        entry
+                            .sources
+                            .insert(ip.clone(), CandidateSource::Imported(label.clone()));
                     }
                 }
             }
+
+            entry
+        })
+        .collect()
+}
+
+/// Test TCP connection latency to a single IP using the current probe config
+pub fn test_ip_latency(ip: &str) -> Option<u64> {
+    test_ip_latency_with(ip, &probe_config())
+}
+
+/// Test TCP connection latency to a single IPv4 or IPv6 address
+///
+/// With a proxy configured, this is the time until the tunnel to the IP is up.
+pub fn test_ip_latency_with(ip: &str, config: &ProbeConfig) -> Option<u64> {
+    probe_ip_with(ip, config).latency_ms()
+}
+
+/// Probe a single IP, classifying why the connection failed
+pub fn probe_ip_with(ip: &str, config: &ProbeConfig) -> ProbeSample {
+    let ip: IpAddr = match ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => {
+            return ProbeSample {
+                outcome: ProbeOutcome::Other,
+                elapsed_ms: 0,
+            }
+        }
+    };
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let start = Instant::now();
+
+    let connection = match &config.proxy {
+        Some(proxy) => proxy::connect_through(proxy, &ip.to_string(), config.port, timeout),
+        // SocketAddr formats IPv6 as [addr]:port, unlike a plain "{}:{}" string
+        None => TcpStream::connect_timeout(&SocketAddr::new(ip, config.port), timeout),
+    };
+
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    let outcome = match connection {
+        Ok(_stream) => ProbeOutcome::Success,
+        Err(e) => ProbeOutcome::classify(&e),
+    };
+    ProbeSample { outcome, elapsed_ms }
+}
+
+/// Probe every candidate allowed by the family policy, fastest first
+pub fn rank_domain(entry: &DomainEntry, config: &ProbeConfig) -> Vec<LatencyResult> {
+    rank_domain_cached(entry, config, &ProbeCache::new())
+}
+
+/// Probe every candidate allowed by the family policy, keeping failures
+pub fn probe_domain_cached(
+    entry: &DomainEntry,
+    config: &ProbeConfig,
+    cache: &ProbeCache,
+) -> Vec<LatencyResult> {
+    entry
+        .candidate_ips
+        .iter()
+        .filter(|ip| config.family.allows(ip))
+        .map(|ip| {
+            let sample = cache.get_or_probe(ip, config.port, ProbeKind::TcpConnect, || {
+                probe_ip_with(ip, config)
+            });
+            LatencyResult::from_sample(ip, &entry.domain, sample)
+        })
+        .collect()
+}
+
+/// Like `rank_domain`, reusing results for endpoints already in `cache`
+pub fn rank_domain_cached(
+    entry: &DomainEntry,
+    config: &ProbeConfig,
+    cache: &ProbeCache,
+) -> Vec<LatencyResult> {
+    let mut ranked: Vec<LatencyResult> = probe_domain_cached(entry, config, cache)
+        .into_iter()
+        .filter(|r| r.outcome.is_success())
+        .collect();
+
+    // Stable sort keeps candidate order for equal latencies
+    ranked.sort_by_key(|r| r.latency_ms.unwrap_or(u64::MAX));
+    ranked
+}
+
+/// Give every member of a group the same IP order, ranked across the group
+///
+/// Only IPs reachable for every ranked member are kept. Each IP scores the
+/// sum of its positions in the members' own rankings (so throughput or git
+/// re-ranking of a member still counts), ties going to the lower summed
+/// latency. Members keep their own latency for each IP. A group without a
+/// common IP is left ranked per domain.
+pub fn apply_domain_groups(results: &mut RankedResults, groups: &[DomainGroup]) {
+    for group in groups {
+        let mut members: Vec<String> = results.keys().filter(|d| group.matches(d)).cloned().collect();
+        if members.len() < 2 {
+            continue;
+        }
+        members.sort();
+
+        let first = &results[&members[0]];
+        let mut scored: Vec<(String, usize, u64)> = first
+            .iter()
+            .filter_map(|(ip, _)| {
+                let mut position_sum = 0;
+                let mut latency_sum = 0;
+                for member in &members {
+                    let ranked = &results[member];
+                    let position = ranked.iter().position(|(candidate, _)| candidate == ip)?;
+                    position_sum += position;
+                    latency_sum += ranked[position].1;
+                }
+                Some((ip.clone(), position_sum, latency_sum))
+            })
+            .collect();
+
+        if scored.is_empty() {
+            #[cfg(debug_assertions)]
+            log::warn!("Domain group {} has no IP reachable for all members", group.name);
+            continue;
+        }
+        scored.sort_by_key(|(_, position_sum, latency_sum)| (*position_sum, *latency_sum));
+
+        for member in &members {
+            let ranked = results.get_mut(member).expect("member taken from results");
+            let latency_of = |ip: &str| ranked.iter().find(|(candidate, _)| candidate == ip).map(|(_, l)| *l);
+            let coherent: Vec<(String, u64)> = scored
+                .iter()
+                .filter_map(|(ip, _, _)| latency_of(ip).map(|latency| (ip.clone(), latency)))
+                .collect();
+            *ranked = coherent;
         }
     }
+}
+
+/// Throughput probe results per domain: (ip, result), best first
+pub type ThroughputResults = HashMap<String, Vec<(String, ThroughputResult)>>;
+
+// Throughput measured during the last speed test
+static LAST_THROUGHPUT: OnceLock<Mutex<ThroughputResults>> = OnceLock::new();
+
+fn get_last_throughput() -> &'static Mutex<ThroughputResults> {
+    LAST_THROUGHPUT.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Throughput (Mbps, stalls) measured for bulk-transfer domains in the last speed test
+pub fn last_throughput_results() -> ThroughputResults {
+    get_last_throughput().lock().unwrap().clone()
+}
+
+/// Re-rank reachable IPs of a bulk-transfer domain by download throughput
+///
+/// Only the `max_candidates` fastest IPs by connect latency are downloaded
+/// from; they move ahead of the rest in throughput order. Returns the
+/// measurements taken, best first.
+pub fn rerank_by_throughput(
+    entry: &DomainEntry,
+    ranked: &mut [(String, u64)],
+    config: &ProbeConfig,
+    cache: &ProbeCache<Option<ThroughputResult>>,
+) -> Vec<(String, ThroughputResult)> {
+    if !config.throughput.enabled {
+        return Vec::new();
+    }
+    let url = match config.throughput.objects.get(&entry.domain) {
+        Some(url) => url,
+        None => return Vec::new(),
+    };
+    let port = http::Url::parse(url).map(|u| u.port).unwrap_or(config.port);
+
+    let mut measured: Vec<(String, ThroughputResult)> = ranked
+        .iter()
+        .take(config.throughput.max_candidates)
+        .filter_map(|(ip, _)| {
+            cache
+                .get_or_probe_target(ip, port, ProbeKind::Throughput, url, || {
+                    throughput::measure_throughput(ip, url, &config.throughput, config).ok()
+                })
+                .map(|result| (ip.clone(), result))
+        })
+        .collect();
+    measured.sort_by(|a, b| b.1.score().total_cmp(&a.1.score()));
+    move_measured_first(ranked, &measured);
+
+    measured
+}
+
+/// Put measured IPs first in measurement order, the rest keep latency order
+fn move_measured_first<T>(ranked: &mut [(String, u64)], measured: &[(String, T)]) {
+    let position = |ip: &String| measured.iter().position(|(m, _)| m == ip).unwrap_or(usize::MAX);
+    ranked.sort_by_key(|(ip, _)| position(ip));
+}
+
+/// Git probe results per domain: (ip, result), fastest negotiation first
+pub type GitResults = HashMap<String, Vec<(String, GitProbeResult)>>;
+
+// Git negotiation measured during the last speed test
+static LAST_GIT_RESULTS: OnceLock<Mutex<GitResults>> = OnceLock::new();
+
+fn get_last_git_results() -> &'static Mutex<GitResults> {
+    LAST_GIT_RESULTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Git negotiation times measured for git-ranked domains in the last speed test
+pub fn last_git_results() -> GitResults {
+    get_last_git_results().lock().unwrap().clone()
+}
+
+/// Re-rank reachable IPs of a domain by git smart-HTTP negotiation time
+///
+/// Like `rerank_by_throughput`, only the fastest `max_candidates` IPs are
+/// probed and moved ahead of the rest.
+pub fn rerank_by_git(
+    ranked: &mut [(String, u64)],
+    config: &ProbeConfig,
+    cache: &ProbeCache<Option<GitProbeResult>>,
+) -> Vec<(String, GitProbeResult)> {
+    let mut measured: Vec<(String, GitProbeResult)> = ranked
+        .iter()
+        .take(config.git.max_candidates)
+        .filter_map(|(ip, _)| {
+            cache
+                .get_or_probe_target(ip, config.git.port, ProbeKind::GitSmartHttp, &config.git.repo_path, || {
+                    gitprobe::probe_git(ip, &config.git, config).ok()
+                })
+                .map(|result| (ip.clone(), result))
+        })
+        .collect();
+    measured.sort_by_key(|(_, result)| result.negotiation_ms);
+    move_measured_first(ranked, &measured);
+
+    measured
+}
+
+/// Test all candidate IPs for a domain and find the fastest one
+pub fn find_best_ip_for_domain(entry: &mut DomainEntry) -> Option<LatencyResult> {
+    let best_result = rank_domain(entry, &probe_config()).into_iter().next();
 
     // Update entry with best result
     if let Some(ref result) = best_result {
@@ -243,30 +861,106 @@ pub fn find_best_ip_for_domain(entry: &mut DomainEntry) -> Option<LatencyResult>
 /// Speed test progress callback type
 pub type ProgressCallback = Box<dyn Fn(usize, usize, &str) + Send + Sync>;
 
-/// Test all domains in parallel and find the best IP for each
-/// Returns a map of domain -> (best_ip, latency_ms)
-pub fn test_all_domains_parallel(
-    progress_callback: Option<Arc<dyn Fn(usize, usize, &str) + Send + Sync>>,
-) -> HashMap<String, (String, u64)> {
+/// Shared progress callback that can be cloned into worker threads
+pub type SharedProgressCallback = Arc<dyn Fn(usize, usize, &str) + Send + Sync>;
+
+/// Map of domain -> reachable IPs with latency, fastest first
+pub type RankedResults = HashMap<String, Vec<(String, u64)>>;
+
+/// Test all domains in parallel and rank every reachable IP for each
+pub fn test_all_domains_ranked(
+    progress_callback: Option<SharedProgressCallback>,
+) -> RankedResults {
+    let config = probe_config();
     let domains = get_domain_candidates();
     let total = domains.len();
-    let results: Arc<Mutex<HashMap<String, (String, u64)>>> = Arc::new(Mutex::new(HashMap::new()));
+    let results: Arc<Mutex<RankedResults>> = Arc::new(Mutex::new(HashMap::new()));
+    let measurements: Arc<Mutex<Vec<Measurement>>> = Arc::new(Mutex::new(Vec::new()));
     let completed = Arc::new(Mutex::new(0usize));
+    // Shared across domains so every unique endpoint is probed once per run
+    let cache: Arc<ProbeCache> = Arc::new(ProbeCache::new());
+    let throughput_cache: Arc<ProbeCache<Option<ThroughputResult>>> = Arc::new(ProbeCache::new());
+    let throughput_results: Arc<Mutex<ThroughputResults>> = Arc::new(Mutex::new(HashMap::new()));
+    let git_cache: Arc<ProbeCache<Option<GitProbeResult>>> = Arc::new(ProbeCache::new());
+    let git_results: Arc<Mutex<GitResults>> = Arc::new(Mutex::new(HashMap::new()));
+    let timestamp = paths::unix_now();
+    let network_id = netprofile::current_network_id();
 
     let mut handles = vec![];
 
-    for mut entry in domains {
+    for entry in domains {
         let results = Arc::clone(&results);
+        let measurements = Arc::clone(&measurements);
         let completed = Arc::clone(&completed);
+        let cache = Arc::clone(&cache);
+        let throughput_cache = Arc::clone(&throughput_cache);
+        let throughput_results = Arc::clone(&throughput_results);
+        let git_cache = Arc::clone(&git_cache);
+        let git_results = Arc::clone(&git_results);
         let progress_callback = progress_callback.clone();
-        let domain = entry.domain.clone();
+        let config = config.clone();
+        let network_id = network_id.clone();
 
         let handle = thread::spawn(move || {
-            if let Some(best) = find_best_ip_for_domain(&mut entry) {
-                if let Some(latency) = best.latency_ms {
-                    let mut res = results.lock().unwrap();
-                    res.insert(best.domain.clone(), (best.ip, latency));
+            let probed = probe_domain_cached(&entry, &config, &cache);
+            measurements
+                .lock()
+                .unwrap()
+                .extend(probed.iter().map(|r| Measurement {
+                    timestamp,
+                    domain: r.domain.clone(),
+                    ip: r.ip.clone(),
+                    kind: ProbeKind::TcpConnect,
+                    latency_ms: r.latency_ms,
+                    kbps: None,
+                    outcome: r.outcome,
+                    network: network_id.clone(),
+                }));
+            let measurement = |ip: &String, kind: ProbeKind, latency_ms: u64, kbps: Option<u64>| Measurement {
+                timestamp,
+                domain: entry.domain.clone(),
+                ip: ip.clone(),
+                kind,
+                latency_ms: Some(latency_ms),
+                kbps,
+                outcome: ProbeOutcome::Success,
+                network: network_id.clone(),
+            };
+
+            let mut ranked: Vec<(String, u64)> = probed
+                .into_iter()
+                .filter_map(|r| r.latency_ms.map(|latency| (r.ip, latency)))
+                .collect();
+            ranked.sort_by_key(|(_, latency)| *latency);
+
+            let signal = config.ranking_overrides.get(&entry.domain).copied().unwrap_or(entry.ranking);
+            match signal {
+                RankingSignal::Latency => {}
+                RankingSignal::Throughput => {
+                    let measured = rerank_by_throughput(&entry, &mut ranked, &config, &throughput_cache);
+                    measurements.lock().unwrap().extend(measured.iter().map(|(ip, r)| {
+                        measurement(ip, ProbeKind::Throughput, r.duration_ms, Some((r.mbps * 1000.0) as u64))
+                    }));
+                    if !measured.is_empty() {
+                        throughput_results.lock().unwrap().insert(entry.domain.clone(), measured);
+                    }
                 }
+                RankingSignal::GitNegotiation => {
+                    let measured = rerank_by_git(&mut ranked, &config, &git_cache);
+                    measurements.lock().unwrap().extend(
+                        measured
+                            .iter()
+                            .map(|(ip, r)| measurement(ip, ProbeKind::GitSmartHttp, r.negotiation_ms, None)),
+                    );
+                    if !measured.is_empty() {
+                        git_results.lock().unwrap().insert(entry.domain.clone(), measured);
+                    }
+                }
+            }
+
+            if !ranked.is_empty() {
+                let mut res = results.lock().unwrap();
+                res.insert(entry.domain.clone(), ranked);
             }
 
             // Update progress
@@ -276,7 +970,7 @@ pub fn test_all_domains_parallel(
             drop(count);
 
             if let Some(ref cb) = progress_callback {
-                cb(current, total, &domain);
+                cb(current, total, &entry.domain);
             }
         });
 
@@ -288,13 +982,57 @@ pub fn test_all_domains_parallel(
         let _ = handle.join();
     }
 
-    let final_results = results.lock().unwrap().clone();
+    #[cfg(debug_assertions)]
+    log::info!("Speed test probed {} unique endpoints for {} domains", cache.len(), total);
+
+    *get_last_throughput().lock().unwrap() = throughput_results.lock().unwrap().clone();
+    *get_last_git_results().lock().unwrap() = git_results.lock().unwrap().clone();
+
+    let summary = FailureSummary::from_outcomes(
+        measurements
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|m| m.kind == ProbeKind::TcpConnect)
+            .map(|m| m.outcome),
+    );
+    #[cfg(debug_assertions)]
+    if summary.failed() > 0 {
+        log::warn!("Speed test: {} of {} probes failed ({:?})", summary.failed(), summary.probes, summary.failures);
+    }
+    *get_last_failure_summary().lock().unwrap() = summary;
+
+    if history::is_recording_enabled() {
+        let measurements = measurements.lock().unwrap();
+        let store = HistoryStore::open_default();
+        let recorded = store
+            .append(&measurements)
+            .and_then(|_| store.compact_if_due(&RetentionPolicy::default(), timestamp));
+        if let Err(_e) = recorded {
+            #[cfg(debug_assertions)]
+            log::warn!("Failed to record speed test history: {}", _e);
+        }
+    }
+
+    let mut final_results = results.lock().unwrap().clone();
+    apply_domain_groups(&mut final_results, &domain_groups());
     final_results
 }
 
+/// Test all domains in parallel and find the best IP for each
+/// Returns a map of domain -> (best_ip, latency_ms)
+pub fn test_all_domains_parallel(
+    progress_callback: Option<SharedProgressCallback>,
+) -> HashMap<String, (String, u64)> {
+    test_all_domains_ranked(progress_callback)
+        .into_iter()
+        .filter_map(|(domain, ranked)| ranked.into_iter().next().map(|best| (domain, best)))
+        .collect()
+}
+
 /// Quick test of key domains only (github.com, api, raw)
 pub fn test_key_domains() -> Vec<LatencyResult> {
-    let key_domains = vec!["github.com", "api.github.com", "raw.githubusercontent.com"];
+    let key_domains = ["github.com", "api.github.com", "raw.githubusercontent.com"];
     let mut results = vec![];
 
     let all_domains = get_domain_candidates();
@@ -303,12 +1041,10 @@ pub fn test_key_domains() -> Vec<LatencyResult> {
             if let Some(result) = find_best_ip_for_domain(&mut entry) {
                 results.push(result);
             } else {
-                results.push(LatencyResult {
-                    ip: entry.candidate_ips.first().cloned().unwrap_or_default(),
-                    domain: entry.domain,
-                    latency_ms: None,
-                    success: false,
-                });
+                // Report how the first candidate failed
+                let ip = entry.candidate_ips.first().cloned().unwrap_or_default();
+                let sample = probe_ip_with(&ip, &probe_config());
+                results.push(LatencyResult::from_sample(&ip, &entry.domain, sample));
             }
         }
     }
@@ -357,6 +1093,155 @@ mod tests {
         assert!(domains.iter().any(|d| d.domain == "github.com"));
     }
 
+    #[test]
+    fn test_imported_candidates_merge() {
+        let source = "test:merge";
+        let entries = vec![
+            ("140.82.121.3".to_string(), "github.com".to_string()),
+            ("140.82.112.4".to_string(), "github.com".to_string()),
+            ("1.2.3.4".to_string(), "example.com".to_string()),
+        ];
+        assert_eq!(add_imported_candidates(source, &entries), 2);
+
+        let domains = get_domain_candidates();
+        let github = domains.iter().find(|d| d.domain == "github.com").unwrap();
+        assert_eq!(github.candidate_ips.last().map(String::as_str), Some("140.82.121.3"));
+        assert_eq!(
+            github.source_of("140.82.121.3"),
+            CandidateSource::Imported(source.to_string())
+        );
+        // Built-in IPs keep their tag and are not duplicated
+        assert_eq!(github.source_of("140.82.112.4"), CandidateSource::Builtin);
+        assert_eq!(github.candidate_ips.iter().filter(|ip| *ip == "140.82.112.4").count(), 1);
+        assert_eq!(
+            github.fallback_ip(FamilyPolicy::PreferFastest).map(String::as_str),
+            Some("140.82.112.4")
+        );
+
+        remove_imported_source(source);
+        let domains = get_domain_candidates();
+        let github = domains.iter().find(|d| d.domain == "github.com").unwrap();
+        assert!(!github.candidate_ips.contains(&"140.82.121.3".to_string()));
+    }
+
+    #[test]
+    fn test_family_policy() {
+        assert!(FamilyPolicy::V4Only.allows("140.82.112.4"));
+        assert!(!FamilyPolicy::V4Only.allows("2606:50c0:8000::133"));
+        assert!(FamilyPolicy::V6Only.allows("2606:50c0:8000::133"));
+        assert!(!FamilyPolicy::V6Only.allows("140.82.112.4"));
+        assert!(FamilyPolicy::PreferFastest.allows("2606:50c0:8000::133"));
+        assert!(!FamilyPolicy::PreferFastest.allows("not-an-ip"));
+        assert_eq!(FamilyPolicy::parse("v6"), Some(FamilyPolicy::V6Only));
+        assert_eq!(FamilyPolicy::parse("bogus"), None);
+
+        let raw = get_domain_candidates()
+            .into_iter()
+            .find(|d| d.domain == "raw.githubusercontent.com")
+            .unwrap();
+        assert_eq!(
+            raw.fallback_ip(FamilyPolicy::V6Only).map(String::as_str),
+            Some("2606:50c0:8000::133")
+        );
+        assert_eq!(
+            raw.fallback_ip(FamilyPolicy::PreferFastest).map(String::as_str),
+            Some("185.199.108.133")
+        );
+    }
+
+    #[test]
+    fn test_ipv6_probe_address() {
+        // Connect to a local IPv6 listener to make sure the [addr]:port form is used
+        let listener = match std::net::TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            Err(_) => return, // IPv6 loopback unavailable
+        };
+        let config = ProbeConfig {
+            port: listener.local_addr().unwrap().port(),
+            timeout_ms: 1000,
+            ..ProbeConfig::default()
+        };
+        assert!(test_ip_latency_with("::1", &config).is_some());
+    }
+
+    #[test]
+    fn test_probe_cache_dedup() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cache: Arc<ProbeCache<Option<u64>>> = Arc::new(ProbeCache::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // Several "domains" sharing the same endpoint probe it once
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let calls = Arc::clone(&calls);
+                thread::spawn(move || {
+                    cache.get_or_probe("185.199.108.133", 443, ProbeKind::TcpConnect, || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        Some(42)
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Some(42));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A different port is a different endpoint
+        let other = cache.get_or_probe("185.199.108.133", 80, ProbeKind::TcpConnect, || None);
+        assert_eq!(other, None);
+        assert_eq!(cache.len(), 2);
+
+        // So is another object fetched from the same endpoint
+        let object = |url: &str, value: u64| {
+            cache.get_or_probe_target("185.199.108.133", 443, ProbeKind::Throughput, url, || Some(value))
+        };
+        assert_eq!(object("https://raw.githubusercontent.com/a", 1), Some(1));
+        assert_eq!(object("https://raw.githubusercontent.com/b", 2), Some(2));
+        assert_eq!(object("https://raw.githubusercontent.com/a", 3), Some(1));
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_probe_outcome_classification() {
+        // Nothing listens on a freshly released port
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let config = ProbeConfig {
+            port,
+            ..ProbeConfig::default()
+        };
+        let sample = probe_ip_with("127.0.0.1", &config);
+        assert_eq!(sample.outcome, ProbeOutcome::Refused);
+        assert_eq!(sample.latency_ms(), None);
+
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert_eq!(ProbeOutcome::classify(&timeout), ProbeOutcome::Timeout);
+        let tls = io::Error::new(io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert_eq!(ProbeOutcome::classify(&tls), ProbeOutcome::TlsFailure);
+        for outcome in [ProbeOutcome::Success, ProbeOutcome::Reset, ProbeOutcome::TlsFailure] {
+            assert_eq!(ProbeOutcome::parse(outcome.as_str()), Some(outcome));
+        }
+    }
+
+    #[test]
+    fn test_failure_summary() {
+        use ProbeOutcome::*;
+        let summary = FailureSummary::from_outcomes([Success, Timeout, Refused, Timeout]);
+        assert_eq!(summary.probes, 4);
+        assert_eq!(summary.failed(), 3);
+        assert_eq!(summary.failures, vec![(Timeout, 2), (Refused, 1)]);
+        assert_eq!(summary.describe(), "超时 2, 连接被拒绝 1");
+        assert!(!summary.local_network_down());
+
+        assert!(FailureSummary::from_outcomes([Unreachable, Unreachable]).local_network_down());
+    }
+
     #[test]
     fn test_quality_rating() {
         assert_eq!(get_quality_rating(30), "极佳");
@@ -365,4 +1250,43 @@ mod tests {
         assert_eq!(get_quality_rating(300), "较慢");
         assert_eq!(get_quality_rating(1000), "很慢");
     }
+
+    #[test]
+    fn test_domain_group_matching() {
+        assert_eq!(group_of("gist.github.com").as_deref(), Some("github-web"));
+        assert_eq!(group_of("objects.githubusercontent.com").as_deref(), Some("githubusercontent"));
+        assert_eq!(group_of("githubusercontent.com"), None);
+        assert_eq!(group_of("evilgithubusercontent.com"), None);
+        assert_eq!(group_of("api.github.com"), None);
+
+        let entries = get_domain_candidates();
+        let raw = entries.iter().find(|e| e.domain == "raw.githubusercontent.com").unwrap();
+        assert_eq!(raw.group.as_deref(), Some("githubusercontent"));
+    }
+
+    #[test]
+    fn test_apply_domain_groups() {
+        let ranked = |list: &[(&str, u64)]| list.iter().map(|(ip, l)| (ip.to_string(), *l)).collect::<Vec<_>>();
+        let mut results: RankedResults = HashMap::new();
+        results.insert("github.com".into(), ranked(&[("a", 10), ("b", 20), ("c", 30), ("d", 40)]));
+        // gist prefers c and cannot reach d
+        results.insert("gist.github.com".into(), ranked(&[("c", 12), ("a", 15), ("b", 25)]));
+        results.insert("api.github.com".into(), ranked(&[("x", 5)]));
+
+        apply_domain_groups(&mut results, &domain_groups());
+
+        // Positions: a = 0 + 1, c = 2 + 0, b = 1 + 2; d is not common
+        let order = |results: &RankedResults, domain: &str| {
+            results[domain].iter().map(|(ip, _)| ip.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(order(&results, "github.com"), vec!["a", "c", "b"]);
+        assert_eq!(order(&results, "gist.github.com"), vec!["a", "c", "b"]);
+        assert_eq!(results["gist.github.com"][0].1, 15);
+        assert_eq!(order(&results, "api.github.com"), vec!["x"]);
+
+        // Without a common IP the members stay independent
+        results.insert("gist.github.com".into(), ranked(&[("z", 12)]));
+        apply_domain_groups(&mut results, &domain_groups());
+        assert_eq!(order(&results, "gist.github.com"), vec!["z"]);
+    }
 }
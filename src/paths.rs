@@ -0,0 +1,46 @@
+//! Locations of files the tool keeps between runs
+
+use std::path::PathBuf;
+
+/// Environment variable overriding the data directory
+pub const DATA_DIR_ENV: &str = "FREE_TO_GITHUB_DATA_DIR";
+
+/// Directory for persistent state (history, caches, profiles)
+///
+/// `%APPDATA%\free_to_github` on Windows and `~/.local/share/free_to_github`
+/// elsewhere, matching the debug log location. Unit tests use a per-process
+/// temporary directory so they never touch real user state.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("free_to_github_test_{}", std::process::id()));
+    }
+
+    if cfg!(target_os = "windows") {
+        match std::env::var("APPDATA") {
+            Ok(appdata) => PathBuf::from(appdata).join("free_to_github"),
+            Err(_) => PathBuf::from(".").join(".free_to_github"),
+        }
+    } else {
+        home_dir().join(".local/share/free_to_github")
+    }
+}
+
+/// Home directory of the current user (cross-platform)
+pub fn home_dir() -> PathBuf {
+    let var = if cfg!(target_os = "windows") { "USERPROFILE" } else { "HOME" };
+    std::env::var(var)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("."))
+}
+
+/// Current Unix time in seconds
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,712 @@
+//! JSON-RPC 2.0 control interface over a Unix domain socket
+//!
+//! A running instance (normally the daemon) owns the speed test results and
+//! the hosts state; IDE plugins, status-bar widgets and the GUIs query and
+//! control it through this socket instead of keeping their own copies.
+//! Messages are newline-delimited JSON objects. The methods, their results
+//! and the notifications are documented in `rpcApi.md`.
+//!
+//! Access is limited by the peer's credentials: the owner of the server
+//! process, root and explicitly allowed users or groups. The system service
+//! listens in its state directory; clients look for a per-user instance
+//! first and fall back to that socket.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::network::RankedResults;
+use crate::{daemon, helper, hosts, paths, service};
+
+/// Environment variable overriding the RPC socket path
+pub const SOCKET_ENV: &str = "FREE_TO_GITHUB_RPC_SOCKET";
+
+/// Socket file name inside the data directory
+pub const SOCKET_FILE: &str = "rpc.sock";
+
+/// Standard JSON-RPC error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// The operation itself failed (e.g. the hosts file could not be written)
+pub const OPERATION_FAILED: i64 = -32000;
+
+/// `run_speed_test` while another test is still running
+pub const SPEED_TEST_RUNNING: i64 = -32001;
+
+/// Notification names
+pub const NOTIFY_PROGRESS: &str = "speed_test.progress";
+pub const NOTIFY_FINISHED: &str = "speed_test.finished";
+pub const NOTIFY_STATUS: &str = "status.changed";
+
+/// Largest message accepted on the socket
+#[cfg(unix)]
+const MAX_MESSAGE_BYTES: u64 = 1024 * 1024;
+
+/// How often idle connections and the accept loop check for shutdown (milliseconds)
+#[cfg(unix)]
+const POLL_INTERVAL_MS: u64 = 200;
+
+/// Write timeout, so a stalled subscriber cannot hold up notifications (milliseconds)
+#[cfg(unix)]
+const WRITE_TIMEOUT_MS: u64 = 5000;
+
+/// RPC socket path, honouring [`SOCKET_ENV`]
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| paths::data_dir().join(SOCKET_FILE))
+}
+
+/// Socket of the system service, inside its state directory
+pub fn system_socket_path() -> PathBuf {
+    Path::new(service::SYSTEM_STATE_DIR).join(SOCKET_FILE)
+}
+
+/// Sockets a client tries, in order: this user's instance, then the system
+/// service (only the former when [`SOCKET_ENV`] is set)
+pub fn client_socket_paths() -> Vec<PathBuf> {
+    let mut sockets = vec![socket_path()];
+    if std::env::var_os(SOCKET_ENV).is_none() && sockets[0] != system_socket_path() {
+        sockets.push(system_socket_path());
+    }
+    sockets
+}
+
+/// First socket with a reachable server, if any
+pub fn find_server() -> Option<PathBuf> {
+    client_socket_paths().into_iter().find(|socket| is_running(socket))
+}
+
+/// A JSON-RPC error object
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl From<RpcError> for io::Error {
+    fn from(e: RpcError) -> Self {
+        io::Error::other(format!("{} (code {})", e.message, e.code))
+    }
+}
+
+/// Results in wire format: domains sorted by name, IPs in rank order
+pub fn results_to_json(results: &RankedResults) -> Value {
+    let mut domains: Vec<&String> = results.keys().collect();
+    domains.sort();
+    let domains: Vec<Value> = domains
+        .into_iter()
+        .map(|domain| {
+            let ips: Vec<Value> = results[domain]
+                .iter()
+                .map(|(ip, latency)| json!({ "ip": ip, "latency_ms": latency }))
+                .collect();
+            json!({ "domain": domain, "ips": ips })
+        })
+        .collect();
+    Value::Array(domains)
+}
+
+/// Inverse of [`results_to_json`]
+pub fn results_from_json(value: &Value) -> RankedResults {
+    let mut results = RankedResults::new();
+    for entry in value.as_array().into_iter().flatten() {
+        let Some(domain) = entry["domain"].as_str() else { continue };
+        let ips = entry["ips"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|ip| Some((ip["ip"].as_str()?.to_string(), ip["latency_ms"].as_u64()?)))
+            .collect();
+        results.insert(domain.to_string(), ips);
+    }
+    results
+}
+
+fn status_json() -> Value {
+    let daemon = daemon::status();
+    json!({
+        "enabled": hosts::is_enabled().unwrap_or(false),
+        "can_write_hosts": helper::check_write_access().is_ok(),
+        "has_optimized_ips": hosts::has_optimized_ips(),
+        "results_saved_at": hosts::optimized_ips_saved_at(),
+        "speed_test_running": daemon::is_round_running(),
+        "daemon": {
+            "state": daemon.state.as_str(),
+            "last_run": daemon.last_run,
+            "next_run": daemon.next_run,
+            "last_outcome": daemon.last_outcome,
+            "failures": daemon.failures,
+        },
+    })
+}
+
+fn get_results_json() -> Value {
+    json!({
+        "saved_at": hosts::optimized_ips_saved_at(),
+        "domains": results_to_json(&hosts::ranked_ips()),
+    })
+}
+
+/// Build a request message
+pub fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+/// Build a notification message
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": e.to_json() }),
+    }
+}
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{import, network};
+
+    /// Settings for the RPC server
+    #[derive(Debug, Clone)]
+    pub struct RpcConfig {
+        pub socket: PathBuf,
+        /// Users besides root and the server's own user allowed to connect
+        pub allowed_uids: Vec<u32>,
+        /// Primary groups whose users are allowed to connect
+        pub allowed_gids: Vec<u32>,
+    }
+
+    impl Default for RpcConfig {
+        fn default() -> Self {
+            Self {
+                socket: socket_path(),
+                allowed_uids: Vec::new(),
+                allowed_gids: Vec::new(),
+            }
+        }
+    }
+
+    /// One client connection; writes are serialized so notifications from
+    /// the speed test thread never interleave with responses
+    struct Connection {
+        id: u64,
+        writer: Mutex<UnixStream>,
+    }
+
+    impl Connection {
+        fn send(&self, message: &Value) -> io::Result<()> {
+            let mut line = message.to_string();
+            line.push('\n');
+            self.writer.lock().unwrap().write_all(line.as_bytes())
+        }
+    }
+
+    /// State shared by the accept loop and every connection
+    struct Shared {
+        subscribers: Mutex<Vec<Arc<Connection>>>,
+        stopping: AtomicBool,
+        next_connection: AtomicU64,
+    }
+
+    impl Shared {
+        /// Send a notification to every subscriber plus `extra` (if not subscribed)
+        fn notify(&self, method: &str, params: Value, extra: Option<&Arc<Connection>>) {
+            let message = notification(method, params);
+            let mut subscribers = self.subscribers.lock().unwrap();
+            subscribers.retain(|conn| conn.send(&message).is_ok());
+            if let Some(conn) = extra {
+                if !subscribers.iter().any(|s| s.id == conn.id) {
+                    let _ = conn.send(&message);
+                }
+            }
+        }
+
+        fn dispatch(self: &Arc<Self>, conn: &Arc<Connection>, method: &str, params: &Value) -> Result<Value, RpcError> {
+            let failed = |e: io::Error| RpcError::new(OPERATION_FAILED, e.to_string());
+            match method {
+                "status" => Ok(status_json()),
+                "get_results" => Ok(get_results_json()),
+                "subscribe" => {
+                    let mut subscribers = self.subscribers.lock().unwrap();
+                    if !subscribers.iter().any(|s| s.id == conn.id) {
+                        subscribers.push(Arc::clone(conn));
+                    }
+                    Ok(json!({ "subscribed": true }))
+                }
+                "enable" => {
+                    let optimized = match params.get("mode").and_then(Value::as_str) {
+                        None | Some("optimized") => true,
+                        Some("default") => false,
+                        Some(other) => {
+                            return Err(RpcError::new(INVALID_PARAMS, format!("unknown mode: {}", other)))
+                        }
+                    };
+                    let result = if optimized { helper::enable_optimized() } else { helper::enable() };
+                    result.map_err(failed)?;
+                    let optimized = optimized && hosts::has_optimized_ips();
+                    self.notify(NOTIFY_STATUS, json!({ "enabled": true }), None);
+                    Ok(json!({ "enabled": true, "optimized": optimized }))
+                }
+                "disable" => {
+                    helper::disable().map_err(failed)?;
+                    self.notify(NOTIFY_STATUS, json!({ "enabled": false }), None);
+                    Ok(json!({ "enabled": false }))
+                }
+                "run_speed_test" => {
+                    // One guard for the daemon's own rounds and requested ones
+                    let Some(guard) = daemon::try_begin_round() else {
+                        return Err(RpcError::new(SPEED_TEST_RUNNING, "a speed test is already running"));
+                    };
+                    self.spawn_speed_test(Arc::clone(conn), guard);
+                    Ok(json!({ "started": true }))
+                }
+                _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+            }
+        }
+
+        fn spawn_speed_test(self: &Arc<Self>, caller: Arc<Connection>, guard: daemon::RoundGuard) {
+            let shared = Arc::clone(self);
+            thread::spawn(move || {
+                let progress = {
+                    let shared = Arc::clone(&shared);
+                    let caller = Arc::clone(&caller);
+                    let progress: network::SharedProgressCallback = Arc::new(move |done: usize, total: usize, domain: &str| {
+                        shared.notify(
+                            NOTIFY_PROGRESS,
+                            json!({ "done": done, "total": total, "domain": domain }),
+                            Some(&caller),
+                        );
+                    });
+                    progress
+                };
+                let network_down = || {
+                    let failures = network::last_failure_summary();
+                    let mut error = format!("every probe failed ({})", failures.describe());
+                    if failures.local_network_down() {
+                        error.push_str(", the local network seems down");
+                    }
+                    json!({ "error": error })
+                };
+
+                // A daemon in this process runs the test as one of its rounds,
+                // so its hysteresis decides what reaches the hosts file
+                let mut finished = match daemon::run_round_now(&guard, Some(Arc::clone(&progress))) {
+                    Some(Ok(daemon::RoundOutcome::NetworkDown)) => network_down(),
+                    Some(Ok(outcome)) => json!({ "outcome": outcome.describe() }),
+                    Some(Err(e)) => json!({ "error": format!("applying the results failed: {}", e) }),
+                    None => {
+                        import::import_configured();
+                        let results = network::test_all_domains_ranked(Some(progress));
+                        // A run where every probe failed must not replace good results
+                        if results.values().all(|ranked| ranked.is_empty()) {
+                            network_down()
+                        } else {
+                            hosts::set_ranked_ips(results);
+                            json!({})
+                        }
+                    }
+                };
+                let current = get_results_json();
+                finished["saved_at"] = current["saved_at"].clone();
+                finished["domains"] = current["domains"].clone();
+                drop(guard);
+                shared.notify(NOTIFY_FINISHED, finished, Some(&caller));
+            });
+        }
+
+        /// Answer one message; `None` for notifications sent by the client
+        fn handle_message(self: &Arc<Self>, conn: &Arc<Connection>, line: &[u8]) -> Option<Value> {
+            let message: Value = match serde_json::from_slice(line) {
+                Ok(message) => message,
+                Err(e) => return Some(response(&Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))),
+            };
+
+            let id = message.get("id").cloned();
+            let method = message.get("method").and_then(Value::as_str);
+            let (Some(method), Some("2.0")) = (method, message.get("jsonrpc").and_then(Value::as_str)) else {
+                let error = RpcError::new(INVALID_REQUEST, "expected a JSON-RPC 2.0 request");
+                return Some(response(&id.unwrap_or(Value::Null), Err(error)));
+            };
+
+            let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+            let result = self.dispatch(conn, method, &params);
+            id.map(|id| response(&id, result))
+        }
+    }
+
+    /// The RPC server: one thread per client so subscribers can stay connected
+    pub struct RpcServer {
+        listener: UnixListener,
+        config: RpcConfig,
+        shared: Arc<Shared>,
+    }
+
+    impl RpcServer {
+        /// Bind the socket, replacing a stale one left by a previous run
+        pub fn bind(config: RpcConfig) -> io::Result<Self> {
+            if let Some(parent) = config.socket.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if config.socket.exists() {
+                if UnixStream::connect(&config.socket).is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("another instance is listening on {}", config.socket.display()),
+                    ));
+                }
+                fs::remove_file(&config.socket)?;
+            }
+
+            let listener = UnixListener::bind(&config.socket)?;
+            // Other users are checked per connection, but only if they can connect at all
+            let mode = if config.allowed_uids.is_empty() && config.allowed_gids.is_empty() { 0o600 } else { 0o666 };
+            fs::set_permissions(&config.socket, fs::Permissions::from_mode(mode))?;
+            listener.set_nonblocking(true)?;
+
+            Ok(Self {
+                listener,
+                config,
+                shared: Arc::new(Shared {
+                    subscribers: Mutex::new(Vec::new()),
+                    stopping: AtomicBool::new(false),
+                    next_connection: AtomicU64::new(0),
+                }),
+            })
+        }
+
+        pub fn socket(&self) -> &Path {
+            &self.config.socket
+        }
+
+        fn is_authorized(&self, peer: helper::PeerCredentials) -> bool {
+            peer.uid == 0
+                || peer.uid == helper::current_uid()
+                || self.config.allowed_uids.contains(&peer.uid)
+                || self.config.allowed_gids.contains(&peer.gid)
+        }
+
+        /// Serve clients until `stop` is set, then remove the socket
+        pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+            while !stop.load(Ordering::Relaxed) {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        let authorized = helper::peer_credentials(&stream)
+                            .map(|peer| self.is_authorized(peer))
+                            .unwrap_or(false);
+                        if !authorized {
+                            #[cfg(debug_assertions)]
+                            log::warn!("RPC: refused connection from another user");
+                            continue;
+                        }
+                        if let Err(_e) = self.spawn_connection(stream) {
+                            #[cfg(debug_assertions)]
+                            log::warn!("RPC: failed to set up connection: {}", _e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            self.shared.stopping.store(true, Ordering::Relaxed);
+            let _ = fs::remove_file(&self.config.socket);
+            Ok(())
+        }
+
+        fn spawn_connection(&self, stream: UnixStream) -> io::Result<()> {
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS)))?;
+            stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS)))?;
+            let conn = Arc::new(Connection {
+                id: self.shared.next_connection.fetch_add(1, Ordering::Relaxed),
+                writer: Mutex::new(stream.try_clone()?),
+            });
+            let shared = Arc::clone(&self.shared);
+            thread::spawn(move || serve_connection(shared, conn, stream));
+            Ok(())
+        }
+    }
+
+    fn serve_connection(shared: Arc<Shared>, conn: Arc<Connection>, stream: UnixStream) {
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        loop {
+            let limit = MAX_MESSAGE_BYTES.saturating_sub(line.len() as u64);
+            match (&mut reader).take(limit).read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) if !line.ends_with(b"\n") && line.len() as u64 >= MAX_MESSAGE_BYTES => {
+                    let error = RpcError::new(INVALID_REQUEST, "message too large");
+                    let _ = conn.send(&response(&Value::Null, Err(error)));
+                    break;
+                }
+                Ok(_) if !line.ends_with(b"\n") => continue,
+                Ok(_) => {
+                    if !line.iter().all(u8::is_ascii_whitespace) {
+                        if let Some(reply) = shared.handle_message(&conn, &line) {
+                            if conn.send(&reply).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    line.clear();
+                }
+                // Partial data stays in `line`, so timeouts just poll for shutdown
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    if shared.stopping.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        shared.subscribers.lock().unwrap().retain(|s| s.id != conn.id);
+    }
+
+    /// Client side of the interface
+    pub struct RpcClient {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+        next_id: u64,
+    }
+
+    impl RpcClient {
+        pub fn connect(socket: &Path) -> io::Result<Self> {
+            let writer = UnixStream::connect(socket)?;
+            let reader = BufReader::new(writer.try_clone()?);
+            Ok(Self { reader, writer, next_id: 1 })
+        }
+
+        fn read_message(&mut self) -> io::Result<Value> {
+            let mut line = Vec::new();
+            if (&mut self.reader).take(MAX_MESSAGE_BYTES).read_until(b'\n', &mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "RPC server closed the connection"));
+            }
+            serde_json::from_slice(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+
+        /// Wait for the next notification: `(method, params)`
+        pub fn next_notification(&mut self) -> io::Result<(String, Value)> {
+            loop {
+                let message = self.read_message()?;
+                if message.get("id").is_none() {
+                    let method = message["method"].as_str().unwrap_or_default().to_string();
+                    return Ok((method, message["params"].clone()));
+                }
+            }
+        }
+
+        /// Call a method, passing notifications that arrive meanwhile to `on_notification`
+        pub fn call_with(
+            &mut self,
+            method: &str,
+            params: Value,
+            mut on_notification: impl FnMut(&str, &Value),
+        ) -> io::Result<Value> {
+            let id = self.next_id;
+            self.next_id += 1;
+            let mut line = request(id, method, params).to_string();
+            line.push('\n');
+            self.writer.write_all(line.as_bytes())?;
+
+            loop {
+                let message = self.read_message()?;
+                match message.get("id") {
+                    None => on_notification(message["method"].as_str().unwrap_or_default(), &message["params"]),
+                    Some(got) if got.as_u64() == Some(id) => {
+                        if let Some(error) = message.get("error") {
+                            let code = error["code"].as_i64().unwrap_or(OPERATION_FAILED);
+                            let text = error["message"].as_str().unwrap_or("unknown error");
+                            return Err(RpcError::new(code, text).into());
+                        }
+                        return Ok(message["result"].clone());
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        /// Call a method, ignoring notifications
+        pub fn call(&mut self, method: &str, params: Value) -> io::Result<Value> {
+            self.call_with(method, params, |_, _| {})
+        }
+
+        /// Run a speed test on the server, reporting progress until it finishes
+        ///
+        /// Fails when the server reports that every probe failed; its stored
+        /// results are unchanged in that case.
+        pub fn run_speed_test(&mut self, mut progress: impl FnMut(usize, usize, &str)) -> io::Result<RankedResults> {
+            let mut finished = None;
+            self.call_with("run_speed_test", json!({}), |method, params| {
+                speed_test_event(method, params, &mut progress, &mut finished)
+            })?;
+            while finished.is_none() {
+                let (method, params) = self.next_notification()?;
+                speed_test_event(&method, &params, &mut progress, &mut finished);
+            }
+            finished.unwrap_or_else(|| Ok(RankedResults::new()))
+        }
+    }
+
+    fn speed_test_event(
+        method: &str,
+        params: &Value,
+        progress: &mut impl FnMut(usize, usize, &str),
+        finished: &mut Option<io::Result<RankedResults>>,
+    ) {
+        match method {
+            NOTIFY_PROGRESS => progress(
+                params["done"].as_u64().unwrap_or(0) as usize,
+                params["total"].as_u64().unwrap_or(0) as usize,
+                params["domain"].as_str().unwrap_or_default(),
+            ),
+            NOTIFY_FINISHED => {
+                *finished = Some(match params["error"].as_str() {
+                    Some(error) => Err(io::Error::other(error.to_string())),
+                    None => Ok(results_from_json(&params["domains"])),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether an instance answers on the socket
+    pub fn is_running(socket: &Path) -> bool {
+        UnixStream::connect(socket).is_ok()
+    }
+
+    /// One-shot call over a fresh connection
+    pub fn call(socket: &Path, method: &str, params: Value) -> io::Result<Value> {
+        RpcClient::connect(socket)?.call(method, params)
+    }
+
+    /// One-shot speed test over a fresh connection
+    pub fn run_speed_test(socket: &Path, progress: impl FnMut(usize, usize, &str)) -> io::Result<RankedResults> {
+        RpcClient::connect(socket)?.run_speed_test(progress)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_running(_socket: &Path) -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "the RPC interface needs Unix domain sockets")
+}
+
+#[cfg(not(unix))]
+pub fn call(_socket: &Path, _method: &str, _params: Value) -> io::Result<Value> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub fn run_speed_test(
+    _socket: &Path,
+    _progress: impl FnMut(usize, usize, &str),
+) -> io::Result<RankedResults> {
+    Err(unsupported())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_json_roundtrip() {
+        let mut results = RankedResults::new();
+        results.insert(
+            "github.com".to_string(),
+            vec![("140.82.112.3".to_string(), 40), ("140.82.112.4".to_string(), 55)],
+        );
+        results.insert("api.github.com".to_string(), vec![("140.82.112.6".to_string(), 42)]);
+
+        let value = results_to_json(&results);
+        assert_eq!(value[0]["domain"], "api.github.com");
+        assert_eq!(value[1]["ips"][1]["latency_ms"], 55);
+        assert_eq!(results_from_json(&value), results);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rpc_server() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixStream;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        let dir = paths::data_dir().join("rpc_server");
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = RpcServer::bind(RpcConfig {
+            socket: dir.join(SOCKET_FILE),
+            ..RpcConfig::default()
+        })
+        .unwrap();
+        let socket = server.socket().to_path_buf();
+        assert!(RpcServer::bind(RpcConfig { socket: socket.clone(), ..RpcConfig::default() }).is_err());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let handle = thread::spawn(move || server.serve(&flag));
+
+        let status = call(&socket, "status", json!({})).unwrap();
+        assert_eq!(status["speed_test_running"], false);
+        assert!(status["daemon"]["state"].is_string());
+        assert!(call(&socket, "get_results", json!({})).unwrap()["domains"].is_array());
+        assert!(call(&socket, "enable", json!({ "mode": "fastest" })).is_err());
+
+        let mut client = RpcClient::connect(&socket).unwrap();
+        assert_eq!(client.call("subscribe", json!({})).unwrap()["subscribed"], true);
+        let err = client.call("reboot", json!({})).unwrap_err();
+        assert!(err.to_string().contains(&METHOD_NOT_FOUND.to_string()));
+
+        // Malformed input gets a parse error and the connection stays usable
+        let mut raw = UnixStream::connect(&socket).unwrap();
+        raw.write_all(b"{not json\n{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"status\"}\n").unwrap();
+        let mut reader = BufReader::new(raw);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["error"]["code"], PARSE_ERROR);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["id"], 7);
+
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
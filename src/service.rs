@@ -0,0 +1,467 @@
+//! systemd integration for the background modes
+//!
+//! `service install` writes and registers a unit so the chosen mode survives
+//! reboots: a system unit for hosts mode (it has to write `/etc/hosts`), or a
+//! user unit for the unprivileged proxy and DNS modes. Hosts mode can instead
+//! use a timer that runs one re-test round periodically. Units carry
+//! sandboxing options limiting them to what each mode needs. Everything is
+//! written below `root`, so tests (and image builders) can target a scratch
+//! directory; systemctl is only invoked when `root` is `/`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::paths;
+
+/// Prefix shared by every unit this tool installs
+const UNIT_PREFIX: &str = "free-to-github";
+
+/// Data directory of the system unit (managed by systemd's `StateDirectory=`)
+pub const SYSTEM_STATE_DIR: &str = "/var/lib/free-to-github";
+
+/// Which background mode the service runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceMode {
+    /// Daemon rewriting the hosts block (system unit, runs as root)
+    Hosts,
+    /// Local CONNECT proxy (user unit)
+    Proxy,
+    /// Local DNS server (user unit)
+    Dns,
+}
+
+impl ServiceMode {
+    pub const ALL: [ServiceMode; 3] = [ServiceMode::Hosts, ServiceMode::Proxy, ServiceMode::Dns];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceMode::Hosts => "hosts",
+            ServiceMode::Proxy => "proxy",
+            ServiceMode::Dns => "dns",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<ServiceMode> {
+        ServiceMode::ALL.into_iter().find(|m| m.as_str() == s)
+    }
+
+    /// Whether the unit is a user unit rather than a system unit
+    pub fn is_user_unit(&self) -> bool {
+        *self != ServiceMode::Hosts
+    }
+
+    fn service_name(&self) -> String {
+        match self {
+            ServiceMode::Hosts => format!("{}.service", UNIT_PREFIX),
+            _ => format!("{}-{}.service", UNIT_PREFIX, self.as_str()),
+        }
+    }
+}
+
+/// Long-running daemon or periodic one-shot runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServiceKind {
+    #[default]
+    Daemon,
+    /// A timer starting one re-test round (hosts mode only)
+    Timer,
+}
+
+/// Where and what to install
+#[derive(Debug, Clone)]
+pub struct ServiceOptions {
+    pub mode: ServiceMode,
+    pub kind: ServiceKind,
+    /// Filesystem root units are written below (`/` for the live system)
+    pub root: PathBuf,
+    /// CLI binary the units run
+    pub exe: PathBuf,
+    /// Home directory of the user owning user units
+    pub home: PathBuf,
+    /// Minutes between timer runs
+    pub timer_interval_minutes: u64,
+    /// Extra arguments of the hosts-mode daemon (e.g. `--rpc-allow-uid 1000`)
+    pub daemon_args: Vec<String>,
+}
+
+impl ServiceOptions {
+    pub fn new(mode: ServiceMode) -> Self {
+        Self {
+            mode,
+            kind: ServiceKind::Daemon,
+            root: PathBuf::from("/"),
+            exe: std::env::current_exe().unwrap_or_else(|_| PathBuf::from("/usr/local/bin/free_to_github_cli")),
+            home: paths::home_dir(),
+            timer_interval_minutes: 30,
+            daemon_args: Vec::new(),
+        }
+    }
+
+    fn is_live_root(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Directory the mode's units go to, below `root`
+    pub fn unit_dir(&self) -> PathBuf {
+        if self.mode.is_user_unit() {
+            under_root(&self.root, &self.home.join(".config/systemd/user"))
+        } else {
+            self.root.join("etc/systemd/system")
+        }
+    }
+}
+
+fn under_root(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Name of the one-shot service started by the timer
+fn retest_service_name() -> String {
+    format!("{}-retest.service", UNIT_PREFIX)
+}
+
+fn timer_name() -> String {
+    format!("{}-retest.timer", UNIT_PREFIX)
+}
+
+/// Every unit file name that may exist for a mode
+pub fn unit_names(mode: ServiceMode) -> Vec<String> {
+    let mut names = vec![mode.service_name()];
+    if mode == ServiceMode::Hosts {
+        names.push(retest_service_name());
+        names.push(timer_name());
+    }
+    names
+}
+
+/// Sandboxing for the root-owned hosts unit: it may only write the hosts
+/// file and its own state directory
+const SYSTEM_HARDENING: &str = "\
+NoNewPrivileges=yes
+ProtectSystem=strict
+ReadWritePaths=/etc/hosts
+ProtectHome=read-only
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+CapabilityBoundingSet=
+RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX AF_NETLINK
+";
+
+/// Sandboxing options available to unprivileged user units
+const USER_HARDENING: &str = "\
+NoNewPrivileges=yes
+LockPersonality=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX
+";
+
+/// Generate `(file name, content)` for every unit the options install
+pub fn generate_units(options: &ServiceOptions) -> io::Result<Vec<(String, String)>> {
+    let exe = options.exe.display();
+    let description = "Free to GitHub";
+
+    if options.kind == ServiceKind::Timer {
+        if options.mode != ServiceMode::Hosts {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "timer units are only available for hosts mode",
+            ));
+        }
+        let service = format!(
+            "[Unit]\nDescription={} re-test (one round)\nWants=network-online.target\nAfter=network-online.target\n\n\
+             [Service]\nType=oneshot\nExecStart={} daemon --once\nEnvironment={}={}\nStateDirectory={}\n{}",
+            description,
+            exe,
+            paths::DATA_DIR_ENV,
+            SYSTEM_STATE_DIR,
+            UNIT_PREFIX,
+            SYSTEM_HARDENING
+        );
+        let timer = format!(
+            "[Unit]\nDescription={} periodic re-test\n\n\
+             [Timer]\nOnBootSec=5min\nOnUnitActiveSec={}min\nRandomizedDelaySec=2min\nPersistent=true\n\n\
+             [Install]\nWantedBy=timers.target\n",
+            description, options.timer_interval_minutes
+        );
+        return Ok(vec![(retest_service_name(), service), (timer_name(), timer)]);
+    }
+
+    let service = match options.mode {
+        ServiceMode::Hosts => format!(
+            "[Unit]\nDescription={} background re-test\nWants=network-online.target\nAfter=network-online.target\n\n\
+             [Service]\nType=simple\nExecStart={} daemon{}\nRestart=on-failure\nRestartSec=30\n\
+             Environment={}={}\nStateDirectory={}\n{}\n\
+             [Install]\nWantedBy=multi-user.target\n",
+            description,
+            exe,
+            options.daemon_args.iter().map(|arg| format!(" {}", arg)).collect::<String>(),
+            paths::DATA_DIR_ENV,
+            SYSTEM_STATE_DIR,
+            UNIT_PREFIX,
+            SYSTEM_HARDENING
+        ),
+        ServiceMode::Proxy | ServiceMode::Dns => format!(
+            "[Unit]\nDescription={} local {}\n\n\
+             [Service]\nType=simple\nExecStart={} {}\nRestart=on-failure\nRestartSec=5\n{}\n\
+             [Install]\nWantedBy=default.target\n",
+            description,
+            options.mode.as_str(),
+            exe,
+            options.mode.as_str(),
+            USER_HARDENING
+        ),
+    };
+    Ok(vec![(options.mode.service_name(), service)])
+}
+
+/// Unit started after installation (the timer in timer mode)
+fn primary_unit(options: &ServiceOptions) -> String {
+    match options.kind {
+        ServiceKind::Timer => timer_name(),
+        ServiceKind::Daemon => options.mode.service_name(),
+    }
+}
+
+fn systemctl(user: bool, args: &[&str]) -> io::Result<String> {
+    let mut command = Command::new("systemctl");
+    if user {
+        command.arg("--user");
+    }
+    let output = command.args(args).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(io::Error::other(format!("systemctl {} failed: {}", args.join(" "), stderr)))
+    }
+}
+
+/// Write the units and, on the live system, enable and start them
+///
+/// Installing one kind removes the other (daemon vs. timer) so the two never
+/// run side by side. Returns the paths written.
+pub fn install(options: &ServiceOptions) -> io::Result<Vec<PathBuf>> {
+    let units = generate_units(options)?;
+    remove_units(options)?;
+
+    let dir = options.unit_dir();
+    fs::create_dir_all(&dir)?;
+    let mut written = Vec::new();
+    for (name, content) in units {
+        let path = dir.join(&name);
+        fs::write(&path, content)?;
+        written.push(path);
+    }
+
+    if options.is_live_root() {
+        let user = options.mode.is_user_unit();
+        systemctl(user, &["daemon-reload"])?;
+        systemctl(user, &["enable", "--now", &primary_unit(options)])?;
+    }
+    Ok(written)
+}
+
+/// Stop, disable and remove every unit of the mode, with the `*.wants` links
+/// left below an alternate root
+fn remove_units(options: &ServiceOptions) -> io::Result<Vec<PathBuf>> {
+    let dir = options.unit_dir();
+    let user = options.mode.is_user_unit();
+    let names = unit_names(options.mode);
+
+    if options.is_live_root() {
+        for name in &names {
+            if dir.join(name).exists() {
+                // A unit that is not loaded cannot be disabled; removal still proceeds
+                let _ = systemctl(user, &["disable", "--now", name]);
+            }
+        }
+    }
+
+    let mut removed = Vec::new();
+    for name in &names {
+        let path = dir.join(name);
+        if path.exists() {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for wants in entries.flatten().filter(|e| e.file_name().to_string_lossy().ends_with(".wants")) {
+                let link = wants.path().join(name);
+                if link.symlink_metadata().is_ok() {
+                    fs::remove_file(&link)?;
+                    removed.push(link);
+                }
+            }
+        }
+    }
+
+    if options.is_live_root() && !removed.is_empty() {
+        systemctl(user, &["daemon-reload"])?;
+    }
+    Ok(removed)
+}
+
+/// Stop, disable and remove every unit of the mode
+///
+/// When this call removed hosts-mode units, that unit's state directory goes
+/// too. The hosts block itself is left alone.
+pub fn uninstall(options: &ServiceOptions) -> io::Result<Vec<PathBuf>> {
+    let mut removed = remove_units(options)?;
+
+    // The system unit keeps its status, cache and history in its state
+    // directory; it goes only with the unit, and only if this user may delete it
+    if options.mode == ServiceMode::Hosts && !removed.is_empty() {
+        let state_dir = under_root(&options.root, Path::new(SYSTEM_STATE_DIR));
+        if state_dir.exists() {
+            match fs::remove_dir_all(&state_dir) {
+                Ok(()) => removed.push(state_dir),
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    #[cfg(debug_assertions)]
+                    log::warn!("Service: keeping {}: {}", state_dir.display(), e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// State of one installed unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitStatus {
+    pub name: String,
+    pub path: PathBuf,
+    /// `systemctl is-active` output on the live system
+    pub active: Option<String>,
+}
+
+/// Installed units of a mode
+pub fn status(options: &ServiceOptions) -> Vec<UnitStatus> {
+    let dir = options.unit_dir();
+    unit_names(options.mode)
+        .into_iter()
+        .map(|name| (dir.join(&name), name))
+        .filter(|(path, _)| path.exists())
+        .map(|(path, name)| {
+            let active = options.is_live_root().then(|| {
+                // is-active exits non-zero for inactive units but still prints the state
+                let mut command = Command::new("systemctl");
+                if options.mode.is_user_unit() {
+                    command.arg("--user");
+                }
+                command
+                    .args(["is-active", &name])
+                    .output()
+                    .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string())
+            });
+            UnitStatus { name, path, active }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_options(mode: ServiceMode, kind: ServiceKind) -> ServiceOptions {
+        let root = paths::data_dir().join(format!("service_root_{}_{:?}", mode.as_str(), kind));
+        let _ = fs::remove_dir_all(&root);
+        ServiceOptions {
+            mode,
+            kind,
+            root,
+            exe: PathBuf::from("/usr/bin/free_to_github_cli"),
+            home: PathBuf::from("/home/dev"),
+            timer_interval_minutes: 45,
+            daemon_args: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_hosts_unit_is_hardened() {
+        let options = scratch_options(ServiceMode::Hosts, ServiceKind::Daemon);
+        let units = generate_units(&options).unwrap();
+        assert_eq!(units.len(), 1);
+        let (name, content) = &units[0];
+        assert_eq!(name, "free-to-github.service");
+        assert!(content.contains("ExecStart=/usr/bin/free_to_github_cli daemon\n"));
+        assert!(content.contains("ProtectSystem=strict"));
+        assert!(content.contains("ReadWritePaths=/etc/hosts"));
+        assert!(content.contains("WantedBy=multi-user.target"));
+
+        let allowed = ServiceOptions { daemon_args: vec!["--rpc-allow-uid".to_string(), "1000".to_string()], ..options };
+        let units = generate_units(&allowed).unwrap();
+        assert!(units[0].1.contains("ExecStart=/usr/bin/free_to_github_cli daemon --rpc-allow-uid 1000\n"));
+
+        let proxy = generate_units(&scratch_options(ServiceMode::Proxy, ServiceKind::Daemon)).unwrap();
+        assert!(proxy[0].1.contains("ExecStart=/usr/bin/free_to_github_cli proxy\n"));
+        assert!(proxy[0].1.contains("WantedBy=default.target"));
+        assert!(!proxy[0].1.contains("ProtectSystem"));
+
+        let timer = scratch_options(ServiceMode::Dns, ServiceKind::Timer);
+        assert!(generate_units(&timer).is_err());
+    }
+
+    #[test]
+    fn test_install_and_uninstall_under_alternate_root() {
+        let options = scratch_options(ServiceMode::Hosts, ServiceKind::Timer);
+
+        // Without hosts units to remove, the state directory is left alone
+        let state_dir = options.root.join("var/lib/free-to-github");
+        fs::create_dir_all(&state_dir).unwrap();
+        assert!(uninstall(&options).unwrap().is_empty());
+        assert!(state_dir.exists());
+
+        let written = install(&options).unwrap();
+        let dir = options.root.join("etc/systemd/system");
+        assert_eq!(written, vec![dir.join("free-to-github-retest.service"), dir.join("free-to-github-retest.timer")]);
+        let timer = fs::read_to_string(dir.join("free-to-github-retest.timer")).unwrap();
+        assert!(timer.contains("OnUnitActiveSec=45min"));
+        assert_eq!(status(&options).len(), 2);
+
+        // Switching to the daemon removes the timer units but keeps the state
+        fs::write(state_dir.join("daemon.status"), "state=idle\n").unwrap();
+        let daemon = ServiceOptions { kind: ServiceKind::Daemon, ..options.clone() };
+        install(&daemon).unwrap();
+        assert!(!dir.join("free-to-github-retest.timer").exists());
+        assert!(state_dir.join("daemon.status").exists());
+
+        // Enablement links (as systemctl --root would create) are cleaned up too
+        let wants = dir.join("multi-user.target.wants");
+        fs::create_dir_all(&wants).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("free-to-github.service"), wants.join("free-to-github.service")).unwrap();
+
+        uninstall(&daemon).unwrap();
+        assert!(status(&daemon).is_empty());
+        assert!(!state_dir.exists());
+        assert!(fs::read_dir(&wants).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_user_unit_location() {
+        let options = scratch_options(ServiceMode::Dns, ServiceKind::Daemon);
+        let written = install(&options).unwrap();
+        assert_eq!(written, vec![options.root.join("home/dev/.config/systemd/user/free-to-github-dns.service")]);
+        assert_eq!(uninstall(&options).unwrap(), written);
+    }
+}
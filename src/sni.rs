@@ -0,0 +1,357 @@
+//! Transparent SNI-routing TCP forwarder
+//!
+//! For tools that ignore proxy variables. Managed names are pointed at this
+//! forwarder (e.g. `127.0.0.1 github.com` with the forwarder on port 443, or a
+//! firewall redirect); it reads the server name from the TLS ClientHello
+//! without terminating TLS and splices the stream to the best IP for that
+//! name from the optimized table. When an IP does not accept the connection
+//! the next-ranked one is tried, which gives connection-level failover that
+//! hosts entries cannot.
+
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::localproxy::{self, ConnectionRecord, Route};
+use crate::proxy::ProxyConfig;
+use crate::{hosts, logger, network};
+
+/// Default listen address; port 443 needs root on most systems
+pub const DEFAULT_BIND: &str = "127.0.0.1:8443";
+
+/// Timeout for each upstream connect attempt (milliseconds)
+const CONNECT_TIMEOUT_MS: u64 = 5000;
+
+/// Timeout for receiving the ClientHello (milliseconds)
+const HELLO_TIMEOUT_MS: u64 = 10_000;
+
+/// Largest TLS record (2^14 bytes plus expansion allowance)
+const MAX_RECORD_LEN: usize = 16384 + 2048;
+
+/// How often the accept loop checks for a stop request (milliseconds)
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// How often results saved by other processes are picked up (seconds)
+const RELOAD_INTERVAL_SECS: u64 = 30;
+
+/// Settings for the SNI forwarder
+#[derive(Debug, Clone)]
+pub struct SniConfig {
+    pub bind: SocketAddr,
+    /// Port connected to on the selected IP
+    pub target_port: u16,
+    pub connect_timeout_ms: u64,
+    /// Corporate proxy outgoing connections go through, if any
+    pub upstream: Option<ProxyConfig>,
+}
+
+impl Default for SniConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.parse().expect("valid default bind address"),
+            target_port: 443,
+            connect_timeout_ms: CONNECT_TIMEOUT_MS,
+            upstream: network::probe_config().proxy,
+        }
+    }
+}
+
+/// Extract the server name from a TLS record holding a ClientHello
+///
+/// Returns `None` when the bytes are not a ClientHello or carry no SNI.
+pub fn parse_sni(record: &[u8]) -> Option<String> {
+    // Record header: handshake content type, version, length
+    if *record.first()? != 0x16 {
+        return None;
+    }
+    let len = u16::from_be_bytes([*record.get(3)?, *record.get(4)?]) as usize;
+    let hello = record.get(5..5 + len)?;
+
+    // Handshake header: ClientHello and a 24-bit length
+    if *hello.first()? != 0x01 {
+        return None;
+    }
+    let mut pos = 4;
+    // client_version and random
+    pos += 2 + 32;
+    let session_id_len = *hello.get(pos)? as usize;
+    pos += 1 + session_id_len;
+    let suites_len = u16::from_be_bytes([*hello.get(pos)?, *hello.get(pos + 1)?]) as usize;
+    pos += 2 + suites_len;
+    let compression_len = *hello.get(pos)? as usize;
+    pos += 1 + compression_len;
+
+    let extensions_len = u16::from_be_bytes([*hello.get(pos)?, *hello.get(pos + 1)?]) as usize;
+    pos += 2;
+    let extensions = hello.get(pos..pos + extensions_len)?;
+
+    let mut pos = 0;
+    while pos + 4 <= extensions.len() {
+        let kind = u16::from_be_bytes([extensions[pos], extensions[pos + 1]]);
+        let len = u16::from_be_bytes([extensions[pos + 2], extensions[pos + 3]]) as usize;
+        let data = extensions.get(pos + 4..pos + 4 + len)?;
+        pos += 4 + len;
+        if kind != 0x0000 {
+            continue;
+        }
+
+        // server_name_list: list length, then (name_type, length, name) entries
+        let mut entry = 2;
+        while entry + 3 <= data.len() {
+            let name_type = data[entry];
+            let name_len = u16::from_be_bytes([data[entry + 1], data[entry + 2]]) as usize;
+            let name = data.get(entry + 3..entry + 3 + name_len)?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(|n| n.to_ascii_lowercase());
+            }
+            entry += 3 + name_len;
+        }
+        return None;
+    }
+    None
+}
+
+/// Read the first TLS record from the client
+fn read_client_hello(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut record = vec![0u8; 5];
+    stream.read_exact(&mut record)?;
+    let len = u16::from_be_bytes([record[3], record[4]]) as usize;
+    if record[0] != 0x16 || len > MAX_RECORD_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a TLS handshake"));
+    }
+    record.resize(5 + len, 0);
+    stream.read_exact(&mut record[5..])?;
+    Ok(record)
+}
+
+/// Resolver used for server names
+pub type Resolver = dyn Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync;
+
+/// SNI-routing forwarder
+pub struct SniForwarder {
+    listener: TcpListener,
+    config: SniConfig,
+    resolve: Arc<Resolver>,
+    log: Arc<localproxy::ConnectionLogger>,
+    /// Reload the optimized IP cache written by other processes
+    reload_cache: bool,
+}
+
+impl SniForwarder {
+    /// Bind the listen address, routing managed names by the optimized IPs
+    pub fn bind(config: SniConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.bind)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            config,
+            resolve: Arc::new(hosts::managed_ips),
+            log: Arc::new(|_| {}),
+            reload_cache: true,
+        })
+    }
+
+    /// Use a different resolver for server names
+    pub fn with_resolver<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync + 'static,
+    {
+        self.resolve = Arc::new(resolve);
+        self.reload_cache = false;
+        self
+    }
+
+    /// Receive a record for every finished connection
+    pub fn with_logger<F>(mut self, log: F) -> Self
+    where
+        F: Fn(&ConnectionRecord) + Send + Sync + 'static,
+    {
+        self.log = Arc::new(log);
+        self
+    }
+
+    /// Address actually bound (useful with port 0)
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept clients until `stop` is set, one thread per connection
+    pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+        let mut last_reload = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            if self.reload_cache && last_reload.elapsed() >= Duration::from_secs(RELOAD_INTERVAL_SECS) {
+                hosts::reload_optimized_ips();
+                last_reload = Instant::now();
+            }
+
+            let (client, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let config = self.config.clone();
+            let resolve = Arc::clone(&self.resolve);
+            let log = Arc::clone(&self.log);
+            thread::spawn(move || {
+                if let Some(record) = handle_client(client, peer, &config, &*resolve) {
+                    logger::log_connection_metrics(&record.host, u128::from(record.connect_ms), record.error.is_none());
+                    log(&record);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Forward one client; returns `None` when it did not start a TLS handshake
+fn handle_client(
+    mut client: TcpStream,
+    peer: SocketAddr,
+    config: &SniConfig,
+    resolve: &Resolver,
+) -> Option<ConnectionRecord> {
+    let start = Instant::now();
+    client.set_nonblocking(false).ok()?;
+    client.set_read_timeout(Some(Duration::from_millis(HELLO_TIMEOUT_MS))).ok()?;
+
+    let hello = read_client_hello(&mut client).ok()?;
+    let mut record = ConnectionRecord {
+        client: peer,
+        host: parse_sni(&hello).unwrap_or_default(),
+        port: config.target_port,
+        route: Route::Refused,
+        error: None,
+        connect_ms: 0,
+        bytes_up: 0,
+        bytes_down: 0,
+        duration_ms: 0,
+    };
+
+    // Without a managed name there is nowhere safe to send the stream: the
+    // system resolver may well point the name back at this forwarder
+    let Some(ips) = resolve(&record.host) else {
+        record.error = Some(if record.host.is_empty() {
+            "ClientHello without SNI".to_string()
+        } else {
+            "not a managed domain".to_string()
+        });
+        return Some(record);
+    };
+
+    let timeout = Duration::from_millis(config.connect_timeout_ms);
+    let connected = localproxy::connect_first(&ips, config.target_port, config.upstream.as_ref(), timeout);
+    record.connect_ms = start.elapsed().as_millis() as u64;
+    let (ip, server) = match connected {
+        Ok(connected) => connected,
+        Err(e) => {
+            record.error = Some(e.to_string());
+            record.duration_ms = start.elapsed().as_millis() as u64;
+            return Some(record);
+        }
+    };
+    record.route = Route::Optimized(ip);
+
+    let (bytes_up, bytes_down) = localproxy::relay(client, server, &hello);
+    record.bytes_up = bytes_up;
+    record.bytes_down = bytes_down;
+    record.duration_ms = start.elapsed().as_millis() as u64;
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// First flight of a real TLS client for `name`
+    fn client_hello(name: &str) -> Vec<u8> {
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let mut conn = ClientConnection::new(Arc::new(config), ServerName::try_from(name.to_string()).unwrap()).unwrap();
+        let mut hello = Vec::new();
+        conn.write_tls(&mut hello).unwrap();
+        hello
+    }
+
+    #[test]
+    fn test_parse_sni() {
+        assert_eq!(parse_sni(&client_hello("GitHub.com")).as_deref(), Some("github.com"));
+        assert_eq!(parse_sni(b"GET / HTTP/1.1\r\n\r\n"), None);
+
+        let hello = client_hello("github.com");
+        assert_eq!(parse_sni(&hello[..hello.len() / 2]), None);
+    }
+
+    #[test]
+    fn test_forwards_by_sni_with_failover() {
+        // Stand-in server echoing whatever it receives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = stream.try_clone().unwrap();
+                let mut writer = stream;
+                let _ = io::copy(&mut reader, &mut writer);
+            }
+        });
+
+        let config = SniConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            target_port: port,
+            connect_timeout_ms: 1000,
+            upstream: None,
+        };
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&records);
+        // Nothing listens on 127.0.0.2, so the next-ranked IP must be used
+        let forwarder = SniForwarder::bind(config)
+            .unwrap()
+            .with_resolver(|name: &str| {
+                (name == "github.com").then(|| vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()])
+            })
+            .with_logger(move |record| sink.lock().unwrap().push(record.clone()));
+        let addr = forwarder.local_addr().unwrap();
+        thread::spawn(move || forwarder.serve(&AtomicBool::new(false)));
+
+        let hello = client_hello("github.com");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(&hello).unwrap();
+        let mut echoed = vec![0u8; hello.len()];
+        stream.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed, hello);
+        drop(stream);
+
+        // Unmanaged names are closed without forwarding
+        let mut other = TcpStream::connect(addr).unwrap();
+        other.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        other.write_all(&client_hello("example.com")).unwrap();
+        assert_eq!(other.read(&mut [0u8; 16]).unwrap_or(0), 0);
+
+        for _ in 0..50 {
+            if records.lock().unwrap().len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let records = records.lock().unwrap();
+        let forwarded = records.iter().find(|r| r.host == "github.com").unwrap();
+        assert_eq!(forwarded.route, Route::Optimized("127.0.0.1".parse().unwrap()));
+        let refused = records.iter().find(|r| r.host == "example.com").unwrap();
+        assert_eq!(refused.route, Route::Refused);
+    }
+}
@@ -0,0 +1,307 @@
+//! SSH reachability probes and `~/.ssh/config` management
+//!
+//! Hosts entries do not help when port 22 itself is blocked. GitHub also
+//! serves SSH on `ssh.github.com:443`, so both endpoints are probed for an SSH
+//! banner, and a managed `Host github.com` block is written to the user's SSH
+//! config when the 443 route is faster or the only one working. The block is
+//! delimited by markers like the hosts block, so it can be removed cleanly.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::network::{self, ProbeConfig, ProbeOutcome};
+use crate::{paths, proxy};
+
+const SSH_MARKER_START: &str = "# === FREE_TO_GITHUB SSH START ===";
+const SSH_MARKER_END: &str = "# === FREE_TO_GITHUB SSH END ===";
+
+/// Standard SSH endpoint
+pub const SSH_DIRECT: (&str, u16) = ("github.com", 22);
+
+/// SSH over the HTTPS port
+pub const SSH_OVER_443: (&str, u16) = ("ssh.github.com", 443);
+
+/// Result of probing one SSH endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshProbe {
+    pub outcome: ProbeOutcome,
+    /// Time until the banner arrived, or until the probe failed
+    pub elapsed_ms: u64,
+    /// Server identification line, e.g. `SSH-2.0-babeld-...`
+    pub banner: Option<String>,
+}
+
+impl SshProbe {
+    pub fn works(&self) -> bool {
+        self.banner.is_some()
+    }
+
+    /// Time to banner when the endpoint works
+    pub fn latency_ms(&self) -> Option<u64> {
+        self.works().then_some(self.elapsed_ms)
+    }
+}
+
+/// Connect to `host:port` and wait for an SSH identification line
+///
+/// A connection that succeeds but never sends `SSH-` (a captive portal or a
+/// middlebox answering on the port) counts as a failure.
+pub fn probe_ssh(host: &str, port: u16, config: &ProbeConfig) -> SshProbe {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let start = Instant::now();
+    let failed = |outcome| SshProbe {
+        outcome,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        banner: None,
+    };
+
+    let stream = match proxy::connect(host, port, config.proxy.as_ref(), timeout) {
+        Ok(stream) => stream,
+        Err(e) => return failed(ProbeOutcome::classify(&e)),
+    };
+    if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+        return failed(ProbeOutcome::classify(&e));
+    }
+
+    // Servers may send other lines before the identification string (RFC 4253)
+    let mut reader = BufReader::new(stream);
+    for _ in 0..10 {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return failed(ProbeOutcome::Reset),
+            Ok(_) if line.starts_with("SSH-") => {
+                return SshProbe {
+                    outcome: ProbeOutcome::Success,
+                    elapsed_ms: start.elapsed().as_millis() as u64,
+                    banner: Some(line.trim_end().to_string()),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => return failed(ProbeOutcome::classify(&e)),
+        }
+    }
+    failed(ProbeOutcome::Other)
+}
+
+/// How SSH connections to github.com should be routed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshRoute {
+    /// Plain `github.com:22`, no config block needed
+    Direct,
+    /// `ssh.github.com:443`
+    Port443,
+    /// Neither endpoint answered, so there is nothing to choose from
+    Unknown,
+}
+
+/// Both SSH probes and the route chosen from them
+#[derive(Debug, Clone)]
+pub struct SshReport {
+    pub direct: SshProbe,
+    pub over_443: SshProbe,
+    pub route: SshRoute,
+}
+
+/// Pick the 443 route when it is faster or port 22 is blocked
+pub fn choose_route(direct: &SshProbe, over_443: &SshProbe) -> SshRoute {
+    match (direct.latency_ms(), over_443.latency_ms()) {
+        (None, None) => SshRoute::Unknown,
+        (None, Some(_)) => SshRoute::Port443,
+        (Some(d), Some(h)) if h < d => SshRoute::Port443,
+        _ => SshRoute::Direct,
+    }
+}
+
+/// Probe both SSH endpoints with the current probe config
+pub fn probe_routes() -> SshReport {
+    let config = network::probe_config();
+    let (direct, over_443) = std::thread::scope(|scope| {
+        let direct = scope.spawn(|| probe_ssh(SSH_DIRECT.0, SSH_DIRECT.1, &config));
+        let over_443 = probe_ssh(SSH_OVER_443.0, SSH_OVER_443.1, &config);
+        (direct.join().expect("SSH probe thread panicked"), over_443)
+    });
+
+    SshReport {
+        route: choose_route(&direct, &over_443),
+        direct,
+        over_443,
+    }
+}
+
+/// Path of the current user's SSH client config
+pub fn ssh_config_path() -> PathBuf {
+    paths::home_dir().join(".ssh").join("config")
+}
+
+/// Managed block for a route (`None` when no override is needed)
+pub fn build_ssh_block(route: SshRoute) -> Option<String> {
+    match route {
+        SshRoute::Direct | SshRoute::Unknown => None,
+        SshRoute::Port443 => Some(format!(
+            "{}\nHost github.com\n    HostName {}\n    Port {}\n    User git\n{}\n",
+            SSH_MARKER_START, SSH_OVER_443.0, SSH_OVER_443.1, SSH_MARKER_END
+        )),
+    }
+}
+
+/// Remove the managed block from SSH config content
+pub fn strip_ssh_block(content: &str) -> String {
+    match (content.find(SSH_MARKER_START), content.find(SSH_MARKER_END)) {
+        (Some(start), Some(end)) if end > start => {
+            let before = &content[..start];
+            let after = content[end + SSH_MARKER_END.len()..].trim_start_matches('\n');
+            format!("{}{}", before, after)
+        }
+        _ => content.to_string(),
+    }
+}
+
+/// Put a managed block at the top of SSH config content
+///
+/// ssh uses the first value it finds for each option, so the block goes
+/// first to take effect over any existing `Host github.com` entry.
+pub fn insert_ssh_block(content: &str, block: &str) -> String {
+    let rest = strip_ssh_block(content);
+    if rest.is_empty() {
+        block.to_string()
+    } else {
+        format!("{}\n{}", block, rest)
+    }
+}
+
+/// Check whether the managed block is present
+pub fn is_ssh_config_enabled(path: &Path) -> io::Result<bool> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.contains(SSH_MARKER_START)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write (or remove) the managed block for a route
+///
+/// An `Unknown` route leaves the config as it is: a block that worked before
+/// is kept rather than dropped because of a failed probe.
+pub fn apply_ssh_config(path: &Path, route: SshRoute) -> io::Result<()> {
+    if route == SshRoute::Unknown {
+        return Ok(());
+    }
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let new_content = match build_ssh_block(route) {
+        Some(block) => insert_ssh_block(&content, &block),
+        None => strip_ssh_block(&content),
+    };
+    if new_content == content {
+        return Ok(());
+    }
+
+    if let Some(dir) = path.parent() {
+        if !dir.exists() {
+            fs::create_dir_all(dir)?;
+            set_mode(dir, 0o700)?;
+        }
+    }
+    let is_new = !path.exists();
+    fs::write(path, new_content)?;
+    // ssh refuses configs writable by others
+    if is_new {
+        set_mode(path, 0o600)?;
+    }
+    Ok(())
+}
+
+/// Remove the managed block, restoring the user's own config
+pub fn remove_ssh_config(path: &Path) -> io::Result<()> {
+    apply_ssh_config(path, SshRoute::Direct)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn spawn_banner_server(greeting: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.write_all(greeting.as_bytes());
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_probe_ssh_banner() {
+        let config = ProbeConfig::default();
+        let port = spawn_banner_server("pre-banner notice\r\nSSH-2.0-babeld-test\r\n");
+        let probe = probe_ssh("127.0.0.1", port, &config);
+        assert_eq!(probe.banner.as_deref(), Some("SSH-2.0-babeld-test"));
+        assert!(probe.works());
+
+        // Something answers, but it is not SSH
+        let port = spawn_banner_server("HTTP/1.1 400 Bad Request\r\n\r\n");
+        let probe = probe_ssh("127.0.0.1", port, &config);
+        assert!(!probe.works());
+    }
+
+    #[test]
+    fn test_choose_route() {
+        let probe = |latency: Option<u64>| SshProbe {
+            outcome: if latency.is_some() { ProbeOutcome::Success } else { ProbeOutcome::Timeout },
+            elapsed_ms: latency.unwrap_or(3000),
+            banner: latency.map(|_| "SSH-2.0-x".to_string()),
+        };
+        assert_eq!(choose_route(&probe(None), &probe(Some(80))), SshRoute::Port443);
+        assert_eq!(choose_route(&probe(Some(100)), &probe(Some(80))), SshRoute::Port443);
+        assert_eq!(choose_route(&probe(Some(60)), &probe(Some(80))), SshRoute::Direct);
+        assert_eq!(choose_route(&probe(None), &probe(None)), SshRoute::Unknown);
+    }
+
+    #[test]
+    fn test_ssh_config_block_is_reversible() {
+        let path = paths::data_dir().join("ssh_test").join("config");
+        let _ = fs::remove_file(&path);
+        let original = "Host example\n    User me\n";
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, original).unwrap();
+
+        apply_ssh_config(&path, SshRoute::Port443).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(SSH_MARKER_START));
+        assert!(content.contains("HostName ssh.github.com\n    Port 443"));
+        assert!(content.ends_with(original));
+        assert!(is_ssh_config_enabled(&path).unwrap());
+
+        // Applying again does not duplicate the block
+        apply_ssh_config(&path, SshRoute::Port443).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        // Failed probes keep the working block
+        apply_ssh_config(&path, SshRoute::Unknown).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        remove_ssh_config(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        assert!(!is_ssh_config_enabled(&path).unwrap());
+    }
+}
@@ -0,0 +1,259 @@
+//! Throughput probe for download-heavy domains
+//!
+//! For `codeload.github.com` and the `*.githubusercontent.com` download hosts,
+//! sustained bandwidth matters far more than connect latency. This probe
+//! downloads a configurable object (or a byte range of it) through a candidate
+//! IP for a bounded time or size and reports Mbps and stall events. It costs
+//! real traffic on every speed test, so it only runs when enabled.
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::http::{self, Url};
+use crate::network::ProbeConfig;
+
+/// Upper bound for `max_bytes`, however it is configured (16 MiB)
+pub const MAX_BYTES_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Settings for throughput probes
+#[derive(Debug, Clone)]
+pub struct ThroughputConfig {
+    /// Download from bulk-transfer domains at all; they rank by latency otherwise
+    pub enabled: bool,
+    /// domain -> URL of the object downloaded to rank that domain's IPs
+    ///
+    /// The URL host only sets `Host`/SNI; the connection always goes to the
+    /// candidate IP, so any object served by the same edge works.
+    pub objects: HashMap<String, String>,
+    /// Stop after this many bytes (also sent as a `Range` request), at most
+    /// [`MAX_BYTES_LIMIT`]
+    pub max_bytes: u64,
+    /// Stop after this long
+    pub max_duration_ms: u64,
+    /// A gap without data longer than this counts as one stall event
+    pub stall_threshold_ms: u64,
+    /// Only the fastest reachable IPs (by connect latency) are downloaded from
+    pub max_candidates: usize,
+}
+
+impl Default for ThroughputConfig {
+    fn default() -> Self {
+        // objects.githubusercontent.com only serves signed, expiring release-asset
+        // links, so it has no default object and ranks by latency unless one is set
+        let objects = [
+            ("codeload.github.com", "https://codeload.github.com/git/git/tar.gz/refs/tags/v2.40.0"),
+            ("raw.githubusercontent.com", "https://raw.githubusercontent.com/torvalds/linux/master/MAINTAINERS"),
+        ]
+        .iter()
+        .map(|(domain, url)| (domain.to_string(), url.to_string()))
+        .collect();
+
+        Self {
+            enabled: false,
+            objects,
+            max_bytes: 1024 * 1024,
+            max_duration_ms: 5000,
+            stall_threshold_ms: 500,
+            max_candidates: 3,
+        }
+    }
+}
+
+/// Outcome of one throughput probe
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThroughputResult {
+    /// Body bytes received
+    pub bytes: u64,
+    /// Time from the first body byte to the end of the transfer
+    pub duration_ms: u64,
+    /// Sustained rate after the first byte, in megabits per second
+    pub mbps: f64,
+    /// Number of gaps longer than the stall threshold
+    pub stalls: u32,
+}
+
+impl ThroughputResult {
+    /// Ranking score: sustained rate, discounted for each stall
+    pub fn score(&self) -> f64 {
+        self.mbps / (1.0 + 0.25 * self.stalls as f64)
+    }
+}
+
+/// Download `url` through `ip` and measure sustained throughput
+pub fn measure_throughput(
+    ip: &str,
+    url: &str,
+    config: &ThroughputConfig,
+    probe: &ProbeConfig,
+) -> io::Result<ThroughputResult> {
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid IP: {}", ip)))?;
+    let url = Url::parse(url)?;
+
+    let timeout = Duration::from_millis(probe.timeout_ms);
+    let mut stream = http::connect(
+        &ip.to_string(),
+        url.port,
+        &url.host,
+        url.tls,
+        timeout,
+        probe.proxy.as_ref(),
+    )?;
+
+    let max_bytes = config.max_bytes.clamp(1, MAX_BYTES_LIMIT);
+    let range = format!("bytes=0-{}", max_bytes - 1);
+    http::send_get(&mut stream, &url.host, &url.path, &[("Range", &range)])?;
+
+    let mut reader = BufReader::with_capacity(64 * 1024, stream);
+    let (status, headers) = http::read_head(&mut reader)?;
+    if status != 200 && status != 206 {
+        return Err(io::Error::other(format!("unexpected HTTP status {}", status)));
+    }
+
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.parse::<u64>().ok());
+    let limit = content_length.map_or(max_bytes, |len| len.min(max_bytes));
+
+    // Short read timeouts let us notice stalls while the transfer is running
+    let stall_threshold = Duration::from_millis(config.stall_threshold_ms.max(1));
+    reader.get_ref().tcp().set_read_timeout(Some(stall_threshold))?;
+
+    let deadline = Instant::now() + Duration::from_millis(config.max_duration_ms);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut bytes = 0u64;
+    let mut stalls = 0u32;
+    let mut first_byte: Option<Instant> = None;
+    let mut last_data = Instant::now();
+    let mut in_stall = false;
+
+    while bytes < limit && Instant::now() < deadline {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let now = Instant::now();
+                if first_byte.is_some() && !in_stall && now - last_data > stall_threshold {
+                    stalls += 1;
+                }
+                first_byte.get_or_insert(now);
+                bytes += n as u64;
+                last_data = now;
+                in_stall = false;
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                // Count each continuous gap once, however many timeouts it spans
+                if !in_stall {
+                    stalls += 1;
+                    in_stall = true;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    if bytes == 0 {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "no data received"));
+    }
+
+    let elapsed = first_byte.map(|t| last_data - t).unwrap_or_default();
+    // A single-read transfer has no measurable duration; count it as 1 ms
+    let secs = elapsed.as_secs_f64().max(0.001);
+
+    Ok(ThroughputResult {
+        bytes,
+        duration_ms: elapsed.as_millis() as u64,
+        mbps: bytes as f64 * 8.0 / secs / 1_000_000.0,
+        stalls,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Local HTTP server sending `chunks` of 16 KiB with `pause` between them
+    fn spawn_blob_server(chunks: usize, pause: Duration) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).unwrap();
+            assert!(String::from_utf8_lossy(&request[..n]).contains("Range: bytes=0-"));
+
+            let _ = stream.write_all(
+                format!("HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\r\n", chunks * 16384).as_bytes(),
+            );
+            let chunk = vec![0x5au8; 16384];
+            for i in 0..chunks {
+                if i > 0 {
+                    thread::sleep(pause);
+                }
+                if stream.write_all(&chunk).is_err() {
+                    return;
+                }
+            }
+        });
+
+        port
+    }
+
+    fn local_config() -> ThroughputConfig {
+        ThroughputConfig {
+            enabled: true,
+            objects: HashMap::new(),
+            max_bytes: 1024 * 1024,
+            max_duration_ms: 3000,
+            stall_threshold_ms: 100,
+            max_candidates: 3,
+        }
+    }
+
+    #[test]
+    fn test_measure_throughput() {
+        let port = spawn_blob_server(8, Duration::ZERO);
+        let url = format!("http://localhost:{}/blob", port);
+        let result = measure_throughput("127.0.0.1", &url, &local_config(), &ProbeConfig::default()).unwrap();
+
+        assert_eq!(result.bytes, 8 * 16384);
+        assert_eq!(result.stalls, 0);
+        assert!(result.mbps > 0.0);
+    }
+
+    #[test]
+    fn test_throughput_counts_stalls() {
+        let port = spawn_blob_server(3, Duration::from_millis(300));
+        let url = format!("http://localhost:{}/blob", port);
+        let result = measure_throughput("127.0.0.1", &url, &local_config(), &ProbeConfig::default()).unwrap();
+
+        assert_eq!(result.bytes, 3 * 16384);
+        assert_eq!(result.stalls, 2);
+        assert!(result.score() < result.mbps);
+    }
+
+    #[test]
+    fn test_default_objects_match_their_domains() {
+        let config = ThroughputConfig::default();
+        assert!(!config.enabled);
+        assert!(config.max_bytes <= MAX_BYTES_LIMIT);
+        let bulk: Vec<String> = crate::network::get_domain_candidates()
+            .into_iter()
+            .filter(|entry| entry.ranking == crate::network::RankingSignal::Throughput)
+            .map(|entry| entry.domain)
+            .collect();
+        // Host and SNI come from the URL, so each object must live on its own domain
+        for (domain, url) in &config.objects {
+            assert!(bulk.contains(domain), "{} is not a bulk-transfer domain", domain);
+            assert_eq!(&Url::parse(url).unwrap().host, domain);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Before/after comparison of system DNS against optimized IPs
+//!
+//! For each domain, the IP the upstream DNS returns (queried directly, so an
+//! enabled hosts block does not mask it) and the optimized (or default) IP
+//! are probed the same way: repeated TCP connects plus a verified
+//! TLS handshake. The report is shown side by side with a per-domain verdict.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Serialize;
+
+use crate::{dns, hosts, network};
+
+/// Probes per path; loss is the share of failed attempts
+const ATTEMPTS: usize = 3;
+
+/// Timeout for each connect and handshake (milliseconds)
+const TIMEOUT_MS: u64 = 3000;
+
+/// Latency differences below this share of the system latency count as unchanged
+const LATENCY_MARGIN: f64 = 0.1;
+
+/// Measurements of one path (system or optimized) to a domain
+#[derive(Debug, Clone, Serialize)]
+pub struct PathReport {
+    pub ip: Option<String>,
+    /// Median latency of the successful attempts (connect + handshake)
+    pub latency_ms: Option<u64>,
+    /// Whether the certificate validated for the domain
+    pub tls_valid: Option<bool>,
+    /// Share of failed attempts, 1.0 when nothing could be probed
+    pub loss: f64,
+}
+
+impl PathReport {
+    fn unavailable() -> Self {
+        Self {
+            ip: None,
+            latency_ms: None,
+            tls_valid: None,
+            loss: 1.0,
+        }
+    }
+
+    fn works(&self) -> bool {
+        self.latency_ms.is_some() && self.tls_valid != Some(false)
+    }
+}
+
+/// Whether the optimized IP helps for a domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Improved,
+    Unchanged,
+    Worse,
+    BothFailed,
+    NoData,
+}
+
+/// Side-by-side result for one domain
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainComparison {
+    pub domain: String,
+    pub system: PathReport,
+    pub optimized: PathReport,
+    pub verdict: Verdict,
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+            };
+            let config = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .expect("ring provider supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+/// Connect to `ip:443` and complete a verified TLS handshake for `domain`
+fn handshake(domain: &str, ip: IpAddr) -> io::Result<()> {
+    let timeout = Duration::from_millis(TIMEOUT_MS);
+    let tcp = TcpStream::connect_timeout(&SocketAddr::new(ip, 443), timeout)?;
+    tcp.set_read_timeout(Some(timeout))?;
+    tcp.set_write_timeout(Some(timeout))?;
+
+    let server_name = ServerName::try_from(domain.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let conn = ClientConnection::new(tls_config(), server_name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut stream = StreamOwned::new(conn, tcp);
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    Ok(())
+}
+
+fn probe_path(domain: &str, ip: &str) -> PathReport {
+    let addr: IpAddr = match ip.parse() {
+        Ok(addr) => addr,
+        Err(_) => return PathReport::unavailable(),
+    };
+
+    let mut latencies = Vec::new();
+    let mut failures = 0;
+    let mut tls_valid = None;
+    for _ in 0..ATTEMPTS {
+        let start = Instant::now();
+        match handshake(domain, addr) {
+            Ok(()) => {
+                latencies.push(start.elapsed().as_millis() as u64);
+                tls_valid = Some(true);
+            }
+            Err(e) => {
+                if network::ProbeOutcome::classify(&e) == network::ProbeOutcome::TlsFailure && tls_valid.is_none() {
+                    tls_valid = Some(false);
+                }
+                failures += 1;
+            }
+        }
+    }
+
+    latencies.sort_unstable();
+    PathReport {
+        ip: Some(ip.to_string()),
+        latency_ms: latencies.get(latencies.len() / 2).copied(),
+        tls_valid,
+        loss: failures as f64 / ATTEMPTS as f64,
+    }
+}
+
+fn verdict(system: &PathReport, optimized: &PathReport) -> Verdict {
+    if optimized.ip.is_none() {
+        return Verdict::NoData;
+    }
+    if system.ip.is_some() && system.ip == optimized.ip {
+        return Verdict::Unchanged;
+    }
+
+    match (system.works(), optimized.works()) {
+        (false, false) => Verdict::BothFailed,
+        (false, true) => Verdict::Improved,
+        (true, false) => Verdict::Worse,
+        (true, true) => {
+            if (system.loss - optimized.loss).abs() > 0.3 {
+                return if optimized.loss < system.loss { Verdict::Improved } else { Verdict::Worse };
+            }
+            let (sys, opt) = (system.latency_ms.unwrap_or(0) as f64, optimized.latency_ms.unwrap_or(0) as f64);
+            if opt < sys * (1.0 - LATENCY_MARGIN) {
+                Verdict::Improved
+            } else if opt > sys * (1.0 + LATENCY_MARGIN) {
+                Verdict::Worse
+            } else {
+                Verdict::Unchanged
+            }
+        }
+    }
+}
+
+/// Compare every domain: system resolver vs. the optimized (or default) IP
+pub fn compare_all() -> Vec<DomainComparison> {
+    let upstreams = Arc::new(dns::system_upstreams());
+    let handles: Vec<_> = network::get_domain_candidates()
+        .into_iter()
+        .map(|entry| {
+            let upstreams = Arc::clone(&upstreams);
+            thread::spawn(move || {
+                let domain = entry.domain;
+                // IPv6 answers are probed too; the A record comes first
+                let system_ip = dns::resolve_upstream(&domain, &upstreams, Duration::from_millis(TIMEOUT_MS))
+                    .first()
+                    .map(|ip| ip.to_string());
+                let optimized_ip = hosts::get_optimized_ip(&domain).or_else(|| entry.candidate_ips.first().cloned());
+
+                let system = match &system_ip {
+                    Some(ip) => probe_path(&domain, ip),
+                    None => PathReport::unavailable(),
+                };
+                let optimized = match &optimized_ip {
+                    Some(ip) => probe_path(&domain, ip),
+                    None => PathReport::unavailable(),
+                };
+
+                DomainComparison {
+                    verdict: verdict(&system, &optimized),
+                    domain,
+                    system,
+                    optimized,
+                }
+            })
+        })
+        .collect();
+
+    handles.into_iter().filter_map(|h| h.join().ok()).collect()
+}
@@ -0,0 +1,149 @@
+//! Direct DNS lookups at the upstream resolvers
+//!
+//! The system resolver answers from the hosts file, so while acceleration is
+//! enabled it returns the applied IPs. Asking the upstreams over UDP shows
+//! what a name resolves to without this tool.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Used when the system has no usable nameserver
+const DEFAULT_UPSTREAMS: &[&str] = &["223.5.5.5:53", "119.29.29.29:53"];
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Build a standard recursive query for one name
+fn build_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + name.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // RD set, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+/// Offset after a possibly compressed name starting at `pos`
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            // A pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l if l > 63 => return None,
+            l => pos += 1 + l,
+        }
+    }
+}
+
+/// A and AAAA addresses in the answer section of a response
+fn parse_answer_ips(response: &[u8]) -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    if response.len() < 12 {
+        return ips;
+    }
+    let qdcount = u16::from_be_bytes([response[4], response[5]]);
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        let Some(end) = skip_name(response, pos) else { return ips };
+        pos = end + 4;
+    }
+    for _ in 0..ancount {
+        let Some(end) = skip_name(response, pos) else { return ips };
+        let Some(fixed) = response.get(end..end + 10) else { return ips };
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let Some(rdata) = response.get(end + 10..end + 10 + rdlength) else { return ips };
+        match (rtype, rdlength) {
+            (TYPE_A, 4) => ips.push(IpAddr::from(<[u8; 4]>::try_from(rdata).expect("length checked"))),
+            (TYPE_AAAA, 16) => ips.push(IpAddr::from(<[u8; 16]>::try_from(rdata).expect("length checked"))),
+            _ => {}
+        }
+        pos = end + 10 + rdlength;
+    }
+    ips
+}
+
+/// Send a query to the upstreams in order and return the first matching response
+fn query(packet: &[u8], upstreams: &[SocketAddr], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no upstream resolvers configured");
+    for upstream in upstreams {
+        let local: SocketAddr = if upstream.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let attempt = (|| {
+            let socket = UdpSocket::bind(local)?;
+            socket.set_read_timeout(Some(timeout))?;
+            socket.connect(upstream)?;
+            socket.send(packet)?;
+
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.recv(&mut buf)?;
+                // Ignore stray packets that do not answer our query ID
+                if n >= 12 && buf[0..2] == packet[0..2] {
+                    return Ok(buf[..n].to_vec());
+                }
+            }
+        })();
+        match attempt {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Nameservers of `/etc/resolv.conf` without loopback stubs, or the defaults
+///
+/// Local stubs such as systemd-resolved answer from the hosts file too.
+pub fn system_upstreams() -> Vec<SocketAddr> {
+    let system: Vec<SocketAddr> = if cfg!(unix) {
+        std::fs::read_to_string("/etc/resolv.conf")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .filter_map(|rest| rest.split_whitespace().next())
+            .filter_map(|addr| addr.parse::<IpAddr>().ok())
+            .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+            .map(|ip| SocketAddr::new(ip, 53))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if system.is_empty() {
+        DEFAULT_UPSTREAMS.iter().filter_map(|s| s.parse().ok()).collect()
+    } else {
+        system
+    }
+}
+
+/// Resolve a name's A and AAAA records directly at the upstreams
+pub fn resolve_upstream(name: &str, upstreams: &[SocketAddr], timeout: Duration) -> Vec<IpAddr> {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    [TYPE_A, TYPE_AAAA]
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, qtype)| {
+            let id = (seed as u16) ^ (std::process::id() as u16).wrapping_add(i as u16);
+            query(&build_query(id, name, qtype), upstreams, timeout).ok()
+        })
+        .flat_map(|response| parse_answer_ips(&response))
+        .collect()
+}
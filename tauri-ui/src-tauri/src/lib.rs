@@ -1,7 +1,10 @@
+mod compare;
+mod dns;
 mod hosts;
 mod network;
+mod rpc_client;
 
-use network::SpeedTestResult;
+use network::{FailureSummary, SpeedTestResult};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Mutex;
@@ -10,6 +13,7 @@ use tauri::State;
 /// Application state shared across commands
 pub struct AppState {
     speed_test_results: Mutex<Vec<SpeedTestResult>>,
+    failure_summary: Mutex<FailureSummary>,
     has_optimized: Mutex<bool>,
 }
 
@@ -29,6 +33,18 @@ pub struct OperationResult {
 /// Get current acceleration status
 #[tauri::command]
 fn get_status(state: State<AppState>) -> StatusResponse {
+    // A running daemon owns the state; this UI is just a client then
+    if rpc_client::is_running() {
+        if let Ok((enabled, has_permission, has_optimized)) = rpc_client::status() {
+            *state.has_optimized.lock().unwrap() = has_optimized;
+            return StatusResponse {
+                enabled,
+                has_permission,
+                has_optimized,
+            };
+        }
+    }
+
     let enabled = hosts::is_enabled().unwrap_or(false);
     let has_permission = hosts::check_permission().is_ok();
     let has_optimized = *state.has_optimized.lock().unwrap();
@@ -43,13 +59,21 @@ fn get_status(state: State<AppState>) -> StatusResponse {
 /// Check if we have admin permission
 #[tauri::command]
 fn check_permission() -> bool {
+    if rpc_client::is_running() {
+        return rpc_client::status().map(|(_, can_write, _)| can_write).unwrap_or(false);
+    }
     hosts::check_permission().is_ok()
 }
 
 /// Enable acceleration with default IPs
 #[tauri::command]
 fn enable_acceleration() -> OperationResult {
-    match hosts::enable() {
+    let result = if rpc_client::is_running() {
+        rpc_client::enable(false)
+    } else {
+        hosts::enable()
+    };
+    match result {
         Ok(_) => OperationResult {
             success: true,
             message: "Acceleration enabled successfully".to_string(),
@@ -66,7 +90,9 @@ fn enable_acceleration() -> OperationResult {
 fn enable_optimized(state: State<AppState>) -> OperationResult {
     let has_opt = *state.has_optimized.lock().unwrap();
     
-    let result = if has_opt {
+    let result = if rpc_client::is_running() {
+        rpc_client::enable(has_opt)
+    } else if has_opt {
         hosts::enable_optimized()
     } else {
         hosts::enable()
@@ -91,7 +117,12 @@ fn enable_optimized(state: State<AppState>) -> OperationResult {
 /// Disable acceleration
 #[tauri::command]
 fn disable_acceleration() -> OperationResult {
-    match hosts::disable() {
+    let result = if rpc_client::is_running() {
+        rpc_client::disable()
+    } else {
+        hosts::disable()
+    };
+    match result {
         Ok(_) => OperationResult {
             success: true,
             message: "Acceleration disabled successfully".to_string(),
@@ -106,17 +137,30 @@ fn disable_acceleration() -> OperationResult {
 /// Run speed test and return results
 #[tauri::command]
 fn run_speed_test(state: State<AppState>) -> Vec<SpeedTestResult> {
-    let raw_results = network::test_all_domains_parallel();
-    
-    // Convert to HashMap for hosts module
-    let results_map: HashMap<String, (String, u64)> = raw_results.clone();
-    hosts::set_optimized_ips(results_map);
+    // Let a running daemon test so it keeps the results for everyone; it
+    // stores them itself, and keeps its previous ones when the test failed
+    let (raw_results, failures) = if rpc_client::is_running() {
+        (rpc_client::run_speed_test().unwrap_or_default(), FailureSummary::default())
+    } else {
+        let (results, failures) = network::test_all_domains_parallel();
+        // Keep the previous results when every probe failed
+        if !results.is_empty() {
+            let results_map: HashMap<String, (String, u64)> = results.clone();
+            hosts::set_optimized_ips(results_map);
+        }
+        (results, failures)
+    };
+    if raw_results.is_empty() {
+        *state.failure_summary.lock().unwrap() = failures;
+        return Vec::new();
+    }
     
     // Convert to display format
     let display_results = network::results_to_display(&raw_results);
     
     // Update state
     *state.speed_test_results.lock().unwrap() = display_results.clone();
+    *state.failure_summary.lock().unwrap() = failures;
     *state.has_optimized.lock().unwrap() = true;
     
     display_results
@@ -128,6 +172,21 @@ fn get_speed_test_results(state: State<AppState>) -> Vec<SpeedTestResult> {
     state.speed_test_results.lock().unwrap().clone()
 }
 
+/// Compare system DNS against the optimized IPs for every domain
+#[tauri::command]
+async fn run_comparison() -> Vec<compare::DomainComparison> {
+    // Blocking probes run off the async runtime's worker threads
+    tauri::async_runtime::spawn_blocking(compare::compare_all)
+        .await
+        .unwrap_or_default()
+}
+
+/// Get why probes failed in the last speed test
+#[tauri::command]
+fn get_failure_summary(state: State<AppState>) -> FailureSummary {
+    state.failure_summary.lock().unwrap().clone()
+}
+
 /// Flush DNS cache (Windows only)
 #[tauri::command]
 fn flush_dns() -> OperationResult {
@@ -243,6 +302,7 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .manage(AppState {
             speed_test_results: Mutex::new(Vec::new()),
+            failure_summary: Mutex::new(FailureSummary::default()),
             has_optimized: Mutex::new(false),
         })
         .invoke_handler(tauri::generate_handler![
@@ -253,6 +313,8 @@ pub fn run() {
             disable_acceleration,
             run_speed_test,
             get_speed_test_results,
+            get_failure_summary,
+            run_comparison,
             flush_dns,
             open_hosts_folder,
             open_github,
@@ -1,5 +1,6 @@
 //! Network module for IP latency testing and smart IP selection
 
+use std::io;
 use std::net::{TcpStream, ToSocketAddrs};
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
@@ -13,13 +14,96 @@ const CONNECT_TIMEOUT_MS: u64 = 3000;
 /// Port to use for testing (HTTPS)
 const TEST_PORT: u16 = 443;
 
+/// Classified result of a single probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeOutcome {
+    Success,
+    /// No answer within the timeout (often a silently dropped or blocked IP)
+    Timeout,
+    /// The host answered with a reset to the connection attempt
+    Refused,
+    /// An established connection was reset or cut off
+    Reset,
+    /// No route to the network or host (often the local network is down)
+    Unreachable,
+    /// TLS handshake or certificate validation failed
+    TlsFailure,
+    /// Any other error
+    Other,
+}
+
+impl ProbeOutcome {
+    /// Classify an I/O error returned by a probe
+    pub fn classify(err: &io::Error) -> ProbeOutcome {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ProbeOutcome::Timeout,
+            io::ErrorKind::ConnectionRefused => ProbeOutcome::Refused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ProbeOutcome::Reset,
+            io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::AddrNotAvailable => ProbeOutcome::Unreachable,
+            io::ErrorKind::InvalidData => ProbeOutcome::TlsFailure,
+            _ => ProbeOutcome::Other,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == ProbeOutcome::Success
+    }
+}
+
 /// Result of a single IP latency test
 #[derive(Debug, Clone, Serialize)]
 pub struct LatencyResult {
     pub ip: String,
     pub domain: String,
+    /// Latency when the probe succeeded
     pub latency_ms: Option<u64>,
-    pub success: bool,
+    pub outcome: ProbeOutcome,
+    /// Latency on success, time to failure otherwise
+    pub elapsed_ms: u64,
+}
+
+/// Failure counts of a speed test, grouped by outcome
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FailureSummary {
+    /// Probes performed
+    pub probes: usize,
+    /// (outcome, count) of failed probes, most frequent first
+    pub failures: Vec<(ProbeOutcome, usize)>,
+    /// Every probe failed as unreachable: the local network looks down
+    pub local_network_down: bool,
+}
+
+impl FailureSummary {
+    pub fn from_outcomes(outcomes: impl IntoIterator<Item = ProbeOutcome>) -> FailureSummary {
+        let mut probes = 0;
+        let mut counts: HashMap<ProbeOutcome, usize> = HashMap::new();
+        for outcome in outcomes {
+            probes += 1;
+            if !outcome.is_success() {
+                *counts.entry(outcome).or_insert(0) += 1;
+            }
+        }
+
+        let mut failures: Vec<(ProbeOutcome, usize)> = counts.into_iter().collect();
+        failures.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let failed: usize = failures.iter().map(|(_, count)| count).sum();
+        let local_network_down = probes > 0
+            && failed == probes
+            && failures.iter().all(|(outcome, _)| *outcome == ProbeOutcome::Unreachable);
+
+        FailureSummary {
+            probes,
+            failures,
+            local_network_down,
+        }
+    }
 }
 
 /// Domain with multiple candidate IPs
@@ -190,40 +274,61 @@ pub fn get_domain_candidates() -> Vec<DomainEntry> {
 
 /// Test TCP connection latency to a single IP
 pub fn test_ip_latency(ip: &str) -> Option<u64> {
+    let (outcome, elapsed_ms) = probe_ip(ip);
+    outcome.is_success().then_some(elapsed_ms)
+}
+
+/// Probe a single IP: classified outcome and time to success or failure
+pub fn probe_ip(ip: &str) -> (ProbeOutcome, u64) {
     let addr = format!("{}:{}", ip, TEST_PORT);
     let socket_addr = match addr.to_socket_addrs() {
         Ok(mut addrs) => match addrs.next() {
             Some(addr) => addr,
-            None => return None,
+            None => return (ProbeOutcome::Other, 0),
         },
-        Err(_) => return None,
+        Err(_) => return (ProbeOutcome::Other, 0),
     };
 
     let timeout = Duration::from_millis(CONNECT_TIMEOUT_MS);
     let start = Instant::now();
 
-    match TcpStream::connect_timeout(&socket_addr, timeout) {
-        Ok(_stream) => {
-            let elapsed = start.elapsed().as_millis() as u64;
-            Some(elapsed)
-        }
-        Err(_) => None,
-    }
+    let outcome = match TcpStream::connect_timeout(&socket_addr, timeout) {
+        Ok(_stream) => ProbeOutcome::Success,
+        Err(e) => ProbeOutcome::classify(&e),
+    };
+    (outcome, start.elapsed().as_millis() as u64)
+}
+
+/// Probe every candidate IP of a domain, keeping failures
+pub fn probe_domain(entry: &DomainEntry) -> Vec<LatencyResult> {
+    entry
+        .candidate_ips
+        .iter()
+        .map(|ip| {
+            let (outcome, elapsed_ms) = probe_ip(ip);
+            LatencyResult {
+                ip: ip.clone(),
+                domain: entry.domain.clone(),
+                latency_ms: outcome.is_success().then_some(elapsed_ms),
+                outcome,
+                elapsed_ms,
+            }
+        })
+        .collect()
 }
 
 /// Test all candidate IPs for a domain and find the fastest one
 pub fn find_best_ip_for_domain(entry: &mut DomainEntry) -> Option<LatencyResult> {
-    let mut best_result: Option<LatencyResult> = None;
+    let probed = probe_domain(entry);
+    best_of(entry, probed)
+}
 
-    for ip in &entry.candidate_ips {
-        if let Some(latency) = test_ip_latency(ip) {
-            let result = LatencyResult {
-                ip: ip.clone(),
-                domain: entry.domain.clone(),
-                latency_ms: Some(latency),
-                success: true,
-            };
+/// Pick the fastest successful result and record it on the entry
+fn best_of(entry: &mut DomainEntry, probed: Vec<LatencyResult>) -> Option<LatencyResult> {
+    let mut best_result: Option<LatencyResult> = None;
 
+    for result in probed {
+        if let Some(latency) = result.latency_ms {
             match &best_result {
                 None => best_result = Some(result),
                 Some(current_best) => {
@@ -245,18 +350,23 @@ pub fn find_best_ip_for_domain(entry: &mut DomainEntry) -> Option<LatencyResult>
     best_result
 }
 
-/// Test all domains in parallel and find the best IP for each
-pub fn test_all_domains_parallel() -> HashMap<String, (String, u64)> {
+/// Test all domains in parallel and find the best IP for each,
+/// along with a summary of why probes failed
+pub fn test_all_domains_parallel() -> (HashMap<String, (String, u64)>, FailureSummary) {
     let domains = get_domain_candidates();
     let results: Arc<Mutex<HashMap<String, (String, u64)>>> = Arc::new(Mutex::new(HashMap::new()));
+    let outcomes: Arc<Mutex<Vec<ProbeOutcome>>> = Arc::new(Mutex::new(Vec::new()));
 
     let mut handles = vec![];
 
     for mut entry in domains {
         let results = Arc::clone(&results);
+        let outcomes = Arc::clone(&outcomes);
 
         let handle = thread::spawn(move || {
-            if let Some(best) = find_best_ip_for_domain(&mut entry) {
+            let probed = probe_domain(&entry);
+            outcomes.lock().unwrap().extend(probed.iter().map(|r| r.outcome));
+            if let Some(best) = best_of(&mut entry, probed) {
                 if let Some(latency) = best.latency_ms {
                     let mut res = results.lock().unwrap();
                     res.insert(best.domain.clone(), (best.ip, latency));
@@ -272,7 +382,8 @@ pub fn test_all_domains_parallel() -> HashMap<String, (String, u64)> {
     }
 
     let final_results = results.lock().unwrap().clone();
-    final_results
+    let summary = FailureSummary::from_outcomes(outcomes.lock().unwrap().iter().copied());
+    (final_results, summary)
 }
 
 /// Get latency quality rating
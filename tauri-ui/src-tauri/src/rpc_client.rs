@@ -0,0 +1,143 @@
+//! Client for the JSON-RPC control socket of a running Free to GitHub daemon
+//!
+//! When the daemon is running it owns the speed test results and the hosts
+//! state, and the commands in `lib.rs` forward to it instead of acting
+//! locally. See `rpcApi.md` in the repository root for the protocol.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Environment variable overriding the socket path (same as the daemon)
+const SOCKET_ENV: &str = "FREE_TO_GITHUB_RPC_SOCKET";
+
+/// Socket of the system service, inside its state directory
+const SYSTEM_SOCKET: &str = "/var/lib/free-to-github/rpc.sock";
+
+/// Sockets tried in order: this user's daemon
+/// (`~/.local/share/free_to_github/rpc.sock`), then the system service
+fn socket_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return vec![PathBuf::from(path)];
+    }
+    let data_dir = std::env::var_os("FREE_TO_GITHUB_DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(".local/share/free_to_github")
+        });
+    vec![data_dir.join("rpc.sock"), PathBuf::from(SYSTEM_SOCKET)]
+}
+
+#[cfg(unix)]
+mod transport {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    /// Connect to the first daemon that answers
+    fn connect() -> io::Result<UnixStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no daemon socket");
+        for socket in socket_paths() {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn is_running() -> bool {
+        connect().is_ok()
+    }
+
+    /// Call `method` and wait for its response; with `until` set, keep reading
+    /// notifications until one with that name arrives and return its params
+    pub fn call(method: &str, params: Value, until: Option<&str>) -> io::Result<Value> {
+        let mut stream = connect()?;
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        stream.write_all(format!("{}\n", request).as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut result = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the connection"));
+            }
+            let message: Value = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            if message.get("id").is_some() {
+                if let Some(error) = message.get("error") {
+                    let text = error["message"].as_str().unwrap_or("unknown error");
+                    return Err(io::Error::new(io::ErrorKind::Other, text.to_string()));
+                }
+                result = Some(message["result"].clone());
+            } else if until.is_some() && message["method"].as_str() == until {
+                return Ok(message["params"].clone());
+            }
+
+            if let (Some(result), None) = (&result, until) {
+                return Ok(result.clone());
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod transport {
+    use super::*;
+
+    pub fn is_running() -> bool {
+        false
+    }
+
+    pub fn call(_method: &str, _params: Value, _until: Option<&str>) -> io::Result<Value> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the daemon socket needs Unix domain sockets"))
+    }
+}
+
+pub use transport::is_running;
+
+/// Daemon status: (enabled, can write hosts, has optimized IPs)
+pub fn status() -> io::Result<(bool, bool, bool)> {
+    let status = transport::call("status", json!({}), None)?;
+    Ok((
+        status["enabled"].as_bool().unwrap_or(false),
+        status["can_write_hosts"].as_bool().unwrap_or(false),
+        status["has_optimized_ips"].as_bool().unwrap_or(false),
+    ))
+}
+
+pub fn enable(optimized: bool) -> io::Result<()> {
+    let mode = if optimized { "optimized" } else { "default" };
+    transport::call("enable", json!({ "mode": mode }), None).map(|_| ())
+}
+
+pub fn disable() -> io::Result<()> {
+    transport::call("disable", json!({}), None).map(|_| ())
+}
+
+/// Run a speed test on the daemon; best IP and latency per domain
+///
+/// Fails when every probe failed; the daemon keeps its previous results then.
+pub fn run_speed_test() -> io::Result<HashMap<String, (String, u64)>> {
+    let finished = transport::call("run_speed_test", json!({}), Some("speed_test.finished"))?;
+    if let Some(error) = finished["error"].as_str() {
+        return Err(io::Error::new(io::ErrorKind::Other, error.to_string()));
+    }
+    let mut best = HashMap::new();
+    for entry in finished["domains"].as_array().into_iter().flatten() {
+        let first = &entry["ips"][0];
+        if let (Some(domain), Some(ip), Some(latency)) =
+            (entry["domain"].as_str(), first["ip"].as_str(), first["latency_ms"].as_u64())
+        {
+            best.insert(domain.to_string(), (ip.to_string(), latency));
+        }
+    }
+    Ok(best)
+}